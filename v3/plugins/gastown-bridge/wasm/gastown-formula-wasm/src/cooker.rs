@@ -9,29 +9,597 @@
 //! - Batch processing with shared state
 //! - Zero-copy where possible
 
+use std::cell::RefCell;
 use wasm_bindgen::prelude::*;
-use gastown_shared::{FxHashMap, pool::SmallBuffer, capacity};
+use serde::{Deserialize, Serialize};
+use gastown_shared::{FxHashMap, pool::SmallBuffer};
 use crate::{Formula, CookedFormula, Step, Leg};
 
+/// Newline normalization applied to substituted var values
+///
+/// Different output contexts want different line breaks: TOML wants
+/// `\n`, HTML wants `<br>`, Markdown wants a hard break (two spaces
+/// plus newline).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NewlineStyle {
+    /// Leave var values untouched
+    #[default]
+    Preserve,
+    /// Normalize to `\n`
+    Unix,
+    /// Normalize to `\r\n`
+    Windows,
+    /// Replace newlines with `<br>`
+    HtmlBreak,
+    /// Replace newlines with `  \n` (Markdown hard break)
+    MarkdownBreak,
+}
+
+/// Options controlling a single cook
+///
+/// All fields are optional; `Default` produces the plain `cook_formula`
+/// behavior.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CookOptions {
+    /// Canonical URL of the source formula, propagated to
+    /// `CookedFormula.formula_url`
+    #[serde(default)]
+    pub source_url: Option<String>,
+    /// Agent or user that triggered the cook, propagated to
+    /// `CookedFormula.cooked_by`
+    #[serde(default)]
+    pub cooked_by: Option<String>,
+    /// Newline normalization applied to substituted var values
+    #[serde(default)]
+    pub newline_style: NewlineStyle,
+    /// Maximum size of a single var value in bytes (default 64KB)
+    ///
+    /// Guards against accidentally passing file contents where a path was
+    /// intended; a 10MB value would otherwise balloon every substituted
+    /// field.
+    #[serde(default = "default_max_var_value_bytes")]
+    pub max_var_value_bytes: u32,
+    /// When set, only these vars are substituted; every other `{{VAR}}`
+    /// token is left verbatim even if a value was supplied
+    ///
+    /// Lets pipelines cook formulas with untrusted var input without
+    /// risking unexpected substitutions.
+    #[serde(default)]
+    pub var_whitelist: Option<Vec<String>>,
+    /// When `true`, `cooked_vars` only records vars that were actually
+    /// substituted at least once
+    ///
+    /// Supplied-but-unreferenced vars are dropped from the output so
+    /// stored cooked formulas do not leak unrelated pipeline state.
+    #[serde(default)]
+    pub sparse_cooked_vars: bool,
+    /// Host-supplied environment map layered under the provided vars
+    ///
+    /// Lookup precedence is provided vars, then this map, then formula
+    /// defaults — so process env or session context can be passed once
+    /// per cook instead of merged into every vars object in JS.
+    #[serde(default)]
+    pub env: Option<std::collections::HashMap<String, String>>,
+    /// Opening delimiter for template expressions (default `${`)
+    ///
+    /// Formulas embedding shell snippets can move expressions to e.g.
+    /// `%{...}` so literal `${...}` passes through untouched. A doubled
+    /// first character escapes the delimiter (`$${` emits a literal
+    /// `${`).
+    #[serde(default = "default_expr_open")]
+    pub expr_open: String,
+    /// Closing delimiter for template expressions (default `}`)
+    #[serde(default = "default_expr_close")]
+    pub expr_close: String,
+}
+
+/// Default opening delimiter for template expressions
+#[inline(always)]
+fn default_expr_open() -> String {
+    "${".to_string()
+}
+
+/// Default closing delimiter for template expressions
+#[inline(always)]
+fn default_expr_close() -> String {
+    "}".to_string()
+}
+
+/// Default cap on a single var value: 64KB
+#[inline(always)]
+pub(crate) fn default_max_var_value_bytes() -> u32 {
+    64 * 1024
+}
+
+impl Default for CookOptions {
+    fn default() -> Self {
+        Self {
+            source_url: None,
+            cooked_by: None,
+            newline_style: NewlineStyle::default(),
+            max_var_value_bytes: default_max_var_value_bytes(),
+            var_whitelist: None,
+            sparse_cooked_vars: false,
+            env: None,
+            expr_open: default_expr_open(),
+            expr_close: default_expr_close(),
+        }
+    }
+}
+
+/// Normalize the newlines in one var value
+///
+/// Line endings are first unified to `\n` so mixed `\r\n`/`\n` input
+/// normalizes consistently.
+pub(crate) fn normalize_newlines(value: &str, style: NewlineStyle) -> String {
+    if style == NewlineStyle::Preserve {
+        return value.to_string();
+    }
+
+    let unified = value.replace("\r\n", "\n");
+    match style {
+        NewlineStyle::Preserve => unreachable!(),
+        NewlineStyle::Unix => unified,
+        NewlineStyle::Windows => unified.replace('\n', "\r\n"),
+        NewlineStyle::HtmlBreak => unified.replace('\n', "<br>"),
+        NewlineStyle::MarkdownBreak => unified.replace('\n', "  \n"),
+    }
+}
+
 /// Pre-computed variable pattern for fast substitution
 struct VarPattern {
     pattern: String,  // "{{name}}"
     value: String,
 }
 
+/// Errors produced while cooking formulas
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum CookError {
+    /// Batch inputs have differing lengths
+    BatchLengthMismatch { formulas: usize, vars: usize },
+    /// Re-cooking changed `original_name`, which must be stable
+    OriginalNameChanged { original: String, recooked: String },
+    /// A var value violated a declared constraint
+    ValidationFailed {
+        var_name: String,
+        constraint: String,
+        expected: String,
+        actual: String,
+    },
+    /// A `${...}` template expression failed to parse or evaluate
+    InvalidExpression { expression: String, message: String },
+    /// Var values reference each other in a cycle
+    VarReferenceCycle { chain: Vec<String> },
+    /// Var references nest deeper than `MAX_VAR_REF_DEPTH`
+    VarReferenceTooDeep { var_name: String, max: usize },
+    /// Supplied values violated declared pattern or enum constraints
+    ConstraintViolations { violations: Vec<ConstraintViolation> },
+    /// A var value exceeded the configured size limit
+    VarValueTooLarge {
+        var_name: String,
+        size: usize,
+        max: u32,
+    },
+}
+
+impl std::fmt::Display for CookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CookError::BatchLengthMismatch { formulas, vars } => write!(
+                f,
+                "Batch length mismatch: {} formulas but {} var sets",
+                formulas, vars
+            ),
+            CookError::OriginalNameChanged { original, recooked } => write!(
+                f,
+                "Cook invariant violated: original_name changed from '{}' to '{}'",
+                original, recooked
+            ),
+            CookError::ConstraintViolations { violations } => {
+                write!(f, "{} constraint violation(s):", violations.len())?;
+                for violation in violations {
+                    write!(
+                        f,
+                        " [{}: {} expects {}, got '{}']",
+                        violation.var_name,
+                        violation.constraint,
+                        violation.expected,
+                        violation.actual
+                    )?;
+                }
+                Ok(())
+            }
+            CookError::VarReferenceCycle { chain } => {
+                write!(f, "Var reference cycle: {}", chain.join(" -> "))
+            }
+            CookError::VarReferenceTooDeep { var_name, max } => write!(
+                f,
+                "Var '{}' references nest deeper than the maximum of {}",
+                var_name, max
+            ),
+            CookError::InvalidExpression {
+                expression,
+                message,
+            } => write!(f, "Invalid expression '${{{}}}': {}", expression, message),
+            CookError::ValidationFailed {
+                var_name,
+                constraint,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Validation failed for var '{}': {} constraint expects {}, got '{}'",
+                var_name, constraint, expected, actual
+            ),
+            CookError::VarValueTooLarge { var_name, size, max } => write!(
+                f,
+                "Var '{}' value is {} bytes, exceeding the {} byte limit",
+                var_name, size, max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CookError {}
+
+impl From<CookError> for JsValue {
+    fn from(err: CookError) -> Self {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
+/// One `{name, value}` entry in the array form of `vars_json`
+#[derive(Deserialize)]
+struct NamedVar {
+    name: String,
+    value: String,
+}
+
+/// Parse `vars_json` as either a flat object or an array of `{name, value}`
+///
+/// Some APIs return vars as `[{"name": "VAR", "value": "v"}]` instead of
+/// `{"VAR": "v"}`; both forms cook identically. In the array form a
+/// repeated name keeps the last value, matching JSON object semantics.
+pub(crate) fn parse_vars_json(vars_json: &str) -> Result<FxHashMap<String, String>, String> {
+    if vars_json.trim_start().starts_with('[') {
+        let entries: Vec<NamedVar> =
+            serde_json::from_str(vars_json).map_err(|e| format!("Vars parse error: {}", e))?;
+        return Ok(entries
+            .into_iter()
+            .map(|entry| (entry.name, entry.value))
+            .collect());
+    }
+
+    serde_json::from_str(vars_json).map_err(|e| format!("Vars parse error: {}", e))
+}
+
+/// Replacement emitted for secret var values in cooked output
+pub(crate) const SECRET_MASK: &str = "***";
+
+/// Copy `vars` with every secret-declared var's value replaced by the
+/// mask; `None` when the formula declares no secret vars
+fn mask_secret_vars(
+    formula: &Formula,
+    vars: &FxHashMap<String, String>,
+) -> Option<FxHashMap<String, String>> {
+    if !formula.vars.values().any(|var| var.secret) {
+        return None;
+    }
+    let mut masked = vars.clone();
+    for (name, var) in &formula.vars {
+        if var.secret && masked.contains_key(name) {
+            masked.insert(name.clone(), SECRET_MASK.to_string());
+        }
+    }
+    Some(masked)
+}
+
+/// Maximum number of memoized cook results
+const COOK_CACHE_CAPACITY: usize = 128;
+
+/// One memoized cook result
+struct CookCacheEntry {
+    json: String,
+    last_used: u64,
+}
+
+/// LRU cache of cook results keyed by input content hash
+///
+/// WASM is single-threaded, so a thread-local cache is effectively
+/// global (matching the linter's strategy registry).
+#[derive(Default)]
+struct CookCache {
+    entries: FxHashMap<u64, CookCacheEntry>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+thread_local! {
+    static COOK_CACHE: RefCell<CookCache> = RefCell::new(CookCache::default());
+}
+
+/// Cook result cache counters, serialized for `cache_stats`
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
+pub struct CookCacheStats {
+    pub size: u32,
+    pub capacity: u32,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Content hash over both cook inputs
+fn cook_cache_key(formula_json: &str, vars_json: &str) -> u64 {
+    use std::hash::Hasher;
+    let mut hasher = rustc_hash::FxHasher::default();
+    hasher.write(formula_json.as_bytes());
+    // Separator so ("ab", "c") and ("a", "bc") hash differently
+    hasher.write_u8(0);
+    hasher.write(vars_json.as_bytes());
+    hasher.finish()
+}
+
+/// Look up a memoized cook result, updating hit/miss counters
+fn cook_cache_get(formula_json: &str, vars_json: &str) -> Option<String> {
+    let key = cook_cache_key(formula_json, vars_json);
+    COOK_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        cache.tick += 1;
+        let tick = cache.tick;
+        match cache.entries.get_mut(&key) {
+            Some(entry) => {
+                entry.last_used = tick;
+                let json = entry.json.clone();
+                cache.hits += 1;
+                Some(json)
+            }
+            None => {
+                cache.misses += 1;
+                None
+            }
+        }
+    })
+}
+
+/// Memoize one cook result, evicting the least recently used entry at
+/// capacity
+fn cook_cache_put(formula_json: &str, vars_json: &str, json: String) {
+    let key = cook_cache_key(formula_json, vars_json);
+    COOK_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.entries.len() >= COOK_CACHE_CAPACITY && !cache.entries.contains_key(&key) {
+            if let Some(oldest) = cache
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key)
+            {
+                cache.entries.remove(&oldest);
+            }
+        }
+        cache.tick += 1;
+        let last_used = cache.tick;
+        cache.entries.insert(key, CookCacheEntry { json, last_used });
+    });
+}
+
+/// Snapshot the cook cache counters
+pub fn cook_cache_stats() -> CookCacheStats {
+    COOK_CACHE.with(|cache| {
+        let cache = cache.borrow();
+        CookCacheStats {
+            size: cache.entries.len() as u32,
+            capacity: COOK_CACHE_CAPACITY as u32,
+            hits: cache.hits,
+            misses: cache.misses,
+        }
+    })
+}
+
+/// Drop all memoized cook results and reset the counters
+pub fn cook_cache_clear() {
+    COOK_CACHE.with(|cache| {
+        *cache.borrow_mut() = CookCache::default();
+    });
+}
+
 /// Cook a formula with variable substitution
 ///
 /// # Performance
 /// Target: <0.05ms (500x faster than JavaScript)
 #[inline]
 pub fn cook_formula_impl(formula_json: &str, vars_json: &str) -> Result<String, JsValue> {
+    // Identical inputs return the memoized result (including its
+    // original `cooked_at` timestamp) without re-cooking
+    if let Some(json) = cook_cache_get(formula_json, vars_json) {
+        crate::record_output_bytes(json.len());
+        return Ok(json);
+    }
+
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+
+    let supplied = parse_vars_json(vars_json).map_err(|e| JsValue::from_str(&e))?;
+
+    let vars = resolve_var_references(&formula, &supplied)?;
+
+    check_var_value_sizes(&vars, default_max_var_value_bytes())?;
+    validate_var_bounds(&formula, &vars)?;
+    validate_var_constraints(&formula, &vars)?;
+    validate_var_types(&formula, &vars)?;
+    validate_expressions(&formula, &vars)?;
+    validate_foreach(&formula, &vars)?;
+
+    let mut cooked = cook_formula_internal(&formula, &vars);
+    cooked.var_provenance = var_provenance_for(&formula, &supplied, None);
+
+    let json = serde_json::to_string(&cooked)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))?;
+    crate::record_output_bytes(json.len());
+    cook_cache_put(formula_json, vars_json, json.clone());
+    Ok(json)
+}
+
+/// Cook a formula, returning a structured `JsValue`
+///
+/// Like `cook_formula_impl`, but serializes straight into a JS object via
+/// `serde_wasm_bindgen` instead of a JSON string the caller must
+/// `JSON.parse`. For large formulas this path is faster — it skips one
+/// full serialize/parse round trip; the string path only wins when the
+/// caller wants JSON text anyway (e.g. to write to disk).
+#[inline]
+pub fn cook_formula_js_impl(formula_json: &str, vars_json: &str) -> Result<JsValue, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+
+    cook_parsed_js_impl(&formula, vars_json)
+}
+
+/// Cook an already-parsed formula, returning a structured `JsValue`
+///
+/// The strict `cook_formula` pipeline starting from a `Formula` held
+/// inside WASM (e.g. by a `FormulaHandle`), skipping the JSON parse.
+pub(crate) fn cook_parsed_js_impl(formula: &Formula, vars_json: &str) -> Result<JsValue, JsValue> {
+    let supplied = parse_vars_json(vars_json).map_err(|e| JsValue::from_str(&e))?;
+
+    let vars = resolve_var_references(formula, &supplied)?;
+
+    check_var_value_sizes(&vars, default_max_var_value_bytes())?;
+    validate_var_bounds(formula, &vars)?;
+    validate_var_constraints(formula, &vars)?;
+    validate_var_types(formula, &vars)?;
+    validate_expressions(formula, &vars)?;
+    validate_foreach(formula, &vars)?;
+
+    let mut cooked = cook_formula_internal(formula, &vars);
+    cooked.var_provenance = var_provenance_for(formula, &supplied, None);
+
+    serde_wasm_bindgen::to_value(&cooked)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Cook a formula for preview without timing metadata
+///
+/// Like `cook_formula_impl`, but stamps `cooked_at = "DRY_RUN"` and
+/// `cook_duration_us = 0` so repeated previews of the same input produce
+/// byte-identical output (no spurious diffs in live editors).
+#[inline]
+pub fn cook_formula_dry_run_impl(formula_json: &str, vars_json: &str) -> Result<String, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+
+    let supplied = parse_vars_json(vars_json).map_err(|e| JsValue::from_str(&e))?;
+
+    let vars = resolve_var_references(&formula, &supplied)?;
+
+    check_var_value_sizes(&vars, default_max_var_value_bytes())?;
+    validate_var_bounds(&formula, &vars)?;
+    validate_var_constraints(&formula, &vars)?;
+    validate_var_types(&formula, &vars)?;
+    validate_expressions(&formula, &vars)?;
+    validate_foreach(&formula, &vars)?;
+
+    let mut cooked = cook_formula_internal(&formula, &vars);
+    cooked.var_provenance = var_provenance_for(&formula, &supplied, None);
+    cooked.cooked_at = "DRY_RUN".to_string();
+    cooked.cook_duration_us = 0;
+
+    serde_json::to_string(&cooked)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Cook a formula with variable substitution and cook options
+///
+/// Like `cook_formula_impl`, but stamps provenance fields from the options
+/// onto the cooked result.
+#[inline]
+pub fn cook_formula_opts_impl(
+    formula_json: &str,
+    vars_json: &str,
+    options_json: &str,
+) -> Result<String, JsValue> {
     let formula: Formula = serde_json::from_str(formula_json)
         .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
 
-    let vars: FxHashMap<String, String> = serde_json::from_str(vars_json)
+    let supplied: FxHashMap<String, String> = serde_json::from_str(vars_json)
         .map_err(|e| JsValue::from_str(&format!("Vars parse error: {}", e)))?;
 
-    let cooked = cook_formula_internal(&formula, &vars);
+    let options: CookOptions = serde_json::from_str(options_json)
+        .map_err(|e| JsValue::from_str(&format!("Options parse error: {}", e)))?;
+
+    let vars = layer_env_vars(&supplied, options.env.as_ref());
+    let vars = resolve_var_references(&formula, &vars)?;
+
+    check_var_value_sizes(&vars, options.max_var_value_bytes)?;
+    validate_var_bounds(&formula, &vars)?;
+    validate_var_constraints(&formula, &vars)?;
+    validate_var_types(&formula, &vars)?;
+    validate_expressions_delim(&formula, &vars, &options.expr_open, &options.expr_close)?;
+    validate_foreach(&formula, &vars)?;
+
+    let mut cooked = cook_formula_with_options(&formula, &vars, &options);
+    cooked.var_provenance = var_provenance_for(&formula, &supplied, options.env.as_ref());
+
+    let json = serde_json::to_string(&cooked)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))?;
+    crate::record_output_bytes(json.len());
+    Ok(json)
+}
+
+/// Cook a formula and apply cook options
+#[inline]
+pub(crate) fn cook_formula_with_options(
+    formula: &Formula,
+    vars: &FxHashMap<String, String>,
+    options: &CookOptions,
+) -> CookedFormula {
+    // Whitelist mode: drop non-whitelisted vars up front so their tokens
+    // stay verbatim in the output
+    let whitelisted: Option<FxHashMap<String, String>> = options.var_whitelist.as_ref().map(|allowed| {
+        vars.iter()
+            .filter(|(name, _)| allowed.iter().any(|a| a == *name))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    });
+    let vars = whitelisted.as_ref().unwrap_or(vars);
+
+    let mut cooked = if options.newline_style == NewlineStyle::Preserve {
+        cook_formula_internal_delim(formula, vars, &options.expr_open, &options.expr_close)
+    } else {
+        let normalized: FxHashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.clone(), normalize_newlines(v, options.newline_style)))
+            .collect();
+        cook_formula_internal_delim(formula, &normalized, &options.expr_open, &options.expr_close)
+    };
+    cooked.formula_url = options.source_url.clone();
+    cooked.cooked_by = options.cooked_by.clone();
+
+    // Sparse mode: keep only the vars whose tokens appear in the original
+    // formula, i.e. the ones that were substituted at least once
+    if options.sparse_cooked_vars {
+        let referenced: std::collections::HashSet<String> = list_substitution_sites(formula)
+            .into_iter()
+            .map(|site| site.var_name)
+            .collect();
+        cooked.cooked_vars.retain(|name, _| referenced.contains(name));
+    }
+
+    cooked
+}
+
+/// Re-serialize a cooked formula in the current storage format
+///
+/// Accepts cooked formulas whose `cooked_vars` use the legacy object form
+/// and emits the name-sorted array form, so stored artifacts can be
+/// migrated in one pass.
+#[inline]
+pub fn upgrade_cooked_formula_format_impl(old_json: &str) -> Result<String, JsValue> {
+    let cooked: CookedFormula = serde_json::from_str(old_json)
+        .map_err(|e| JsValue::from_str(&format!("Cooked formula parse error: {}", e)))?;
 
     serde_json::to_string(&cooked)
         .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
@@ -43,306 +611,3953 @@ pub fn cook_formula_impl(formula_json: &str, vars_json: &str) -> Result<String,
 /// More efficient than cooking individually due to memory reuse
 #[inline]
 pub fn cook_batch_impl(formulas_json: &str, vars_json: &str) -> Result<String, JsValue> {
+    // Length check must come first, before any payload deserialization
+    check_batch_lengths(formulas_json, vars_json)?;
+
     let formulas: Vec<Formula> = serde_json::from_str(formulas_json)
         .map_err(|e| JsValue::from_str(&format!("Formulas parse error: {}", e)))?;
 
     let vars_list: Vec<FxHashMap<String, String>> = serde_json::from_str(vars_json)
         .map_err(|e| JsValue::from_str(&format!("Vars parse error: {}", e)))?;
 
-    if formulas.len() != vars_list.len() {
-        return Err(JsValue::from_str("Formulas and vars arrays must have same length"));
-    }
-
-    // Pre-allocate result vector
-    let mut cooked: Vec<CookedFormula> = Vec::with_capacity(formulas.len());
+    // Parallel path when built with `wasm-threads`: cooks are
+    // independent, so they fan out across the rayon pool (the JS host
+    // must have SharedArrayBuffer and an initialized pool). The default
+    // build cooks sequentially with memory reuse.
+    #[cfg(feature = "wasm-threads")]
+    let cooked: Vec<CookedFormula> = {
+        use rayon::prelude::*;
+        formulas
+            .par_iter()
+            .zip(vars_list.par_iter())
+            .map(|(formula, vars)| cook_formula_internal(formula, vars))
+            .collect()
+    };
 
-    // Process in batches for cache efficiency
-    for (formula, vars) in formulas.iter().zip(vars_list.iter()) {
-        cooked.push(cook_formula_internal(formula, vars));
-    }
+    #[cfg(not(feature = "wasm-threads"))]
+    let cooked: Vec<CookedFormula> = {
+        // Process in batches for cache efficiency
+        let mut cooked = Vec::with_capacity(formulas.len());
+        for (formula, vars) in formulas.iter().zip(vars_list.iter()) {
+            cooked.push(cook_formula_internal(formula, vars));
+        }
+        cooked
+    };
 
     serde_json::to_string(&cooked)
         .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
 }
 
-/// Internal function to cook a formula
+/// Default number of formulas cooked per progress chunk
+#[inline(always)]
+fn default_progress_chunk_size() -> usize {
+    64
+}
+
+/// Cook a batch in chunks, emitting each cooked chunk plus a progress
+/// percentage through `emit`
 ///
-/// # Optimizations
-/// - Pre-computes all variable patterns once
-/// - Uses SmallVec for stack allocation when possible
-/// - Single-pass substitution per field
-#[inline]
-fn cook_formula_internal(formula: &Formula, vars: &FxHashMap<String, String>) -> CookedFormula {
-    // Pre-compute variable patterns for efficient substitution
-    let patterns: SmallBuffer<VarPattern, 16> = vars
-        .iter()
-        .map(|(key, value)| VarPattern {
-            pattern: format!("{{{{{}}}}}", key),
-            value: value.clone(),
-        })
-        .collect();
+/// Native-testable core of `cook_batch_with_progress`: the chunk arrives
+/// as a JSON array of cooked formulas and the percentage counts cooked
+/// formulas, so the final call always reports 100. An error from `emit`
+/// (e.g. a throwing JS callback) aborts the remaining chunks.
+pub fn cook_batch_chunked_impl(
+    formulas_json: &str,
+    vars_json: &str,
+    chunk_size: u32,
+    emit: &mut dyn FnMut(&str, f64) -> Result<(), String>,
+) -> Result<u32, JsValue> {
+    // Length check must come first, before any payload deserialization
+    check_batch_lengths(formulas_json, vars_json)?;
 
-    // Fast substitution function using pre-computed patterns
-    let substitute = |text: &str| -> String {
-        if patterns.is_empty() || !text.contains("{{") {
-            return text.to_string();
-        }
+    let formulas: Vec<Formula> = serde_json::from_str(formulas_json)
+        .map_err(|e| JsValue::from_str(&format!("Formulas parse error: {}", e)))?;
 
-        let mut result = text.to_string();
-        for pat in patterns.iter() {
-            if result.contains(&pat.pattern) {
-                result = result.replace(&pat.pattern, &pat.value);
-            }
-        }
-        result
-    };
+    let vars_list: Vec<FxHashMap<String, String>> = serde_json::from_str(vars_json)
+        .map_err(|e| JsValue::from_str(&format!("Vars parse error: {}", e)))?;
 
-    // Cook steps with pre-allocated capacity
-    let cooked_steps: Vec<Step> = if formula.steps.is_empty() {
-        Vec::new()
+    let chunk_size = if chunk_size == 0 {
+        default_progress_chunk_size()
     } else {
-        formula.steps.iter().map(|step| {
-            Step {
-                id: step.id.clone(),
-                title: substitute(&step.title),
-                description: substitute(&step.description),
-                needs: step.needs.clone(),
-                duration: step.duration,
-                requires: step.requires.clone(),
-            }
-        }).collect()
+        chunk_size as usize
     };
 
-    // Cook legs with pre-allocated capacity
-    let cooked_legs: Vec<Leg> = if formula.legs.is_empty() {
-        Vec::new()
+    let total = formulas.len();
+    let mut done = 0usize;
+    for (formula_chunk, vars_chunk) in formulas.chunks(chunk_size).zip(vars_list.chunks(chunk_size)) {
+        let cooked: Vec<CookedFormula> = formula_chunk
+            .iter()
+            .zip(vars_chunk.iter())
+            .map(|(formula, vars)| cook_formula_internal(formula, vars))
+            .collect();
+        done += cooked.len();
+
+        let chunk_json = serde_json::to_string(&cooked)
+            .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))?;
+        let percent = done as f64 * 100.0 / total.max(1) as f64;
+        emit(&chunk_json, percent).map_err(|e| JsValue::from_str(&e))?;
+    }
+
+    Ok(total as u32)
+}
+
+/// Resolve once through the JS microtask queue so the event loop can run
+pub(crate) async fn yield_to_event_loop() {
+    let _ = wasm_bindgen_futures::JsFuture::from(js_sys::Promise::resolve(&JsValue::UNDEFINED)).await;
+}
+
+/// Cook a batch in chunks, yielding to the JS event loop between chunks
+///
+/// Same output as `cook_batch_impl`, but awaits one microtask turn after
+/// every `chunk_size` formulas (0 uses the default of 64) so very large
+/// batches don't freeze the browser main thread. Only meaningful under a
+/// JS event loop.
+pub async fn cook_batch_async_impl(
+    formulas_json: &str,
+    vars_json: &str,
+    chunk_size: u32,
+) -> Result<String, JsValue> {
+    // Length check must come first, before any payload deserialization
+    check_batch_lengths(formulas_json, vars_json)?;
+
+    let formulas: Vec<Formula> = serde_json::from_str(formulas_json)
+        .map_err(|e| JsValue::from_str(&format!("Formulas parse error: {}", e)))?;
+
+    let vars_list: Vec<FxHashMap<String, String>> = serde_json::from_str(vars_json)
+        .map_err(|e| JsValue::from_str(&format!("Vars parse error: {}", e)))?;
+
+    let chunk_size = if chunk_size == 0 {
+        default_progress_chunk_size()
     } else {
-        formula.legs.iter().map(|leg| {
-            Leg {
-                id: leg.id.clone(),
-                title: substitute(&leg.title),
-                focus: substitute(&leg.focus),
-                description: substitute(&leg.description),
-                agent: leg.agent.clone(),
-                order: leg.order,
-            }
-        }).collect()
+        chunk_size as usize
     };
 
-    // Create cooked formula
-    let cooked_formula = Formula {
-        name: substitute(&formula.name),
-        description: substitute(&formula.description),
-        formula_type: formula.formula_type.clone(),
-        version: formula.version,
-        legs: cooked_legs,
-        synthesis: formula.synthesis.clone(),
-        steps: cooked_steps,
-        vars: formula.vars.clone(),
-    };
+    let total = formulas.len();
+    let mut cooked = Vec::with_capacity(total);
+    for (formula_chunk, vars_chunk) in formulas.chunks(chunk_size).zip(vars_list.chunks(chunk_size)) {
+        for (formula, vars) in formula_chunk.iter().zip(vars_chunk.iter()) {
+            cooked.push(cook_formula_internal(formula, vars));
+        }
+        if cooked.len() < total {
+            yield_to_event_loop().await;
+        }
+    }
 
-    // Convert vars to standard HashMap for serialization
-    let cooked_vars: std::collections::HashMap<String, String> = vars
-        .iter()
-        .map(|(k, v)| (k.clone(), v.clone()))
-        .collect();
+    serde_json::to_string(&cooked)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
 
-    CookedFormula {
-        formula: cooked_formula,
-        cooked_at: chrono_lite_now(),
-        cooked_vars,
-        original_name: formula.name.clone(),
+/// Decode an NDJSON byte payload into one deserialized value per line
+///
+/// Blank lines are skipped, so a trailing newline after the last record
+/// is fine. The payload must be UTF-8.
+fn decode_ndjson<T: serde::de::DeserializeOwned>(bytes: &[u8], what: &str) -> Result<Vec<T>, String> {
+    let text = std::str::from_utf8(bytes).map_err(|e| format!("{} not UTF-8: {}", what, e))?;
+
+    let mut out = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value = serde_json::from_str(line)
+            .map_err(|e| format!("{} parse error on line {}: {}", what, i + 1, e))?;
+        out.push(value);
     }
+    Ok(out)
 }
 
-/// Optimized multi-pattern substitution
+/// Batch cook from and to NDJSON byte payloads
 ///
-/// Uses a single pass through the string to find all patterns
-#[inline]
-fn substitute_all(text: &str, patterns: &[VarPattern]) -> String {
-    if patterns.is_empty() || !text.contains("{{") {
-        return text.to_string();
-    }
+/// Native-testable core of `cook_batch_bytes`: `formulas_ndjson` and
+/// `vars_ndjson` hold one JSON document per line, and the output holds
+/// one cooked formula per line in the same order. Byte payloads let Web
+/// Workers transfer the backing ArrayBuffer zero-copy instead of
+/// re-stringifying the batch across the boundary.
+pub fn cook_batch_bytes_internal(
+    formulas_ndjson: &[u8],
+    vars_ndjson: &[u8],
+) -> Result<Vec<u8>, String> {
+    let formulas: Vec<Formula> = decode_ndjson(formulas_ndjson, "Formulas")?;
+    let vars_list: Vec<FxHashMap<String, String>> = decode_ndjson(vars_ndjson, "Vars")?;
 
-    // For small number of patterns, sequential replacement is faster
-    if patterns.len() <= 4 {
-        let mut result = text.to_string();
-        for pat in patterns {
-            if result.contains(&pat.pattern) {
-                result = result.replace(&pat.pattern, &pat.value);
-            }
+    if formulas.len() != vars_list.len() {
+        return Err(CookError::BatchLengthMismatch {
+            formulas: formulas.len(),
+            vars: vars_list.len(),
         }
-        return result;
+        .to_string());
     }
 
-    // For larger pattern sets, build result incrementally
-    let mut result = String::with_capacity(text.len() * 2);
-    let mut last_end = 0;
-    let bytes = text.as_bytes();
-    let len = bytes.len();
+    let mut out = Vec::new();
+    for (formula, vars) in formulas.iter().zip(vars_list.iter()) {
+        let cooked = cook_formula_internal(formula, vars);
+        serde_json::to_writer(&mut out, &cooked).map_err(|e| format!("Serialize error: {}", e))?;
+        out.push(b'\n');
+    }
+    Ok(out)
+}
 
-    let mut i = 0;
-    while i < len {
-        if i + 2 < len && bytes[i] == b'{' && bytes[i + 1] == b'{' {
-            // Found potential pattern start
+/// Batch cook NDJSON bytes, wrapping errors for the WASM boundary
+#[inline]
+pub fn cook_batch_bytes_impl(
+    formulas_ndjson: &[u8],
+    vars_ndjson: &[u8],
+) -> Result<Vec<u8>, JsValue> {
+    cook_batch_bytes_internal(formulas_ndjson, vars_ndjson).map_err(|e| JsValue::from_str(&e))
+}
+
+/// Resolve the real values of secret vars for one cook
+///
+/// The cooked output masks secret values as `***`; this companion
+/// returns the name -> real value map (after defaults and var
+/// references resolve, name-sorted) so callers can inject secrets where
+/// they are actually needed.
+#[inline]
+pub fn cook_formula_secrets_impl(formula_json: &str, vars_json: &str) -> Result<String, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+
+    let vars = parse_vars_json(vars_json).map_err(|e| JsValue::from_str(&e))?;
+    let vars = resolve_var_references(&formula, &vars)?;
+
+    let secrets: std::collections::BTreeMap<String, String> = formula
+        .vars
+        .iter()
+        .filter(|(_, var)| var.secret)
+        .filter_map(|(name, _)| vars.get(name).map(|value| (name.clone(), value.clone())))
+        .collect();
+
+    serde_json::to_string(&secrets)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Cook a formula and project the output down to selected fields
+///
+/// `projection_json` is a JSON array of dot-paths to include (e.g.
+/// `["formula.steps", "cooked_vars"]`). Fields not in the projection are
+/// omitted, which keeps the WASM->JS payload small for UI previews.
+#[inline]
+pub fn cook_formula_project_impl(
+    formula_json: &str,
+    vars_json: &str,
+    projection_json: &str,
+) -> Result<String, JsValue> {
+    let projection: Vec<String> = serde_json::from_str(projection_json)
+        .map_err(|e| JsValue::from_str(&format!("Projection parse error: {}", e)))?;
+
+    let cooked_json = cook_formula_impl(formula_json, vars_json)?;
+    let cooked: serde_json::Value = serde_json::from_str(&cooked_json)
+        .map_err(|e| JsValue::from_str(&format!("Cooked parse error: {}", e)))?;
+
+    let mut out = serde_json::Map::new();
+    for path in &projection {
+        // The inner formula is flattened into the top level of the cooked
+        // JSON, so a leading "formula." segment addresses the same fields
+        let segments: Vec<&str> = path
+            .split('.')
+            .skip_while(|s| *s == "formula")
+            .collect();
+
+        let mut value = &cooked;
+        let mut found = true;
+        for segment in &segments {
+            match value.get(segment) {
+                Some(v) => value = v,
+                None => {
+                    found = false;
+                    break;
+                }
+            }
+        }
+        if !found || segments.is_empty() {
+            continue;
+        }
+
+        // Rebuild the nested structure for this path
+        let mut target = &mut out;
+        for segment in &segments[..segments.len() - 1] {
+            target = target
+                .entry(segment.to_string())
+                .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()))
+                .as_object_mut()
+                .expect("projection target is always an object");
+        }
+        target.insert(segments[segments.len() - 1].to_string(), value.clone());
+    }
+
+    serde_json::to_string(&serde_json::Value::Object(out))
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Assert invariants that must hold when a cooked formula is re-cooked
+///
+/// `original_name` is fixed at first cook time and must survive any number
+/// of re-cooks. Re-cook flows (e.g. reheat) should call this to catch bugs
+/// early: panics in debug builds, returns `Err` in release builds.
+pub fn assert_cook_invariants(
+    original: &CookedFormula,
+    recooked: &CookedFormula,
+) -> Result<(), CookError> {
+    if recooked.original_name != original.original_name {
+        debug_assert!(
+            false,
+            "Cook invariant violated: original_name changed from '{}' to '{}'",
+            original.original_name, recooked.original_name
+        );
+        return Err(CookError::OriginalNameChanged {
+            original: original.original_name.clone(),
+            recooked: recooked.original_name.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Cook one formula with many var sets
+///
+/// More efficient than calling `cook_formula` N times because the formula
+/// is only parsed and validated once. Useful for "deploy to all
+/// environments" scenarios.
+#[inline]
+pub fn cook_formula_multi_impl(formula_json: &str, var_sets_json: &str) -> Result<String, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+
+    let var_sets: Vec<FxHashMap<String, String>> = serde_json::from_str(var_sets_json)
+        .map_err(|e| JsValue::from_str(&format!("Var sets parse error: {}", e)))?;
+
+    let cooked: Vec<CookedFormula> = var_sets
+        .iter()
+        .map(|vars| cook_formula_internal(&formula, vars))
+        .collect();
+
+    serde_json::to_string(&cooked)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Reject var values larger than the configured limit
+///
+/// Oversized values usually mean file contents were passed where a path
+/// was intended; failing fast beats ballooning every substituted field.
+pub(crate) fn check_var_value_sizes(
+    vars: &FxHashMap<String, String>,
+    max: u32,
+) -> Result<(), CookError> {
+    for (name, value) in vars {
+        if value.len() > max as usize {
+            return Err(CookError::VarValueTooLarge {
+                var_name: name.clone(),
+                size: value.len(),
+                max,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Validate supplied var values against declared numeric bounds
+///
+/// Bounds (`min`, `max`, `min_exclusive`, `max_exclusive`) only apply to
+/// vars that declare them. The checked value is the supplied one, falling
+/// back to the var's default; vars without a value are skipped here (the
+/// required-var check is a separate concern).
+pub(crate) fn validate_var_bounds(
+    formula: &Formula,
+    vars: &FxHashMap<String, String>,
+) -> Result<(), CookError> {
+    for (name, var) in &formula.vars {
+        let bounds = [
+            ("min", var.min),
+            ("max", var.max),
+            ("min_exclusive", var.min_exclusive),
+            ("max_exclusive", var.max_exclusive),
+        ];
+        if bounds.iter().all(|(_, b)| b.is_none()) {
+            continue;
+        }
+
+        let Some(value) = vars.get(name).or(var.default.as_ref()) else {
+            continue;
+        };
+
+        let Ok(number) = value.parse::<f64>() else {
+            return Err(CookError::ValidationFailed {
+                var_name: name.clone(),
+                constraint: "numeric".to_string(),
+                expected: "a number".to_string(),
+                actual: value.clone(),
+            });
+        };
+
+        for (constraint, bound) in bounds {
+            let Some(bound) = bound else { continue };
+            let (ok, op) = match constraint {
+                "min" => (number >= bound, ">="),
+                "max" => (number <= bound, "<="),
+                "min_exclusive" => (number > bound, ">"),
+                _ => (number < bound, "<"),
+            };
+            if !ok {
+                return Err(CookError::ValidationFailed {
+                    var_name: name.clone(),
+                    constraint: constraint.to_string(),
+                    expected: format!("{} {}", op, bound),
+                    actual: value.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// One pattern or enum constraint violation
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ConstraintViolation {
+    pub var_name: String,
+    /// Violated constraint: `pattern`, `enum`, `min_length`,
+    /// `max_length`, or `format`
+    pub constraint: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Anchored regex for a named var `format`, or `None` when unknown
+///
+/// These are deliberately permissive sanity checks (e.g. the email
+/// pattern does not chase RFC 5321 corner cases); a var needing stricter
+/// rules can declare its own `pattern`.
+fn format_pattern(format: &str) -> Option<&'static str> {
+    match format {
+        "email" => Some(r"^[^@\s]+@[^@\s]+\.[^@\s]+$"),
+        "uri" => Some(r"^[a-zA-Z][a-zA-Z0-9+.-]*://\S+$"),
+        "date" => Some(r"^\d{4}-\d{2}-\d{2}$"),
+        "uuid" => Some(
+            r"^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}$",
+        ),
+        "slug" => Some(r"^[a-z0-9]+(?:-[a-z0-9]+)*$"),
+        _ => None,
+    }
+}
+
+/// Validate supplied values against declared shape constraints
+///
+/// Covers `pattern`, `enum`, `min_length`/`max_length`, and named
+/// `format` checks. The checked value is the supplied one, falling back
+/// to the var's default; vars without a value are skipped. Unlike the
+/// bounds check, every violation is collected so the caller sees the
+/// full list in one pass. Patterns match the whole value, and a pattern that fails to
+/// compile is itself reported as a violation.
+pub(crate) fn validate_var_constraints(
+    formula: &Formula,
+    vars: &FxHashMap<String, String>,
+) -> Result<(), CookError> {
+    let mut violations: Vec<ConstraintViolation> = Vec::new();
+
+    for (name, var) in &formula.vars {
+        if var.pattern.is_none()
+            && var.enum_values.is_none()
+            && var.min_length.is_none()
+            && var.max_length.is_none()
+            && var.format.is_none()
+        {
+            continue;
+        }
+        let Some(value) = vars.get(name).or(var.default.as_ref()) else {
+            continue;
+        };
+
+        let length = value.chars().count();
+        if let Some(min_length) = var.min_length {
+            if length < min_length as usize {
+                violations.push(ConstraintViolation {
+                    var_name: name.clone(),
+                    constraint: "min_length".to_string(),
+                    expected: format!("at least {} characters", min_length),
+                    actual: value.clone(),
+                });
+            }
+        }
+        if let Some(max_length) = var.max_length {
+            if length > max_length as usize {
+                violations.push(ConstraintViolation {
+                    var_name: name.clone(),
+                    constraint: "max_length".to_string(),
+                    expected: format!("at most {} characters", max_length),
+                    actual: value.clone(),
+                });
+            }
+        }
+
+        if let Some(format) = &var.format {
+            match format_pattern(format) {
+                Some(pattern) => {
+                    // Format regexes are static and known-good
+                    let re = regex::Regex::new(pattern).expect("format pattern compiles");
+                    if !re.is_match(value) {
+                        violations.push(ConstraintViolation {
+                            var_name: name.clone(),
+                            constraint: "format".to_string(),
+                            expected: format!("a value in '{}' format", format),
+                            actual: value.clone(),
+                        });
+                    }
+                }
+                None => violations.push(ConstraintViolation {
+                    var_name: name.clone(),
+                    constraint: "format".to_string(),
+                    expected: "one of [email, uri, date, uuid, slug]".to_string(),
+                    actual: format.clone(),
+                }),
+            }
+        }
+
+        if let Some(allowed) = &var.enum_values {
+            if !allowed.contains(value) {
+                violations.push(ConstraintViolation {
+                    var_name: name.clone(),
+                    constraint: "enum".to_string(),
+                    expected: format!("one of [{}]", allowed.join(", ")),
+                    actual: value.clone(),
+                });
+            }
+        }
+
+        if let Some(pattern) = &var.pattern {
+            match regex::Regex::new(&format!("^(?:{})$", pattern)) {
+                Ok(re) => {
+                    if !re.is_match(value) {
+                        violations.push(ConstraintViolation {
+                            var_name: name.clone(),
+                            constraint: "pattern".to_string(),
+                            expected: format!("a value matching /{}/", pattern),
+                            actual: value.clone(),
+                        });
+                    }
+                }
+                Err(_) => violations.push(ConstraintViolation {
+                    var_name: name.clone(),
+                    constraint: "pattern".to_string(),
+                    expected: "a compilable regex pattern".to_string(),
+                    actual: pattern.clone(),
+                }),
+            }
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        violations.sort_by(|a, b| a.var_name.cmp(&b.var_name));
+        Err(CookError::ConstraintViolations { violations })
+    }
+}
+
+/// Validate supplied values against each var's declared `type`
+///
+/// The checked value is the supplied one, falling back to the var's
+/// default; vars without a value, or with the default `string` type, are
+/// skipped. A value that does not coerce fails with a `type` constraint
+/// error naming the expected type.
+pub(crate) fn validate_var_types(
+    formula: &Formula,
+    vars: &FxHashMap<String, String>,
+) -> Result<(), CookError> {
+    for (name, var) in &formula.vars {
+        if var.var_type == crate::VarType::String {
+            continue;
+        }
+        let Some(value) = vars.get(name).or(var.default.as_ref()) else {
+            continue;
+        };
+        if let Err(expected) = coerce_var_value(var.var_type, value) {
+            return Err(CookError::ValidationFailed {
+                var_name: name.clone(),
+                constraint: "type".to_string(),
+                expected,
+                actual: value.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Coerce a text var value into a native JSON value per its declared type
+///
+/// Coercion rules:
+/// - `int`: decimal integer (`i64`)
+/// - `float`: any `f64` literal
+/// - `bool`: `true`/`false` (case-insensitive) or `1`/`0`
+/// - `list`: a JSON array, or a comma-separated list of strings
+/// - `map`: a JSON object
+/// - `string`: passthrough
+///
+/// On failure returns a description of the expected form, for the
+/// validation error message.
+pub(crate) fn coerce_var_value(
+    var_type: crate::VarType,
+    value: &str,
+) -> Result<serde_json::Value, String> {
+    use crate::VarType;
+
+    match var_type {
+        VarType::String => Ok(serde_json::Value::String(value.to_string())),
+        VarType::Int => value
+            .trim()
+            .parse::<i64>()
+            .map(serde_json::Value::from)
+            .map_err(|_| "an integer".to_string()),
+        VarType::Float => value
+            .trim()
+            .parse::<f64>()
+            .map(serde_json::Value::from)
+            .map_err(|_| "a number".to_string()),
+        VarType::Bool => match value.trim().to_ascii_lowercase().as_str() {
+            "true" | "1" => Ok(serde_json::Value::Bool(true)),
+            "false" | "0" => Ok(serde_json::Value::Bool(false)),
+            _ => Err("true or false".to_string()),
+        },
+        VarType::List => {
+            let trimmed = value.trim();
+            if trimmed.starts_with('[') {
+                return serde_json::from_str::<Vec<serde_json::Value>>(trimmed)
+                    .map(serde_json::Value::Array)
+                    .map_err(|_| "a JSON array".to_string());
+            }
+            Ok(serde_json::Value::Array(
+                trimmed
+                    .split(',')
+                    .map(|item| serde_json::Value::String(item.trim().to_string()))
+                    .collect(),
+            ))
+        }
+        VarType::Map => serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(
+            value.trim(),
+        )
+        .map(serde_json::Value::Object)
+        .map_err(|_| "a JSON object".to_string()),
+    }
+}
+
+/// Native values for every var with a non-string declared type
+///
+/// Infallible: values were already checked by `validate_var_types`, so
+/// any stragglers that fail to coerce here are simply omitted (lenient
+/// callers that skip validation get best-effort typing).
+fn typed_vars_for(
+    formula: &Formula,
+    vars: &FxHashMap<String, String>,
+) -> std::collections::HashMap<String, serde_json::Value> {
+    formula
+        .vars
+        .iter()
+        .filter(|(_, var)| var.var_type != crate::VarType::String)
+        .filter_map(|(name, var)| {
+            let value = vars.get(name).or(var.default.as_ref())?;
+            let typed = coerce_var_value(var.var_type, value).ok()?;
+            Some((name.clone(), typed))
+        })
+        .collect()
+}
+
+/// Validate every `${...}` template expression in a formula
+///
+/// Walks the substitutable text fields and evaluates each expression
+/// against the supplied vars; the first failure is returned with the
+/// offending expression. Run before cooking so invalid expressions are
+/// hard errors rather than silently unexpanded text.
+pub(crate) fn validate_expressions(
+    formula: &Formula,
+    vars: &FxHashMap<String, String>,
+) -> Result<(), CookError> {
+    validate_expressions_delim(formula, vars, "${", "}")
+}
+
+/// Validate template expressions using the configured delimiters
+pub(crate) fn validate_expressions_delim(
+    formula: &Formula,
+    vars: &FxHashMap<String, String>,
+    open: &str,
+    close: &str,
+) -> Result<(), CookError> {
+    let mut fields: Vec<&str> = vec![&formula.name, &formula.description];
+    let mut conditions: Vec<&str> = Vec::new();
+    for step in &formula.steps {
+        fields.push(&step.title);
+        fields.push(&step.description);
+        conditions.extend(step.when.as_deref());
+    }
+    for leg in &formula.legs {
+        fields.push(&leg.title);
+        fields.push(&leg.focus);
+        fields.push(&leg.description);
+        conditions.extend(leg.when.as_deref());
+    }
+
+    for field in fields {
+        for (_, expression) in find_expressions_delim(field, open, close) {
+            if let Err(message) = eval_expression(expression, vars) {
+                return Err(CookError::InvalidExpression {
+                    expression: expression.to_string(),
+                    message,
+                });
+            }
+        }
+    }
+
+    // `when` conditions are bare expressions (no `${...}` wrapper) and
+    // must come out boolean
+    for condition in conditions {
+        match eval_expression(condition, vars) {
+            Ok(result) if result == "true" || result == "false" => {}
+            Ok(result) => {
+                return Err(CookError::InvalidExpression {
+                    expression: condition.to_string(),
+                    message: format!("Condition must evaluate to a boolean, got '{}'", result),
+                });
+            }
+            Err(message) => {
+                return Err(CookError::InvalidExpression {
+                    expression: condition.to_string(),
+                    message,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Record where each effective var value came from
+///
+/// Precedence mirrors the cook pipeline: supplied vars win over the
+/// options env map, which wins over declared defaults. The declared
+/// default is recorded on every entry that has one, even when it was
+/// overridden, so audits can see what would have applied.
+pub(crate) fn var_provenance_for(
+    formula: &Formula,
+    supplied: &FxHashMap<String, String>,
+    env: Option<&std::collections::HashMap<String, String>>,
+) -> std::collections::HashMap<String, crate::VarProvenance> {
+    let mut provenance = std::collections::HashMap::new();
+
+    for name in supplied.keys() {
+        provenance.insert(
+            name.clone(),
+            crate::VarProvenance {
+                source: "supplied".to_string(),
+                default: formula.vars.get(name).and_then(|var| var.default.clone()),
+            },
+        );
+    }
+
+    if let Some(env) = env {
+        for name in env.keys() {
+            provenance.entry(name.clone()).or_insert_with(|| crate::VarProvenance {
+                source: "env".to_string(),
+                default: formula.vars.get(name).and_then(|var| var.default.clone()),
+            });
+        }
+    }
+
+    for (name, var) in &formula.vars {
+        if let Some(default) = &var.default {
+            provenance.entry(name.clone()).or_insert_with(|| crate::VarProvenance {
+                source: "default".to_string(),
+                default: Some(default.clone()),
+            });
+        }
+    }
+
+    provenance
+}
+
+/// Layer a host-supplied environment map under the provided vars
+///
+/// Provided vars win on conflicts; formula defaults stay last in the
+/// chain (applied afterwards by `resolve_var_references`).
+pub(crate) fn layer_env_vars(
+    vars: &FxHashMap<String, String>,
+    env: Option<&std::collections::HashMap<String, String>>,
+) -> FxHashMap<String, String> {
+    let mut layered: FxHashMap<String, String> = env
+        .map(|map| map.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+        .unwrap_or_default();
+    for (name, value) in vars {
+        layered.insert(name.clone(), value.clone());
+    }
+    layered
+}
+
+/// Maximum depth when resolving `${...}` references between var values
+pub(crate) const MAX_VAR_REF_DEPTH: usize = 16;
+
+/// Resolve `${...}` var references inside var values and defaults
+///
+/// Builds the effective vars map the cook substitutes from: supplied
+/// values win, defaults fill in the rest, and a value containing a bare
+/// `${other_var}` reference is rewritten with that var's resolved value,
+/// transitively. Only plain identifier references participate here —
+/// anything more complex is left for the expression evaluator at cook
+/// time. Cycles and over-deep chains are structured errors.
+pub(crate) fn resolve_var_references(
+    formula: &Formula,
+    supplied: &FxHashMap<String, String>,
+) -> Result<FxHashMap<String, String>, CookError> {
+    // name -> raw value; supplied wins over the declared default
+    let mut raw: FxHashMap<String, String> = FxHashMap::default();
+    for (name, var) in &formula.vars {
+        if let Some(value) = supplied.get(name).or(var.default.as_ref()) {
+            raw.insert(name.clone(), value.clone());
+        }
+    }
+    for (name, value) in supplied {
+        raw.entry(name.clone()).or_insert_with(|| value.clone());
+    }
+
+    let mut resolved: FxHashMap<String, String> = FxHashMap::default();
+    let mut chain: Vec<String> = Vec::new();
+    let names: Vec<String> = raw.keys().cloned().collect();
+    for name in names {
+        resolve_var_value(&name, &raw, &mut resolved, &mut chain)?;
+    }
+    Ok(resolved)
+}
+
+/// Resolve one var's value, recursing into its references
+fn resolve_var_value(
+    name: &str,
+    raw: &FxHashMap<String, String>,
+    resolved: &mut FxHashMap<String, String>,
+    chain: &mut Vec<String>,
+) -> Result<String, CookError> {
+    if let Some(done) = resolved.get(name) {
+        return Ok(done.clone());
+    }
+    if chain.iter().any(|seen| seen == name) {
+        let start = chain.iter().position(|seen| seen == name).unwrap();
+        let mut cycle: Vec<String> = chain[start..].to_vec();
+        cycle.push(name.to_string());
+        return Err(CookError::VarReferenceCycle { chain: cycle });
+    }
+    if chain.len() >= MAX_VAR_REF_DEPTH {
+        return Err(CookError::VarReferenceTooDeep {
+            var_name: name.to_string(),
+            max: MAX_VAR_REF_DEPTH,
+        });
+    }
+
+    let value = raw[name].clone();
+    chain.push(name.to_string());
+
+    let mut output = String::with_capacity(value.len());
+    let mut cursor = 0;
+    for (range, expression) in find_expressions(&value) {
+        let reference = expression.trim();
+        let is_reference = !reference.is_empty()
+            && reference
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && raw.contains_key(reference);
+        if !is_reference {
+            continue;
+        }
+        output.push_str(&value[cursor..range.start]);
+        output.push_str(&resolve_var_value(reference, raw, resolved, chain)?);
+        cursor = range.end;
+    }
+    output.push_str(&value[cursor..]);
+
+    chain.pop();
+    resolved.insert(name.to_string(), output.clone());
+    Ok(output)
+}
+
+/// Validate `foreach` references on steps
+///
+/// Each `foreach` must name a variable that resolves (supplied value or
+/// default) to a list per the `list` coercion rules.
+pub(crate) fn validate_foreach(
+    formula: &Formula,
+    vars: &FxHashMap<String, String>,
+) -> Result<(), CookError> {
+    for step in &formula.steps {
+        let Some(list_name) = &step.foreach else {
+            continue;
+        };
+        let value = vars
+            .get(list_name)
+            .or_else(|| formula.vars.get(list_name).and_then(|v| v.default.as_ref()));
+        let resolves = value
+            .map(|v| coerce_var_value(crate::VarType::List, v).is_ok())
+            .unwrap_or(false);
+        if !resolves {
+            return Err(CookError::ValidationFailed {
+                var_name: list_name.clone(),
+                constraint: "foreach".to_string(),
+                expected: "a list value".to_string(),
+                actual: value.cloned().unwrap_or_else(|| "<missing>".to_string()),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Expand `foreach` steps into one concrete step per list item
+///
+/// An expanded step's id becomes `<id>-<item-slug>` and `{{item}}` in
+/// its title and description is replaced with the item value. `needs`
+/// entries naming an expanded step are rewritten to all of its expanded
+/// ids, so downstream steps wait for every instance. Steps whose list
+/// fails to resolve are left unexpanded — the strict cook paths reject
+/// those up front via `validate_foreach`.
+fn expand_foreach_steps(formula: &Formula, vars: &FxHashMap<String, String>) -> Vec<Step> {
+    if formula.steps.iter().all(|step| step.foreach.is_none()) {
+        return formula.steps.clone();
+    }
+
+    // Original id -> expanded ids, for rewriting `needs` afterwards
+    let mut expanded_ids: std::collections::HashMap<String, Vec<String>> =
+        std::collections::HashMap::new();
+    let mut steps = Vec::new();
+
+    for step in &formula.steps {
+        let items = step.foreach.as_ref().and_then(|name| {
+            let value = vars
+                .get(name)
+                .or_else(|| formula.vars.get(name).and_then(|v| v.default.as_ref()))?;
+            let list = coerce_var_value(crate::VarType::List, value).ok()?;
+            Some(
+                list.as_array()?
+                    .iter()
+                    .map(|item| match item {
+                        serde_json::Value::String(text) => text.clone(),
+                        other => other.to_string(),
+                    })
+                    .collect::<Vec<String>>(),
+            )
+        });
+        let Some(items) = items else {
+            steps.push(step.clone());
+            continue;
+        };
+
+        let mut ids = Vec::with_capacity(items.len());
+        for item in &items {
+            let mut expanded = step.clone();
+            expanded.foreach = None;
+            expanded.id = format!("{}-{}", step.id, slugify_item(item));
+            expanded.title = step.title.replace("{{item}}", item);
+            expanded.description = step.description.replace("{{item}}", item);
+            ids.push(expanded.id.clone());
+            steps.push(expanded);
+        }
+        expanded_ids.insert(step.id.clone(), ids);
+    }
+
+    for step in &mut steps {
+        if step.needs.iter().any(|need| expanded_ids.contains_key(need)) {
+            step.needs = step
+                .needs
+                .iter()
+                .flat_map(|need| {
+                    expanded_ids
+                        .get(need)
+                        .cloned()
+                        .unwrap_or_else(|| vec![need.clone()])
+                })
+                .collect();
+        }
+    }
+
+    steps
+}
+
+/// Turn a foreach item value into a step-id suffix
+///
+/// Lowercases and maps runs of non-alphanumeric characters to a single
+/// `-`, so `us-east 1` becomes `us-east-1`.
+fn slugify_item(item: &str) -> String {
+    let mut slug = String::with_capacity(item.len());
+    for c in item.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_matches('-').to_string()
+}
+
+/// Evaluate a step/leg `when` condition against the supplied vars
+///
+/// Entries without a condition pass. Failed evaluations also pass: the
+/// strict cook paths reject invalid conditions up front via
+/// `validate_expressions`, and keeping the entry is the safer lenient
+/// behavior.
+fn when_passes(condition: Option<&str>, vars: &FxHashMap<String, String>) -> bool {
+    match condition {
+        None => true,
+        Some(expression) => eval_expression(expression, vars).as_deref() != Ok("false"),
+    }
+}
+
+/// Expand template expressions in a text field with the default `${...}`
+/// delimiters
+///
+/// Expressions that fail to evaluate are left as-is; the strict cook
+/// paths reject them up front via `validate_expressions`, so stragglers
+/// only appear on lenient callers.
+fn substitute_expressions(text: &str, vars: &FxHashMap<String, String>) -> String {
+    substitute_expressions_delim(text, vars, "${", "}")
+}
+
+/// Expand template expressions using the configured delimiters
+///
+/// A doubled first character of the opening delimiter is an escape: it
+/// emits the delimiter literally without starting an expression (`$${x}`
+/// cooks to `${x}`).
+fn substitute_expressions_delim(
+    text: &str,
+    vars: &FxHashMap<String, String>,
+    open: &str,
+    close: &str,
+) -> String {
+    let escape = delimiter_escape(open);
+    if !text.contains(open) {
+        return text.to_string();
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while !rest.is_empty() {
+        if rest.starts_with(&escape) {
+            result.push_str(open);
+            rest = &rest[escape.len()..];
+            continue;
+        }
+        if rest.starts_with(open) {
+            if let Some(end) = find_expression_close(&rest[open.len()..], close) {
+                let expression = &rest[open.len()..open.len() + end];
+                let full = &rest[..open.len() + end + close.len()];
+                match eval_expression(expression, vars) {
+                    Ok(value) => result.push_str(&value),
+                    Err(_) => result.push_str(full),
+                }
+                rest = &rest[full.len()..];
+                continue;
+            }
+        }
+        let step = rest.chars().next().map(char::len_utf8).unwrap_or(1);
+        result.push_str(&rest[..step]);
+        rest = &rest[step..];
+    }
+    result
+}
+
+/// Locate every `${...}` span in a text field (default delimiters)
+fn find_expressions(text: &str) -> Vec<(std::ops::Range<usize>, &str)> {
+    find_expressions_delim(text, "${", "}")
+}
+
+/// Locate every template expression span using the given delimiters
+///
+/// Returns `(byte_range_including_delimiters, inner_expression)` pairs.
+/// The closing delimiter is found respecting quoted strings, so an
+/// expression like `${name | default("}")}` scans correctly. Escaped
+/// delimiters (doubled first character) are not expressions.
+fn find_expressions_delim<'a>(
+    text: &'a str,
+    open: &str,
+    close: &str,
+) -> Vec<(std::ops::Range<usize>, &'a str)> {
+    let first = open.chars().next().unwrap_or('$');
+    let mut found = Vec::new();
+    let mut offset = 0;
+    while let Some(ahead) = text[offset..].find(open) {
+        let pos = offset + ahead;
+        // A delimiter preceded by its own first character is escaped
+        if text[..pos].ends_with(first) {
+            offset = pos + open.len();
+            continue;
+        }
+        let inner_start = pos + open.len();
+        let Some(end) = find_expression_close(&text[inner_start..], close) else {
+            break;
+        };
+        let inner_end = inner_start + end;
+        found.push((pos..inner_end + close.len(), &text[inner_start..inner_end]));
+        offset = inner_end + close.len();
+    }
+    found
+}
+
+/// Escape sequence for a literal opening delimiter: its first character
+/// doubled (`$${` for `${`, `%%{` for `%{`)
+fn delimiter_escape(open: &str) -> String {
+    let first = open.chars().next().unwrap_or('$');
+    format!("{}{}", first, open)
+}
+
+/// Find the closing delimiter, respecting quoted strings
+///
+/// Returns the byte offset of the delimiter within `text`, or `None`
+/// when unterminated.
+fn find_expression_close(text: &str, close: &str) -> Option<usize> {
+    let mut quote: Option<char> = None;
+    let mut i = 0;
+    while i < text.len() {
+        let c = text[i..].chars().next()?;
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if text[i..].starts_with(close) => return Some(i),
+            None => {}
+        }
+        i += c.len_utf8();
+    }
+    None
+}
+
+/// A value produced while evaluating a template expression
+#[derive(Debug, Clone, PartialEq)]
+enum ExprValue {
+    Num(f64),
+    Str(String),
+    Bool(bool),
+    /// An identifier with no supplied value; only the `default` filter
+    /// may consume it — anything else is an unknown-variable error
+    Missing(String),
+}
+
+impl ExprValue {
+    fn type_name(&self) -> &'static str {
+        match self {
+            ExprValue::Num(_) => "number",
+            ExprValue::Str(_) => "string",
+            ExprValue::Bool(_) => "boolean",
+            ExprValue::Missing(_) => "missing",
+        }
+    }
+
+    /// Error for using a missing variable outside `default(...)`
+    fn require_present(self) -> Result<ExprValue, String> {
+        match self {
+            ExprValue::Missing(name) => Err(format!("Unknown variable '{}'", name)),
+            value => Ok(value),
+        }
+    }
+}
+
+/// One lexed token of a template expression
+#[derive(Debug, Clone, PartialEq)]
+enum ExprToken {
+    Num(f64),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+}
+
+/// Evaluate a `${...}` expression against the supplied vars
+///
+/// Supports numbers, quoted strings, `true`/`false`, var references,
+/// arithmetic (`+ - * / %`, with `+` doubling as string concatenation),
+/// comparisons (`== != < <= > >=`), ternaries (`cond ? a : b`), grouping
+/// parentheses, and the pipe filters `default(value)`,
+/// `replace(from, to)`, `upper`, `lower`, `slug` and `trim`
+/// (parentheses optional for argument-less filters). The result is
+/// rendered as text for substitution.
+pub(crate) fn eval_expression(
+    expression: &str,
+    vars: &FxHashMap<String, String>,
+) -> Result<String, String> {
+    let tokens = tokenize_expression(expression)?;
+    if tokens.is_empty() {
+        return Err("Empty expression".to_string());
+    }
+
+    let mut parser = ExprParser {
+        tokens,
+        pos: 0,
+        vars,
+    };
+    let value = parser.parse_pipe()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(format!(
+            "Unexpected trailing input after position {}",
+            parser.pos
+        ));
+    }
+
+    match value.require_present()? {
+        ExprValue::Num(n) => Ok(render_number(n)),
+        ExprValue::Str(text) => Ok(text),
+        ExprValue::Bool(b) => Ok(b.to_string()),
+        ExprValue::Missing(_) => unreachable!("require_present rejects Missing"),
+    }
+}
+
+/// Render a numeric result without a trailing `.0` for whole numbers
+fn render_number(n: f64) -> String {
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        format!("{}", n as i64)
+    } else {
+        n.to_string()
+    }
+}
+
+/// Lex a template expression into tokens
+fn tokenize_expression(expression: &str) -> Result<Vec<ExprToken>, String> {
+    let mut tokens = Vec::new();
+    let bytes = expression.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i] as char;
+        match c {
+            ' ' | '\t' => i += 1,
+            '0'..='9' | '.' => {
+                let start = i;
+                while i < bytes.len() && matches!(bytes[i] as char, '0'..='9' | '.') {
+                    i += 1;
+                }
+                let literal = &expression[start..i];
+                let number = literal
+                    .parse::<f64>()
+                    .map_err(|_| format!("Invalid number '{}'", literal))?;
+                tokens.push(ExprToken::Num(number));
+            }
+            '"' | '\'' => {
+                let quote = c;
+                let start = i + 1;
+                let mut end = None;
+                for (j, cc) in expression[start..].char_indices() {
+                    if cc == quote {
+                        end = Some(start + j);
+                        break;
+                    }
+                }
+                let end = end.ok_or_else(|| "Unterminated string literal".to_string())?;
+                tokens.push(ExprToken::Str(expression[start..end].to_string()));
+                i = end + 1;
+            }
+            'a'..='z' | 'A'..='Z' | '_' => {
+                let start = i;
+                while i < bytes.len()
+                    && matches!(bytes[i] as char, 'a'..='z' | 'A'..='Z' | '0'..='9' | '_')
+                {
+                    i += 1;
+                }
+                tokens.push(ExprToken::Ident(expression[start..i].to_string()));
+            }
+            '=' | '!' | '<' | '>' => {
+                let two = i + 1 < bytes.len() && bytes[i + 1] as char == '=';
+                let op = match (c, two) {
+                    ('=', true) => "==",
+                    ('!', true) => "!=",
+                    ('<', true) => "<=",
+                    ('>', true) => ">=",
+                    ('<', false) => "<",
+                    ('>', false) => ">",
+                    _ => return Err(format!("Unexpected character '{}'", c)),
+                };
+                tokens.push(ExprToken::Op(op));
+                i += if two { 2 } else { 1 };
+            }
+            '+' => { tokens.push(ExprToken::Op("+")); i += 1; }
+            '-' => { tokens.push(ExprToken::Op("-")); i += 1; }
+            '*' => { tokens.push(ExprToken::Op("*")); i += 1; }
+            '/' => { tokens.push(ExprToken::Op("/")); i += 1; }
+            '%' => { tokens.push(ExprToken::Op("%")); i += 1; }
+            '?' => { tokens.push(ExprToken::Op("?")); i += 1; }
+            ':' => { tokens.push(ExprToken::Op(":")); i += 1; }
+            '|' => { tokens.push(ExprToken::Op("|")); i += 1; }
+            '(' => { tokens.push(ExprToken::Op("(")); i += 1; }
+            ')' => { tokens.push(ExprToken::Op(")")); i += 1; }
+            ',' => { tokens.push(ExprToken::Op(",")); i += 1; }
+            _ => return Err(format!("Unexpected character '{}'", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive-descent evaluator over lexed expression tokens
+///
+/// Grammar, loosest-binding first:
+/// `pipe := ternary ('|' filter)*`, `ternary := cmp ('?' expr ':' expr)?`,
+/// `cmp := add (op add)?`, `add := mul (('+'|'-') mul)*`,
+/// `mul := unary (('*'|'/'|'%') unary)*`, `unary := '-' unary | primary`.
+struct ExprParser<'a> {
+    tokens: Vec<ExprToken>,
+    pos: usize,
+    vars: &'a FxHashMap<String, String>,
+}
+
+impl ExprParser<'_> {
+    fn eat_op(&mut self, op: &str) -> bool {
+        if matches!(self.tokens.get(self.pos), Some(ExprToken::Op(found)) if *found == op) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_op(&mut self, op: &'static str) -> Result<(), String> {
+        if self.eat_op(op) {
+            Ok(())
+        } else {
+            Err(format!("Expected '{}'", op))
+        }
+    }
+
+    fn parse_pipe(&mut self) -> Result<ExprValue, String> {
+        let mut value = self.parse_ternary()?;
+        while self.eat_op("|") {
+            value = self.apply_filter(value)?;
+        }
+        Ok(value)
+    }
+
+    fn apply_filter(&mut self, value: ExprValue) -> Result<ExprValue, String> {
+        let name = match self.tokens.get(self.pos).cloned() {
+            Some(ExprToken::Ident(name)) => {
+                self.pos += 1;
+                name
+            }
+            _ => return Err("Expected a filter name after '|'".to_string()),
+        };
+
+        // Parentheses are optional for argument-less filters, so both
+        // `${name|slug}` and `${name | slug()}` work
+        let mut args = Vec::new();
+        if self.eat_op("(") && !self.eat_op(")") {
+            loop {
+                args.push(self.parse_ternary()?.require_present()?);
+                if self.eat_op(")") {
+                    break;
+                }
+                self.expect_op(",")?;
+            }
+        }
+
+        let expect_args = |count: usize| -> Result<(), String> {
+            if args.len() == count {
+                Ok(())
+            } else {
+                Err(format!(
+                    "Filter '{}' expects {} argument(s), got {}",
+                    name,
+                    count,
+                    args.len()
+                ))
+            }
+        };
+
+        match name.as_str() {
+            "default" => {
+                expect_args(1)?;
+                Ok(match value {
+                    ExprValue::Missing(_) => args.remove(0),
+                    present => present,
+                })
+            }
+            "upper" | "lower" | "slug" | "trim" => {
+                expect_args(0)?;
+                let text = render_value(&value.require_present()?);
+                Ok(ExprValue::Str(match name.as_str() {
+                    "upper" => text.to_uppercase(),
+                    "lower" => text.to_lowercase(),
+                    "slug" => slugify_item(&text),
+                    _ => text.trim().to_string(),
+                }))
+            }
+            "replace" => {
+                expect_args(2)?;
+                let text = render_value(&value.require_present()?);
+                let from = render_value(&args[0]);
+                let to = render_value(&args[1]);
+                Ok(ExprValue::Str(text.replace(&from, &to)))
+            }
+            other => Err(format!("Unknown filter '{}'", other)),
+        }
+    }
+
+    fn parse_ternary(&mut self) -> Result<ExprValue, String> {
+        let condition = self.parse_comparison()?;
+        if !self.eat_op("?") {
+            return Ok(condition);
+        }
+        let ExprValue::Bool(condition) = condition.require_present()? else {
+            return Err("Ternary condition must be a boolean".to_string());
+        };
+        let when_true = self.parse_ternary()?;
+        self.expect_op(":")?;
+        let when_false = self.parse_ternary()?;
+        Ok(if condition { when_true } else { when_false })
+    }
+
+    fn parse_comparison(&mut self) -> Result<ExprValue, String> {
+        let left = self.parse_additive()?;
+        let op = match self.tokens.get(self.pos) {
+            Some(ExprToken::Op(op @ ("==" | "!=" | "<" | "<=" | ">" | ">="))) => *op,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let left = left.require_present()?;
+        let right = self.parse_additive()?.require_present()?;
+
+        let result = match (&left, &right) {
+            (ExprValue::Num(a), ExprValue::Num(b)) => compare(op, a.partial_cmp(b)),
+            (ExprValue::Str(a), ExprValue::Str(b)) => compare(op, a.partial_cmp(b)),
+            (ExprValue::Bool(a), ExprValue::Bool(b)) if op == "==" || op == "!=" => {
+                Some((a == b) == (op == "=="))
+            }
+            _ => None,
+        };
+        result.map(ExprValue::Bool).ok_or_else(|| {
+            format!(
+                "Cannot compare {} and {} with '{}'",
+                left.type_name(),
+                right.type_name(),
+                op
+            )
+        })
+    }
+
+    fn parse_additive(&mut self) -> Result<ExprValue, String> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = match self.tokens.get(self.pos) {
+                Some(ExprToken::Op(op @ ("+" | "-"))) => *op,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let lhs = left.require_present()?;
+            let rhs = self.parse_multiplicative()?.require_present()?;
+            left = match (op, lhs, rhs) {
+                ("+", ExprValue::Num(a), ExprValue::Num(b)) => ExprValue::Num(a + b),
+                ("-", ExprValue::Num(a), ExprValue::Num(b)) => ExprValue::Num(a - b),
+                // `+` concatenates when either side is a string
+                ("+", ExprValue::Str(a), b) => ExprValue::Str(format!("{}{}", a, render_value(&b))),
+                ("+", a, ExprValue::Str(b)) => ExprValue::Str(format!("{}{}", render_value(&a), b)),
+                (op, a, b) => {
+                    return Err(format!(
+                        "Cannot apply '{}' to {} and {}",
+                        op,
+                        a.type_name(),
+                        b.type_name()
+                    ))
+                }
+            };
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<ExprValue, String> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let op = match self.tokens.get(self.pos) {
+                Some(ExprToken::Op(op @ ("*" | "/" | "%"))) => *op,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let lhs = left.require_present()?;
+            let rhs = self.parse_unary()?.require_present()?;
+            let (ExprValue::Num(a), ExprValue::Num(b)) = (&lhs, &rhs) else {
+                return Err(format!(
+                    "Cannot apply '{}' to {} and {}",
+                    op,
+                    lhs.type_name(),
+                    rhs.type_name()
+                ));
+            };
+            if (op == "/" || op == "%") && *b == 0.0 {
+                return Err("Division by zero".to_string());
+            }
+            left = ExprValue::Num(match op {
+                "*" => a * b,
+                "/" => a / b,
+                _ => a % b,
+            });
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<ExprValue, String> {
+        if self.eat_op("-") {
+            return match self.parse_unary()?.require_present()? {
+                ExprValue::Num(n) => Ok(ExprValue::Num(-n)),
+                other => Err(format!("Cannot negate {}", other.type_name())),
+            };
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<ExprValue, String> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(ExprToken::Num(n)) => {
+                self.pos += 1;
+                Ok(ExprValue::Num(n))
+            }
+            Some(ExprToken::Str(text)) => {
+                self.pos += 1;
+                Ok(ExprValue::Str(text))
+            }
+            Some(ExprToken::Ident(name)) => {
+                self.pos += 1;
+                Ok(match name.as_str() {
+                    "true" => ExprValue::Bool(true),
+                    "false" => ExprValue::Bool(false),
+                    _ => match self.vars.get(&name) {
+                        Some(value) => value
+                            .parse::<f64>()
+                            .map(ExprValue::Num)
+                            .unwrap_or_else(|_| ExprValue::Str(value.clone())),
+                        None => ExprValue::Missing(name),
+                    },
+                })
+            }
+            Some(ExprToken::Op("(")) => {
+                self.pos += 1;
+                let value = self.parse_pipe()?;
+                self.expect_op(")")?;
+                Ok(value)
+            }
+            _ => Err("Expected a value".to_string()),
+        }
+    }
+}
+
+/// Evaluate one comparison operator against an ordering
+fn compare(op: &str, ordering: Option<std::cmp::Ordering>) -> Option<bool> {
+    use std::cmp::Ordering::*;
+    let ordering = ordering?;
+    Some(match op {
+        "==" => ordering == Equal,
+        "!=" => ordering != Equal,
+        "<" => ordering == Less,
+        "<=" => ordering != Greater,
+        ">" => ordering == Greater,
+        _ => ordering != Less,
+    })
+}
+
+/// Render an expression value as substitution text
+fn render_value(value: &ExprValue) -> String {
+    match value {
+        ExprValue::Num(n) => render_number(*n),
+        ExprValue::Str(text) => text.clone(),
+        ExprValue::Bool(b) => b.to_string(),
+        ExprValue::Missing(name) => format!("${{{}}}", name),
+    }
+}
+
+/// Validate that batch input arrays have matching lengths
+///
+/// Counts entries via `RawValue` so the payloads themselves are not
+/// deserialized before the lengths are known to agree. Malformed JSON is
+/// left for the full parse to report.
+#[inline]
+pub(crate) fn check_batch_lengths(formulas_json: &str, vars_json: &str) -> Result<(), CookError> {
+    type RawEntries<'a> = Vec<&'a serde_json::value::RawValue>;
+
+    let (Ok(formulas), Ok(vars)) = (
+        serde_json::from_str::<RawEntries>(formulas_json),
+        serde_json::from_str::<RawEntries>(vars_json),
+    ) else {
+        return Ok(());
+    };
+
+    if formulas.len() != vars.len() {
+        return Err(CookError::BatchLengthMismatch {
+            formulas: formulas.len(),
+            vars: vars.len(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Internal function to cook a formula
+///
+/// # Optimizations
+/// - Pre-computes all variable patterns once
+/// - Uses SmallVec for stack allocation when possible
+/// - Single-pass substitution per field
+#[inline]
+fn cook_formula_internal(formula: &Formula, vars: &FxHashMap<String, String>) -> CookedFormula {
+    cook_formula_internal_delim(formula, vars, "${", "}")
+}
+
+/// Cook with the configured expression delimiters
+#[inline]
+fn cook_formula_internal_delim(
+    formula: &Formula,
+    vars: &FxHashMap<String, String>,
+    open: &str,
+    close: &str,
+) -> CookedFormula {
+    let (mut cooked, elapsed_ms) =
+        gastown_shared::timing::measure(|| cook_formula_untimed(formula, vars, open, close));
+
+    // Sub-microsecond cooks round up to 1 so the field is always non-zero
+    cooked.cook_duration_us = ((elapsed_ms * 1000.0) as u64).max(1);
+    cooked
+}
+
+/// Pre-compute `{{name}}` patterns for one vars map
+fn build_var_patterns(vars: &FxHashMap<String, String>) -> SmallBuffer<VarPattern, 16> {
+    vars.iter()
+        .map(|(key, value)| VarPattern {
+            pattern: format!("{{{{{}}}}}", key),
+            value: value.clone(),
+        })
+        .collect()
+}
+
+/// Substitute `{{name}}` tokens then expand template expressions in one
+/// text field, counting replaced tokens for the cook diagnostics
+fn apply_substitutions(
+    text: &str,
+    patterns: &[VarPattern],
+    vars: &FxHashMap<String, String>,
+    substitution_count: &mut u32,
+    open: &str,
+    close: &str,
+) -> String {
+    let substituted = if patterns.is_empty() || !text.contains("{{") {
+        text.to_string()
+    } else {
+        let mut result = text.to_string();
+        for pat in patterns {
+            let occurrences = result.matches(&pat.pattern).count();
+            if occurrences > 0 {
+                *substitution_count += occurrences as u32;
+                result = result.replace(&pat.pattern, &pat.value);
+            }
+        }
+        result
+    };
+
+    // Expressions run after token substitution, so a `{{name}}` inside
+    // an expression is already resolved
+    if substituted.contains(open) {
+        substitute_expressions_delim(&substituted, vars, open, close)
+    } else {
+        substituted
+    }
+}
+
+/// Cook a formula without recording timing
+#[inline]
+fn cook_formula_untimed(
+    formula: &Formula,
+    vars: &FxHashMap<String, String>,
+    open: &str,
+    close: &str,
+) -> CookedFormula {
+    // Secret vars never reach the cooked output: substitution and the
+    // echoed cooked_vars both see the mask
+    let masked;
+    let vars = match mask_secret_vars(formula, vars) {
+        Some(m) => {
+            masked = m;
+            &masked
+        }
+        None => vars,
+    };
+
+    // Pre-compute variable patterns for efficient substitution; the
+    // count feeds the substitution diagnostics
+    let patterns = build_var_patterns(vars);
+    let mut substitution_count: u32 = 0;
+
+    // `foreach` steps expand before anything else so `when` filtering
+    // and substitution see the concrete instances
+    let expanded_steps = expand_foreach_steps(formula, vars);
+
+    // Cook steps with pre-allocated capacity; steps whose `when`
+    // condition evaluates false are dropped from the cooked output
+    let cooked_steps: Vec<Step> = if expanded_steps.is_empty() {
+        Vec::new()
+    } else {
+        expanded_steps.iter()
+            .filter(|step| when_passes(step.when.as_deref(), vars))
+            .map(|step| {
+                Step {
+                    id: step.id.clone(),
+                    title: apply_substitutions(
+                        &step.title, &patterns, vars, &mut substitution_count, open, close,
+                    ),
+                    description: apply_substitutions(
+                        &step.description, &patterns, vars, &mut substitution_count, open, close,
+                    ),
+                    needs: step.needs.clone(),
+                    duration: step.duration,
+                    requires: step.requires.clone(),
+                    when: step.when.clone(),
+                    foreach: step.foreach.clone(),
+                }
+            }).collect()
+    };
+
+    // Cook legs with the same `when` filtering as steps; a leg with var
+    // overrides substitutes from the global vars with its own layered on
+    let mut cooked_legs: Vec<Leg> = Vec::with_capacity(formula.legs.len());
+    for leg in &formula.legs {
+        if !when_passes(leg.when.as_deref(), vars) {
+            continue;
+        }
+        let overridden: Option<(FxHashMap<String, String>, SmallBuffer<VarPattern, 16>)> =
+            if leg.vars.is_empty() {
+                None
+            } else {
+                let mut merged = vars.clone();
+                for (name, value) in &leg.vars {
+                    merged.insert(name.clone(), value.clone());
+                }
+                let merged_patterns = build_var_patterns(&merged);
+                Some((merged, merged_patterns))
+            };
+        let (leg_vars, leg_patterns) = match &overridden {
+            Some((merged, merged_patterns)) => (merged, merged_patterns),
+            None => (vars, &patterns),
+        };
+        let mut sub = |text: &str| {
+            apply_substitutions(
+                text,
+                leg_patterns,
+                leg_vars,
+                &mut substitution_count,
+                open,
+                close,
+            )
+        };
+        cooked_legs.push(Leg {
+            id: leg.id.clone(),
+            title: sub(&leg.title),
+            focus: sub(&leg.focus),
+            description: sub(&leg.description),
+            agent: leg.agent.clone(),
+            order: leg.order,
+            when: leg.when.clone(),
+            vars: leg.vars.clone(),
+        });
+    }
+
+    // Create cooked formula
+    let cooked_formula = Formula {
+        name: apply_substitutions(
+            &formula.name, &patterns, vars, &mut substitution_count, open, close,
+        ),
+        description: apply_substitutions(
+            &formula.description, &patterns, vars, &mut substitution_count, open, close,
+        ),
+        formula_type: formula.formula_type.clone(),
+        version: formula.version,
+        legs: cooked_legs,
+        synthesis: formula.synthesis.clone(),
+        steps: cooked_steps,
+        vars: formula.vars.clone(),
+    };
+
+    // Convert vars to standard HashMap for serialization
+    let cooked_vars: std::collections::HashMap<String, String> = vars
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+
+    let unresolved_count = count_unresolved_tokens(&cooked_formula);
+
+    let mut warnings = Vec::new();
+    if unresolved_count > 0 {
+        warnings.push(unresolved_tokens_warning(&cooked_formula));
+    }
+
+    CookedFormula {
+        typed_vars: typed_vars_for(formula, vars),
+        formula: cooked_formula,
+        cooked_at: chrono_lite_now(),
+        cooked_vars,
+        original_name: formula.name.clone(),
+        cook_duration_us: 0, // Set by cook_formula_internal
+        var_provenance: std::collections::HashMap::new(),
+        substitution_count,
+        unresolved_count,
+        formula_url: None,
+        cooked_by: None,
+        warnings,
+    }
+}
+
+/// One text field pre-split into literal runs and `{{name}}` placeholders
+#[derive(Debug, Clone)]
+enum TemplateSegment {
+    Literal(String),
+    Placeholder(String),
+}
+
+/// A pre-scanned text field; rendering is a single pass over the
+/// segments instead of one scan per var pattern
+#[derive(Debug, Clone, Default)]
+struct CompiledText {
+    segments: Vec<TemplateSegment>,
+}
+
+/// Scan a text field once, splitting it into literals and placeholders
+fn compile_text(text: &str) -> CompiledText {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            // A well-formed placeholder has no stray braces inside
+            Some(end) if !after[..end].contains(['{', '}']) => {
+                literal.push_str(&rest[..start]);
+                if !literal.is_empty() {
+                    segments.push(TemplateSegment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(TemplateSegment::Placeholder(after[..end].to_string()));
+                rest = &after[end + 2..];
+            }
+            // Not a placeholder; emit one char and rescan so nested
+            // braces like `{{{x}}}` still find the inner token
+            _ => {
+                literal.push_str(&rest[..start + 1]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        segments.push(TemplateSegment::Literal(literal));
+    }
+    CompiledText { segments }
+}
+
+impl CompiledText {
+    /// Render with one var set; placeholders missing from `vars` are
+    /// emitted back as literal `{{name}}` tokens, matching the lenient
+    /// cook path
+    fn render(
+        &self,
+        vars: &FxHashMap<String, String>,
+        substitution_count: &mut u32,
+        open: &str,
+        close: &str,
+    ) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                TemplateSegment::Literal(text) => out.push_str(text),
+                TemplateSegment::Placeholder(name) => match vars.get(name) {
+                    Some(value) => {
+                        *substitution_count += 1;
+                        out.push_str(value);
+                    }
+                    None => {
+                        out.push_str("{{");
+                        out.push_str(name);
+                        out.push_str("}}");
+                    }
+                },
+            }
+        }
+
+        if out.contains(open) {
+            substitute_expressions_delim(&out, vars, open, close)
+        } else {
+            out
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CompiledStepText {
+    title: CompiledText,
+    description: CompiledText,
+}
+
+#[derive(Debug, Clone)]
+struct CompiledLegText {
+    title: CompiledText,
+    focus: CompiledText,
+    description: CompiledText,
+}
+
+/// A formula whose template placeholders were scanned once up front
+///
+/// Cooking the same formula with many var sets re-parses nothing: each
+/// cook renders the pre-split segments in a single pass. Formulas with
+/// `foreach` steps fall back to the general path, since their expansion
+/// depends on the supplied vars.
+#[derive(Debug, Clone)]
+pub struct CompiledTemplate {
+    formula: Formula,
+    name: CompiledText,
+    description: CompiledText,
+    steps: Vec<CompiledStepText>,
+    legs: Vec<CompiledLegText>,
+    has_foreach: bool,
+}
+
+impl CompiledTemplate {
+    /// Scan every substitutable field of `formula` once
+    pub fn compile(formula: Formula) -> Self {
+        let steps = formula
+            .steps
+            .iter()
+            .map(|step| CompiledStepText {
+                title: compile_text(&step.title),
+                description: compile_text(&step.description),
+            })
+            .collect();
+        let legs = formula
+            .legs
+            .iter()
+            .map(|leg| CompiledLegText {
+                title: compile_text(&leg.title),
+                focus: compile_text(&leg.focus),
+                description: compile_text(&leg.description),
+            })
+            .collect();
+
+        CompiledTemplate {
+            name: compile_text(&formula.name),
+            description: compile_text(&formula.description),
+            steps,
+            legs,
+            has_foreach: formula.steps.iter().any(|step| step.foreach.is_some()),
+            formula,
+        }
+    }
+
+    /// Cook with one var set, recording timing like `cook_formula`
+    pub fn cook(&self, vars: &FxHashMap<String, String>) -> CookedFormula {
+        let (mut cooked, elapsed_ms) =
+            gastown_shared::timing::measure(|| self.cook_untimed(vars));
+        cooked.cook_duration_us = ((elapsed_ms * 1000.0) as u64).max(1);
+        cooked
+    }
+
+    fn cook_untimed(&self, vars: &FxHashMap<String, String>) -> CookedFormula {
+        let open = default_expr_open();
+        let close = default_expr_close();
+
+        // Same secret masking as the general cook path
+        let masked;
+        let vars = match mask_secret_vars(&self.formula, vars) {
+            Some(m) => {
+                masked = m;
+                &masked
+            }
+            None => vars,
+        };
+
+        // `foreach` expansion produces steps whose text depends on the
+        // vars themselves, so nothing useful was pre-compiled for them
+        if self.has_foreach {
+            return cook_formula_untimed(&self.formula, vars, &open, &close);
+        }
+
+        let mut substitution_count: u32 = 0;
+
+        let cooked_steps: Vec<Step> = self
+            .formula
+            .steps
+            .iter()
+            .zip(&self.steps)
+            .filter(|(step, _)| when_passes(step.when.as_deref(), vars))
+            .map(|(step, compiled)| Step {
+                id: step.id.clone(),
+                title: compiled.title.render(vars, &mut substitution_count, &open, &close),
+                description: compiled
+                    .description
+                    .render(vars, &mut substitution_count, &open, &close),
+                needs: step.needs.clone(),
+                duration: step.duration,
+                requires: step.requires.clone(),
+                when: step.when.clone(),
+                foreach: step.foreach.clone(),
+            })
+            .collect();
+
+        let mut cooked_legs: Vec<Leg> = Vec::with_capacity(self.formula.legs.len());
+        for (leg, compiled) in self.formula.legs.iter().zip(&self.legs) {
+            if !when_passes(leg.when.as_deref(), vars) {
+                continue;
+            }
+            let overridden: Option<FxHashMap<String, String>> = if leg.vars.is_empty() {
+                None
+            } else {
+                let mut merged = vars.clone();
+                for (name, value) in &leg.vars {
+                    merged.insert(name.clone(), value.clone());
+                }
+                Some(merged)
+            };
+            let leg_vars = overridden.as_ref().unwrap_or(vars);
+            cooked_legs.push(Leg {
+                id: leg.id.clone(),
+                title: compiled.title.render(leg_vars, &mut substitution_count, &open, &close),
+                focus: compiled.focus.render(leg_vars, &mut substitution_count, &open, &close),
+                description: compiled
+                    .description
+                    .render(leg_vars, &mut substitution_count, &open, &close),
+                agent: leg.agent.clone(),
+                order: leg.order,
+                when: leg.when.clone(),
+                vars: leg.vars.clone(),
+            });
+        }
+
+        let cooked_formula = Formula {
+            name: self.name.render(vars, &mut substitution_count, &open, &close),
+            description: self
+                .description
+                .render(vars, &mut substitution_count, &open, &close),
+            formula_type: self.formula.formula_type.clone(),
+            version: self.formula.version,
+            legs: cooked_legs,
+            synthesis: self.formula.synthesis.clone(),
+            steps: cooked_steps,
+            vars: self.formula.vars.clone(),
+        };
+
+        let cooked_vars: std::collections::HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        let unresolved_count = count_unresolved_tokens(&cooked_formula);
+
+        let mut warnings = Vec::new();
+        if unresolved_count > 0 {
+            warnings.push(unresolved_tokens_warning(&cooked_formula));
+        }
+
+        CookedFormula {
+            typed_vars: typed_vars_for(&self.formula, vars),
+            formula: cooked_formula,
+            cooked_at: chrono_lite_now(),
+            cooked_vars,
+            original_name: self.formula.name.clone(),
+            cook_duration_us: 0, // Set by cook()
+            var_provenance: std::collections::HashMap::new(),
+            substitution_count,
+            unresolved_count,
+            formula_url: None,
+            cooked_by: None,
+            warnings,
+        }
+    }
+}
+
+/// Build a `CompiledTemplate` from a formula JSON string
+#[inline]
+pub fn compile_formula_impl(formula_json: &str) -> Result<CompiledTemplate, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+    Ok(CompiledTemplate::compile(formula))
+}
+
+/// Cook a pre-compiled formula with one var set
+///
+/// Runs the same strict validation pipeline as `cook_formula` against
+/// the stored formula before rendering.
+#[inline]
+pub fn compiled_cook_impl(
+    template: &CompiledTemplate,
+    vars_json: &str,
+) -> Result<JsValue, JsValue> {
+    let supplied = parse_vars_json(vars_json).map_err(|e| JsValue::from_str(&e))?;
+
+    let vars = resolve_var_references(&template.formula, &supplied)?;
+
+    check_var_value_sizes(&vars, default_max_var_value_bytes())?;
+    validate_var_bounds(&template.formula, &vars)?;
+    validate_var_constraints(&template.formula, &vars)?;
+    validate_var_types(&template.formula, &vars)?;
+    validate_expressions(&template.formula, &vars)?;
+    validate_foreach(&template.formula, &vars)?;
+
+    let mut cooked = template.cook(&vars);
+    cooked.var_provenance = var_provenance_for(&template.formula, &supplied, None);
+
+    serde_wasm_bindgen::to_value(&cooked)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Build the lenient-mode warning for `{{...}}` tokens left after a cook
+///
+/// Lists up to 5 unique token names in first-appearance order; the count
+/// is the number of unique names, not raw occurrences.
+fn unresolved_tokens_warning(formula: &Formula) -> String {
+    let mut names: Vec<String> = Vec::new();
+    for site in list_substitution_sites(formula) {
+        if !names.contains(&site.var_name) {
+            names.push(site.var_name);
+        }
+    }
+
+    let listed: Vec<String> = names
+        .iter()
+        .take(5)
+        .map(|name| format!("{{{{{}}}}}", name))
+        .collect();
+
+    format!(
+        "Formula contains {} unresolved template token{}: {}",
+        names.len(),
+        if names.len() == 1 { "" } else { "s" },
+        listed.join(", ")
+    )
+}
+
+/// Count `{{...}}` tokens remaining in the templated fields of a formula
+///
+/// A non-zero count after cooking means vars were referenced but not
+/// supplied ("why didn't my vars get substituted?" diagnostics)
+#[inline]
+fn count_unresolved_tokens(formula: &Formula) -> u32 {
+    let count = |text: &str| text.matches("{{").count() as u32;
+
+    let mut total = count(&formula.name) + count(&formula.description);
+    for step in &formula.steps {
+        total += count(&step.title) + count(&step.description);
+    }
+    for leg in &formula.legs {
+        total += count(&leg.title) + count(&leg.focus) + count(&leg.description);
+    }
+    total
+}
+
+/// One placeholder in a substitution pre-flight report
+#[derive(Debug, Clone, Serialize)]
+pub struct SubstitutionReportEntry {
+    /// Var name inside the braces
+    pub var_name: String,
+    /// Dot-path of the field containing the token (e.g. `steps[0].title`)
+    pub field_path: String,
+    /// Byte offset of the opening `{{` within the field value
+    pub byte_offset: usize,
+    /// Where the value comes from: `vars`, `default`, or `unresolved`
+    pub source: String,
+    /// The value that would be substituted, when resolved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+/// Pre-flight substitution report for a cook
+///
+/// Produced without cooking: the formula is only scanned, never mutated.
+#[derive(Debug, Clone, Serialize)]
+pub struct SubstitutionReport {
+    /// Every placeholder, in field order, with its resolution
+    pub sites: Vec<SubstitutionReportEntry>,
+    /// Unique var names no source can satisfy, in first-appearance order
+    pub unresolved: Vec<String>,
+    /// Supplied vars that match no placeholder, name-sorted
+    pub unused_vars: Vec<String>,
+}
+
+/// Report how a cook would resolve every placeholder, without cooking
+///
+/// For each `{{...}}` site the report records which source satisfies it
+/// (a supplied var or the formula default) and the value that would be
+/// substituted; sites with no source are listed as unresolved. Supplied
+/// vars that match no placeholder are reported too, since they usually
+/// indicate a typo on the caller's side.
+pub fn substitution_report_internal(
+    formula: &Formula,
+    vars: &FxHashMap<String, String>,
+) -> SubstitutionReport {
+    let mut sites = Vec::new();
+    let mut unresolved: Vec<String> = Vec::new();
+    let mut used: std::collections::HashSet<&str> = std::collections::HashSet::new();
+
+    for site in list_substitution_sites(formula) {
+        let (source, value) = if let Some(value) = vars.get(&site.var_name) {
+            ("vars", Some(value.clone()))
+        } else if let Some(default) = formula
+            .vars
+            .get(&site.var_name)
+            .and_then(|var| var.default.clone())
+        {
+            ("default", Some(default))
+        } else {
+            ("unresolved", None)
+        };
+
+        // Diagnostics mask secret values the same way cooked output does
+        let value = match (&value, formula.vars.get(&site.var_name)) {
+            (Some(_), Some(var)) if var.secret => Some(SECRET_MASK.to_string()),
+            _ => value,
+        };
+
+        if source == "unresolved" && !unresolved.contains(&site.var_name) {
+            unresolved.push(site.var_name.clone());
+        }
+        sites.push(SubstitutionReportEntry {
+            var_name: site.var_name,
+            field_path: site.field_path,
+            byte_offset: site.byte_offset,
+            source: source.to_string(),
+            value,
+        });
+    }
+
+    for site in &sites {
+        used.insert(&site.var_name);
+    }
+    let mut unused_vars: Vec<String> = vars
+        .keys()
+        .filter(|name| !used.contains(name.as_str()))
+        .cloned()
+        .collect();
+    unused_vars.sort();
+
+    SubstitutionReport {
+        sites,
+        unresolved,
+        unused_vars,
+    }
+}
+
+/// WASM wrapper for `substitution_report_internal`
+#[inline]
+pub fn substitution_report_impl(formula_json: &str, vars_json: &str) -> Result<JsValue, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+    let vars = parse_vars_json(vars_json).map_err(|e| JsValue::from_str(&e))?;
+
+    let report = substitution_report_internal(&formula, &vars);
+    serde_wasm_bindgen::to_value(&report)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Static variable analysis of a formula
+///
+/// Produced from the formula alone (no supplied vars), for editors and
+/// CI checks.
+#[derive(Debug, Clone, Serialize)]
+pub struct VarAnalysis {
+    /// Vars referenced in templated fields but not declared under
+    /// `[vars]`, in first-appearance order
+    pub undeclared: Vec<String>,
+    /// Declared vars never referenced by any templated field, name-sorted
+    pub unused: Vec<String>,
+    /// Vars marked `required = true` that also lack a default, name-sorted
+    ///
+    /// These make every cook fail unless a value is supplied, which is
+    /// worth surfacing before the formula ships.
+    pub required_without_default: Vec<String>,
+}
+
+/// Analyze a formula's variable declarations against its references
+pub fn analyze_vars_internal(formula: &Formula) -> VarAnalysis {
+    let mut undeclared: Vec<String> = Vec::new();
+    let mut referenced: std::collections::HashSet<String> = std::collections::HashSet::new();
+
+    for site in list_substitution_sites(formula) {
+        if !formula.vars.contains_key(&site.var_name) && !undeclared.contains(&site.var_name) {
+            undeclared.push(site.var_name.clone());
+        }
+        referenced.insert(site.var_name);
+    }
+
+    let mut unused: Vec<String> = formula
+        .vars
+        .keys()
+        .filter(|name| !referenced.contains(*name))
+        .cloned()
+        .collect();
+    unused.sort();
+
+    let mut required_without_default: Vec<String> = formula
+        .vars
+        .iter()
+        .filter(|(_, var)| var.required && var.default.is_none())
+        .map(|(name, _)| name.clone())
+        .collect();
+    required_without_default.sort();
+
+    VarAnalysis {
+        undeclared,
+        unused,
+        required_without_default,
+    }
+}
+
+/// WASM wrapper for `analyze_vars_internal`
+#[inline]
+pub fn analyze_vars_impl(formula_json: &str) -> Result<JsValue, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+
+    serde_wasm_bindgen::to_value(&analyze_vars_internal(&formula))
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// One `{{VAR_NAME}}` token found in a formula field
+///
+/// Consumed by the dry-run cook, lint checks, and editor integrations
+/// that highlight substitution sites.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SubstitutionSite {
+    /// Var name inside the braces
+    pub var_name: String,
+    /// Dot-path of the field containing the token (e.g. `steps[0].title`)
+    pub field_path: String,
+    /// Byte offset of the opening `{{` within the field value
+    pub byte_offset: usize,
+}
+
+/// Scan all templated fields of a formula for `{{...}}` tokens
+///
+/// Sites are returned in field order, then by offset within each field,
+/// so output is deterministic.
+pub fn list_substitution_sites(formula: &Formula) -> Vec<SubstitutionSite> {
+    let mut sites = Vec::new();
+
+    scan_field(&formula.name, "name", &mut sites);
+    scan_field(&formula.description, "description", &mut sites);
+    for (i, step) in formula.steps.iter().enumerate() {
+        scan_field(&step.title, &format!("steps[{}].title", i), &mut sites);
+        scan_field(
+            &step.description,
+            &format!("steps[{}].description", i),
+            &mut sites,
+        );
+    }
+    for (i, leg) in formula.legs.iter().enumerate() {
+        scan_field(&leg.title, &format!("legs[{}].title", i), &mut sites);
+        scan_field(&leg.focus, &format!("legs[{}].focus", i), &mut sites);
+        scan_field(
+            &leg.description,
+            &format!("legs[{}].description", i),
+            &mut sites,
+        );
+    }
+
+    sites
+}
+
+/// Collect every `{{VAR}}` token in one field value
+fn scan_field(text: &str, field_path: &str, sites: &mut Vec<SubstitutionSite>) {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i + 1 < bytes.len() {
+        if bytes[i] == b'{' && bytes[i + 1] == b'{' {
+            if let Some(end) = find_pattern_end(&bytes[i..]) {
+                sites.push(SubstitutionSite {
+                    var_name: text[i + 2..i + end].trim().to_string(),
+                    field_path: field_path.to_string(),
+                    byte_offset: i,
+                });
+                i += end + 2;
+                continue;
+            }
+        }
+        i += 1;
+    }
+}
+
+/// Optimized multi-pattern substitution
+///
+/// Uses a single pass through the string to find all patterns
+#[inline]
+fn substitute_all(text: &str, patterns: &[VarPattern]) -> String {
+    if patterns.is_empty() || !text.contains("{{") {
+        return text.to_string();
+    }
+
+    // For small number of patterns, sequential replacement is faster
+    if patterns.len() <= 4 {
+        let mut result = text.to_string();
+        for pat in patterns {
+            if result.contains(&pat.pattern) {
+                result = result.replace(&pat.pattern, &pat.value);
+            }
+        }
+        return result;
+    }
+
+    // For larger pattern sets, build result incrementally
+    let mut result = String::with_capacity(text.len() * 2);
+    let mut last_end = 0;
+    let bytes = text.as_bytes();
+    let len = bytes.len();
+
+    let mut i = 0;
+    while i < len {
+        if i + 2 < len && bytes[i] == b'{' && bytes[i + 1] == b'{' {
+            // Found potential pattern start
             if let Some(end) = find_pattern_end(&bytes[i..]) {
                 let pattern_str = &text[i..i + end + 2];
 
-                // Check if this matches any of our patterns
-                if let Some(pat) = patterns.iter().find(|p| p.pattern == pattern_str) {
-                    result.push_str(&text[last_end..i]);
-                    result.push_str(&pat.value);
-                    last_end = i + end + 2;
-                    i = last_end;
-                    continue;
-                }
-            }
-        }
-        i += 1;
+                // Check if this matches any of our patterns
+                if let Some(pat) = patterns.iter().find(|p| p.pattern == pattern_str) {
+                    result.push_str(&text[last_end..i]);
+                    result.push_str(&pat.value);
+                    last_end = i + end + 2;
+                    i = last_end;
+                    continue;
+                }
+            }
+        }
+        i += 1;
+    }
+
+    result.push_str(&text[last_end..]);
+    result
+}
+
+/// Find the end of a pattern (closing }})
+#[inline(always)]
+fn find_pattern_end(bytes: &[u8]) -> Option<usize> {
+    let len = bytes.len();
+    let mut i = 2; // Skip opening {{
+
+    while i + 1 < len {
+        if bytes[i] == b'}' && bytes[i + 1] == b'}' {
+            return Some(i);
+        }
+        i += 1;
+    }
+
+    None
+}
+
+/// Simple timestamp without chrono dependency
+#[inline(always)]
+fn chrono_lite_now() -> String {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let date = js_sys::Date::new_0();
+        date.to_iso_string().into()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        "2026-01-24T00:00:00Z".to_string()
+    }
+}
+
+/// Cook a single field (for external use)
+#[inline]
+pub fn cook_field(text: &str, vars: &FxHashMap<String, String>) -> String {
+    if vars.is_empty() || !text.contains("{{") {
+        return text.to_string();
+    }
+
+    let mut result = text.to_string();
+    for (key, value) in vars {
+        let pattern = format!("{{{{{}}}}}", key);
+        if result.contains(&pattern) {
+            result = result.replace(&pattern, value);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FormulaType;
+
+    #[test]
+    fn test_cook_formula() {
+        let formula = Formula {
+            name: "{{project}}-workflow".to_string(),
+            description: "Workflow for {{project}}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![
+                Step {
+                    id: "step1".to_string(),
+                    title: "Build {{project}}".to_string(),
+                    description: "Build the {{project}} project".to_string(),
+                    needs: vec![],
+                    duration: None,
+                    requires: vec![],
+                    when: None,
+                    foreach: None,
+                },
+            ],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let mut vars = FxHashMap::default();
+        vars.insert("project".to_string(), "auth-service".to_string());
+
+        let cooked = cook_formula_internal(&formula, &vars);
+
+        assert_eq!(cooked.formula.name, "auth-service-workflow");
+        assert_eq!(cooked.formula.description, "Workflow for auth-service");
+        assert_eq!(cooked.formula.steps[0].title, "Build auth-service");
+        assert!(cooked.cook_duration_us > 0);
+    }
+
+    #[test]
+    fn test_cook_batch() {
+        let formulas = vec![
+            Formula {
+                name: "{{name}}-1".to_string(),
+                description: "First {{name}}".to_string(),
+                formula_type: FormulaType::Workflow,
+                version: 1,
+                legs: vec![],
+                synthesis: None,
+                steps: vec![],
+                vars: std::collections::HashMap::new(),
+            },
+            Formula {
+                name: "{{name}}-2".to_string(),
+                description: "Second {{name}}".to_string(),
+                formula_type: FormulaType::Workflow,
+                version: 1,
+                legs: vec![],
+                synthesis: None,
+                steps: vec![],
+                vars: std::collections::HashMap::new(),
+            },
+        ];
+
+        let formulas_json = serde_json::to_string(&formulas).unwrap();
+
+        let vars_list: Vec<FxHashMap<String, String>> = vec![
+            {
+                let mut m = FxHashMap::default();
+                m.insert("name".to_string(), "alpha".to_string());
+                m
+            },
+            {
+                let mut m = FxHashMap::default();
+                m.insert("name".to_string(), "beta".to_string());
+                m
+            },
+        ];
+        let vars_json = serde_json::to_string(&vars_list).unwrap();
+
+        let result = cook_batch_impl(&formulas_json, &vars_json).unwrap();
+        let cooked: Vec<CookedFormula> = serde_json::from_str(&result).unwrap();
+
+        assert_eq!(cooked.len(), 2);
+        assert_eq!(cooked[0].formula.name, "alpha-1");
+        assert_eq!(cooked[1].formula.name, "beta-2");
+    }
+
+    #[test]
+    fn test_cook_formula_multi() {
+        let formula = Formula {
+            name: "deploy-{{env}}".to_string(),
+            description: "Deploy to {{env}}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+        let formula_json = serde_json::to_string(&formula).unwrap();
+        let var_sets_json = r#"[{"env": "dev"}, {"env": "staging"}, {"env": "prod"}]"#;
+
+        let result = cook_formula_multi_impl(&formula_json, var_sets_json).unwrap();
+        let cooked: Vec<CookedFormula> = serde_json::from_str(&result).unwrap();
+
+        assert_eq!(cooked.len(), 3);
+        assert_eq!(cooked[0].formula.name, "deploy-dev");
+        assert_eq!(cooked[1].formula.name, "deploy-staging");
+        assert_eq!(cooked[2].formula.name, "deploy-prod");
+    }
+
+    #[test]
+    fn test_cook_formula_project() {
+        let formula = Formula {
+            name: "project-test".to_string(),
+            description: "Projection".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![Step {
+                id: "step1".to_string(),
+                title: "Do {{thing}}".to_string(),
+                description: "d".to_string(),
+                needs: vec![],
+                duration: None,
+                requires: vec![],
+                when: None,
+                foreach: None,
+            }],
+            vars: std::collections::HashMap::new(),
+        };
+        let formula_json = serde_json::to_string(&formula).unwrap();
+
+        let result = cook_formula_project_impl(
+            &formula_json,
+            r#"{"thing": "work"}"#,
+            r#"["formula.steps", "cooked_vars"]"#,
+        )
+        .unwrap();
+        let projected: serde_json::Value = serde_json::from_str(&result).unwrap();
+
+        assert_eq!(projected["steps"][0]["title"], "Do work");
+        assert_eq!(projected["cooked_vars"][0]["name"], "thing");
+        assert_eq!(projected["cooked_vars"][0]["value"], "work");
+        // Everything else is omitted
+        assert!(projected.get("description").is_none());
+        assert!(projected.get("cooked_at").is_none());
+    }
+
+    #[test]
+    fn test_cook_batch_bytes_ndjson() {
+        let formulas = vec![
+            Formula {
+                name: "{{name}}-1".to_string(),
+                description: "First {{name}}".to_string(),
+                formula_type: FormulaType::Workflow,
+                version: 1,
+                legs: vec![],
+                synthesis: None,
+                steps: vec![],
+                vars: std::collections::HashMap::new(),
+            },
+            Formula {
+                name: "{{name}}-2".to_string(),
+                description: "Second {{name}}".to_string(),
+                formula_type: FormulaType::Workflow,
+                version: 1,
+                legs: vec![],
+                synthesis: None,
+                steps: vec![],
+                vars: std::collections::HashMap::new(),
+            },
+        ];
+
+        let mut formulas_ndjson = Vec::new();
+        for formula in &formulas {
+            formulas_ndjson.extend_from_slice(serde_json::to_string(formula).unwrap().as_bytes());
+            formulas_ndjson.push(b'\n');
+        }
+        let vars_ndjson = b"{\"name\":\"alpha\"}\n{\"name\":\"beta\"}\n";
+
+        let out = cook_batch_bytes_internal(&formulas_ndjson, vars_ndjson).unwrap();
+
+        let cooked: Vec<CookedFormula> = decode_ndjson(&out, "Cooked").unwrap();
+        assert_eq!(cooked.len(), 2);
+        assert_eq!(cooked[0].formula.name, "alpha-1");
+        assert_eq!(cooked[1].formula.name, "beta-2");
+
+        // Length mismatch reported before any cooking
+        let err = cook_batch_bytes_internal(&formulas_ndjson, b"{\"name\":\"alpha\"}\n").unwrap_err();
+        assert!(err.contains("Batch length mismatch"), "got: {}", err);
+    }
+
+    #[test]
+    fn test_cook_batch_length_mismatch() {
+        let formulas_json = r#"[{"a":1},{"b":2},{"c":3}]"#;
+        let vars_json = r#"[{},{}]"#;
+
+        let err = check_batch_lengths(formulas_json, vars_json).unwrap_err();
+        assert_eq!(err, CookError::BatchLengthMismatch { formulas: 3, vars: 2 });
+    }
+
+    #[test]
+    fn test_var_provenance() {
+        let mut formula = typed_formula(crate::VarType::String, Some("dev"));
+        formula.vars.get_mut("value").unwrap().name = "value".to_string();
+
+        let mut supplied = FxHashMap::default();
+        supplied.insert("region".to_string(), "eu-west".to_string());
+
+        let mut env = std::collections::HashMap::new();
+        env.insert("value".to_string(), "from-env".to_string());
+        env.insert("host".to_string(), "ci-runner".to_string());
+
+        let provenance = var_provenance_for(&formula, &supplied, Some(&env));
+
+        // Supplied beats env beats default; the declared default is
+        // recorded wherever one exists
+        assert_eq!(provenance["region"].source, "supplied");
+        assert_eq!(provenance["value"].source, "env");
+        assert_eq!(provenance["value"].default.as_deref(), Some("dev"));
+        assert_eq!(provenance["host"].source, "env");
+
+        // Without env, the declared default is the source
+        let provenance = var_provenance_for(&formula, &FxHashMap::default(), None);
+        assert_eq!(provenance["value"].source, "default");
+
+        // The cooked JSON carries the map
+        let formula_json = serde_json::to_string(&formula).unwrap();
+        let cooked = cook_formula_impl(&formula_json, r#"{"region": "eu-west"}"#).unwrap();
+        let parsed: CookedFormula = serde_json::from_str(&cooked).unwrap();
+        assert_eq!(parsed.var_provenance["region"].source, "supplied");
+        assert_eq!(parsed.var_provenance["value"].source, "default");
+    }
+
+    #[test]
+    fn test_secret_var_masking() {
+        let mut vars_decl = std::collections::HashMap::new();
+        vars_decl.insert(
+            "api_key".to_string(),
+            crate::Var {
+                name: "api_key".to_string(),
+                secret: true,
+                ..Default::default()
+            },
+        );
+        let formula = Formula {
+            name: "deploy".to_string(),
+            description: "Deploy with {{api_key}}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: vars_decl,
+        };
+
+        let mut vars = FxHashMap::default();
+        vars.insert("api_key".to_string(), "sk-live-12345".to_string());
+
+        // Cooked output substitutes and echoes the mask, never the value
+        let cooked = cook_formula_internal(&formula, &vars);
+        assert_eq!(cooked.formula.description, "Deploy with ***");
+        assert_eq!(cooked.cooked_vars["api_key"], "***");
+        let json = serde_json::to_string(&cooked).unwrap();
+        assert!(!json.contains("sk-live-12345"));
+
+        // The compiled path masks identically
+        let compiled = CompiledTemplate::compile(formula.clone()).cook(&vars);
+        assert_eq!(compiled.formula.description, "Deploy with ***");
+
+        // Diagnostics mask too
+        let report = substitution_report_internal(&formula, &vars);
+        assert_eq!(report.sites[0].value.as_deref(), Some("***"));
+
+        // The companion payload carries the real value
+        let formula_json = serde_json::to_string(&formula).unwrap();
+        let secrets =
+            cook_formula_secrets_impl(&formula_json, r#"{"api_key": "sk-live-12345"}"#).unwrap();
+        assert_eq!(secrets, r#"{"api_key":"sk-live-12345"}"#);
+    }
+
+    #[test]
+    fn test_cook_cache_memoizes_identical_inputs() {
+        cook_cache_clear();
+
+        let formula_json = serde_json::to_string(&Formula {
+            name: "{{name}}-cached".to_string(),
+            description: "d".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        })
+        .unwrap();
+        let vars_json = r#"{"name": "alpha"}"#;
+
+        let first = cook_formula_impl(&formula_json, vars_json).unwrap();
+        let second = cook_formula_impl(&formula_json, vars_json).unwrap();
+        // Byte-identical including cooked_at: the second cook never ran
+        assert_eq!(first, second);
+
+        // Different vars miss the cache and cook fresh
+        let other = cook_formula_impl(&formula_json, r#"{"name": "beta"}"#).unwrap();
+        assert!(other.contains("beta-cached"));
+
+        let stats = cook_cache_stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.size, 2);
+
+        cook_cache_clear();
+        let stats = cook_cache_stats();
+        assert_eq!((stats.size, stats.hits, stats.misses), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_cook_batch_chunked_progress() {
+        let formulas: Vec<Formula> = (0..5)
+            .map(|i| Formula {
+                name: format!("{{{{name}}}}-{}", i),
+                description: "d".to_string(),
+                formula_type: FormulaType::Workflow,
+                version: 1,
+                legs: vec![],
+                synthesis: None,
+                steps: vec![],
+                vars: std::collections::HashMap::new(),
+            })
+            .collect();
+        let vars_list: Vec<FxHashMap<String, String>> = (0..5)
+            .map(|_| {
+                let mut m = FxHashMap::default();
+                m.insert("name".to_string(), "f".to_string());
+                m
+            })
+            .collect();
+
+        let formulas_json = serde_json::to_string(&formulas).unwrap();
+        let vars_json = serde_json::to_string(&vars_list).unwrap();
+
+        let mut chunk_lens = Vec::new();
+        let mut percents = Vec::new();
+        let mut emit = |chunk_json: &str, percent: f64| {
+            let chunk: Vec<CookedFormula> = serde_json::from_str(chunk_json).unwrap();
+            chunk_lens.push(chunk.len());
+            percents.push(percent);
+            Ok(())
+        };
+
+        let total = cook_batch_chunked_impl(&formulas_json, &vars_json, 2, &mut emit).unwrap();
+
+        assert_eq!(total, 5);
+        assert_eq!(chunk_lens, vec![2, 2, 1]);
+        assert_eq!(percents, vec![40.0, 80.0, 100.0]);
+    }
+
+    #[test]
+    fn test_check_batch_lengths_matching() {
+        assert!(check_batch_lengths(r#"[{},{}]"#, r#"[{},{}]"#).is_ok());
+        // Malformed JSON is deferred to the full parse
+        assert!(check_batch_lengths("not json", "[]").is_ok());
+    }
+
+    #[test]
+    fn test_no_substitution_needed() {
+        let formula = Formula {
+            name: "static-workflow".to_string(),
+            description: "No variables here".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let vars = FxHashMap::default();
+        let cooked = cook_formula_internal(&formula, &vars);
+
+        assert_eq!(cooked.formula.name, "static-workflow");
+        assert_eq!(cooked.formula.description, "No variables here");
+    }
+
+    #[test]
+    fn test_cook_invariants_hold_on_recook() {
+        let formula = Formula {
+            name: "static-name".to_string(),
+            description: "Original {{detail}}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let mut vars = FxHashMap::default();
+        vars.insert("detail".to_string(), "description".to_string());
+        let original = cook_formula_internal(&formula, &vars);
+
+        // Extract the inner formula, modify a non-name field, re-cook
+        let mut inner = original.formula.clone();
+        inner.description = "Changed description".to_string();
+        let recooked = cook_formula_internal(&inner, &vars);
+
+        assert!(assert_cook_invariants(&original, &recooked).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "Cook invariant violated")]
+    fn test_cook_invariants_detect_name_change() {
+        let formula = Formula {
+            name: "first-name".to_string(),
+            description: "d".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+        let vars = FxHashMap::default();
+        let original = cook_formula_internal(&formula, &vars);
+
+        let mut renamed = formula.clone();
+        renamed.name = "second-name".to_string();
+        let recooked = cook_formula_internal(&renamed, &vars);
+
+        // Debug builds panic; release builds would return Err
+        let _ = assert_cook_invariants(&original, &recooked);
+    }
+
+    fn bounded_formula(min: Option<f64>, max: Option<f64>, exclusive: bool) -> Formula {
+        let mut vars = std::collections::HashMap::new();
+        vars.insert(
+            "replicas".to_string(),
+            crate::Var {
+                name: "replicas".to_string(),
+                min: if exclusive { None } else { min },
+                max: if exclusive { None } else { max },
+                min_exclusive: if exclusive { min } else { None },
+                max_exclusive: if exclusive { max } else { None },
+                ..Default::default()
+            },
+        );
+        Formula {
+            name: "bounded".to_string(),
+            description: "d".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars,
+        }
+    }
+
+    #[test]
+    fn test_cook_vars_object_and_array_forms() {
+        let formula = Formula {
+            name: "{{project}}-workflow".to_string(),
+            description: "Deploy to {{env}}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+        let formula_json = serde_json::to_string(&formula).unwrap();
+
+        let object_form = r#"{"project": "auth", "env": "prod"}"#;
+        let array_form =
+            r#"[{"name": "project", "value": "auth"}, {"name": "env", "value": "prod"}]"#;
+
+        let from_object: CookedFormula =
+            serde_json::from_str(&cook_formula_impl(&formula_json, object_form).unwrap()).unwrap();
+        let from_array: CookedFormula =
+            serde_json::from_str(&cook_formula_impl(&formula_json, array_form).unwrap()).unwrap();
+
+        assert_eq!(from_object.formula.name, "auth-workflow");
+        assert_eq!(from_array.formula.name, from_object.formula.name);
+        assert_eq!(from_array.formula.description, from_object.formula.description);
+        assert_eq!(from_array.cooked_vars, from_object.cooked_vars);
+    }
+
+    #[test]
+    fn test_cook_formula_var_whitelist() {
+        let formula = Formula {
+            name: "whitelist".to_string(),
+            description: "Deploy {{app}} to {{env}} with {{secret}}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let mut vars = FxHashMap::default();
+        vars.insert("app".to_string(), "auth".to_string());
+        vars.insert("env".to_string(), "prod".to_string());
+        vars.insert("secret".to_string(), "hunter2".to_string());
+
+        let options = CookOptions {
+            var_whitelist: Some(vec!["app".to_string(), "env".to_string()]),
+            ..Default::default()
+        };
+        let cooked = cook_formula_with_options(&formula, &vars, &options);
+
+        // Whitelisted vars substitute; the supplied-but-unlisted var stays
+        // verbatim
+        assert_eq!(
+            cooked.formula.description,
+            "Deploy auth to prod with {{secret}}"
+        );
+        assert_eq!(cooked.substitution_count, 2);
+        assert_eq!(cooked.unresolved_count, 1);
+
+        // No whitelist keeps the default substitute-everything behavior
+        let cooked_all = cook_formula_with_options(&formula, &vars, &CookOptions::default());
+        assert_eq!(
+            cooked_all.formula.description,
+            "Deploy auth to prod with hunter2"
+        );
+    }
+
+    #[test]
+    fn test_sparse_cooked_vars() {
+        let formula = Formula {
+            name: "sparse".to_string(),
+            description: "Deploy {{service}} to {{env}}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let mut vars = FxHashMap::default();
+        vars.insert("service".to_string(), "auth".to_string());
+        vars.insert("env".to_string(), "prod".to_string());
+        vars.insert("unreferenced".to_string(), "leaky".to_string());
+
+        // Default mode records every supplied var
+        let cooked = cook_formula_with_options(&formula, &vars, &CookOptions::default());
+        assert!(cooked.cooked_vars.contains_key("unreferenced"));
+
+        // Sparse mode drops the var that was never substituted
+        let options = CookOptions {
+            sparse_cooked_vars: true,
+            ..Default::default()
+        };
+        let cooked = cook_formula_with_options(&formula, &vars, &options);
+        assert!(cooked.cooked_vars.contains_key("service"));
+        assert!(cooked.cooked_vars.contains_key("env"));
+        assert!(!cooked.cooked_vars.contains_key("unreferenced"));
+    }
+
+    #[test]
+    fn test_cook_formula_dry_run_is_deterministic() {
+        let formula_json = r#"{
+            "formula": "preview",
+            "description": "Deploy {{env}}",
+            "type": "workflow",
+            "version": 1
+        }"#;
+
+        let first = cook_formula_dry_run_impl(formula_json, r#"{"env": "prod"}"#).unwrap();
+        let second = cook_formula_dry_run_impl(formula_json, r#"{"env": "prod"}"#).unwrap();
+        assert_eq!(first, second);
+
+        let cooked: CookedFormula = serde_json::from_str(&first).unwrap();
+        assert_eq!(cooked.cooked_at, "DRY_RUN");
+        assert_eq!(cooked.cook_duration_us, 0);
+        assert_eq!(cooked.formula.description, "Deploy prod");
+    }
+
+    #[test]
+    fn test_cook_formula_accepts_null_vars() {
+        // `"vars": null` is a valid JSON spelling of "no vars"
+        let formula_json = r#"{
+            "formula": "null-vars",
+            "description": "Deploy {{env}}",
+            "type": "workflow",
+            "version": 1,
+            "vars": null
+        }"#;
+
+        let result = cook_formula_impl(formula_json, r#"{"env": "prod"}"#).unwrap();
+        let cooked: CookedFormula = serde_json::from_str(&result).unwrap();
+
+        assert_eq!(cooked.formula.description, "Deploy prod");
+        assert!(cooked.formula.vars.is_empty());
+    }
+
+    #[test]
+    fn test_parse_vars_json_last_value_wins() {
+        let vars =
+            parse_vars_json(r#"[{"name": "env", "value": "dev"}, {"name": "env", "value": "prod"}]"#)
+                .unwrap();
+        assert_eq!(vars.get("env").map(String::as_str), Some("prod"));
+    }
+
+    #[test]
+    fn test_cooked_vars_serialize_as_sorted_array() {
+        let formula = Formula {
+            name: "sorted".to_string(),
+            description: "{{zeta}} {{alpha}}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let mut vars = FxHashMap::default();
+        vars.insert("zeta".to_string(), "z".to_string());
+        vars.insert("alpha".to_string(), "a".to_string());
+        let cooked = cook_formula_internal(&formula, &vars);
+
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&cooked).unwrap()).unwrap();
+        let entries = json["cooked_vars"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["name"], "alpha");
+        assert_eq!(entries[0]["value"], "a");
+        assert_eq!(entries[1]["name"], "zeta");
+        assert_eq!(entries[1]["value"], "z");
+    }
+
+    #[test]
+    fn test_upgrade_cooked_formula_format() {
+        // Legacy artifacts stored cooked_vars as an object
+        let legacy = r#"{
+            "formula": "legacy",
+            "description": "old format",
+            "type": "workflow",
+            "version": 1,
+            "cooked_at": "2026-01-24T00:00:00Z",
+            "cooked_vars": {"env": "prod", "app": "auth"},
+            "original_name": "legacy"
+        }"#;
+
+        let upgraded = upgrade_cooked_formula_format_impl(legacy).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&upgraded).unwrap();
+        let entries = json["cooked_vars"].as_array().unwrap();
+        assert_eq!(entries[0]["name"], "app");
+        assert_eq!(entries[1]["name"], "env");
+
+        // Upgrading an already-upgraded artifact is a no-op
+        let again = upgrade_cooked_formula_format_impl(&upgraded).unwrap();
+        assert_eq!(again, upgraded);
+    }
+
+    #[test]
+    fn test_substitution_counters() {
+        let formula = Formula {
+            name: "{{project}}-workflow".to_string(),
+            description: "Build {{project}} for {{env}}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let mut vars = FxHashMap::default();
+        vars.insert("project".to_string(), "auth-service".to_string());
+        let cooked = cook_formula_internal(&formula, &vars);
+
+        // {{project}} replaced twice, {{env}} never supplied
+        assert_eq!(cooked.substitution_count, 2);
+        assert_eq!(cooked.unresolved_count, 1);
+
+        // Fully resolved cook has no leftovers
+        vars.insert("env".to_string(), "prod".to_string());
+        let cooked = cook_formula_internal(&formula, &vars);
+        assert_eq!(cooked.substitution_count, 3);
+        assert_eq!(cooked.unresolved_count, 0);
+    }
+
+    #[test]
+    fn test_unresolved_tokens_warning() {
+        let formula = Formula {
+            name: "lenient".to_string(),
+            description: "Build {{project}} for {{env}} ({{project}})".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+
+        // Empty vars: every token is definitely unresolved
+        let cooked = cook_formula_internal(&formula, &FxHashMap::default());
+        assert_eq!(
+            cooked.warnings,
+            vec!["Formula contains 2 unresolved template tokens: {{project}}, {{env}}"]
+        );
+
+        // Fully resolved cook emits no warning
+        let mut vars = FxHashMap::default();
+        vars.insert("project".to_string(), "auth-service".to_string());
+        vars.insert("env".to_string(), "prod".to_string());
+        let cooked = cook_formula_internal(&formula, &vars);
+        assert!(cooked.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_unresolved_tokens_warning_caps_at_five() {
+        let formula = Formula {
+            name: "many-tokens".to_string(),
+            description: "{{a}} {{b}} {{c}} {{d}} {{e}} {{f}} {{g}}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let cooked = cook_formula_internal(&formula, &FxHashMap::default());
+        assert_eq!(
+            cooked.warnings,
+            vec!["Formula contains 7 unresolved template tokens: {{a}}, {{b}}, {{c}}, {{d}}, {{e}}"]
+        );
+    }
+
+    #[test]
+    fn test_validate_var_bounds() {
+        let formula = bounded_formula(Some(1.0), Some(10.0), false);
+
+        let mut vars = FxHashMap::default();
+        vars.insert("replicas".to_string(), "5".to_string());
+        assert!(validate_var_bounds(&formula, &vars).is_ok());
+
+        // Inclusive bounds admit the endpoints
+        vars.insert("replicas".to_string(), "10".to_string());
+        assert!(validate_var_bounds(&formula, &vars).is_ok());
+
+        vars.insert("replicas".to_string(), "0".to_string());
+        let err = validate_var_bounds(&formula, &vars).unwrap_err();
+        assert_eq!(
+            err,
+            CookError::ValidationFailed {
+                var_name: "replicas".to_string(),
+                constraint: "min".to_string(),
+                expected: ">= 1".to_string(),
+                actual: "0".to_string(),
+            }
+        );
+
+        // Non-numeric value for a bounded var
+        vars.insert("replicas".to_string(), "lots".to_string());
+        let err = validate_var_bounds(&formula, &vars).unwrap_err();
+        assert!(matches!(
+            err,
+            CookError::ValidationFailed { ref constraint, .. } if constraint == "numeric"
+        ));
+    }
+
+    #[test]
+    fn test_validate_var_bounds_exclusive() {
+        let formula = bounded_formula(Some(0.0), Some(1.0), true);
+
+        let mut vars = FxHashMap::default();
+        vars.insert("replicas".to_string(), "0.5".to_string());
+        assert!(validate_var_bounds(&formula, &vars).is_ok());
+
+        // Exclusive bounds reject the endpoints
+        vars.insert("replicas".to_string(), "1".to_string());
+        let err = validate_var_bounds(&formula, &vars).unwrap_err();
+        assert!(matches!(
+            err,
+            CookError::ValidationFailed { ref constraint, .. } if constraint == "max_exclusive"
+        ));
+    }
+
+    fn typed_formula(var_type: crate::VarType, default: Option<&str>) -> Formula {
+        let mut vars = std::collections::HashMap::new();
+        vars.insert(
+            "value".to_string(),
+            crate::Var {
+                name: "value".to_string(),
+                var_type,
+                default: default.map(str::to_string),
+                ..Default::default()
+            },
+        );
+        Formula {
+            name: "typed".to_string(),
+            description: "d".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars,
+        }
+    }
+
+    #[test]
+    fn test_validate_var_constraints() {
+        let mut formula = typed_formula(crate::VarType::String, None);
+        formula.vars.insert(
+            "env".to_string(),
+            crate::Var {
+                name: "env".to_string(),
+                enum_values: Some(vec!["dev".to_string(), "prod".to_string()]),
+                ..Default::default()
+            },
+        );
+        formula.vars.insert(
+            "slug".to_string(),
+            crate::Var {
+                name: "slug".to_string(),
+                pattern: Some("[a-z][a-z0-9-]*".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let mut vars = FxHashMap::default();
+        vars.insert("env".to_string(), "prod".to_string());
+        vars.insert("slug".to_string(), "my-app".to_string());
+        assert!(validate_var_constraints(&formula, &vars).is_ok());
+
+        // All violations are collected, not just the first
+        vars.insert("env".to_string(), "staging".to_string());
+        vars.insert("slug".to_string(), "My App".to_string());
+        let err = validate_var_constraints(&formula, &vars).unwrap_err();
+        let CookError::ConstraintViolations { violations } = err else {
+            panic!("expected constraint violations");
+        };
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].var_name, "env");
+        assert_eq!(violations[0].constraint, "enum");
+        assert_eq!(violations[1].var_name, "slug");
+        assert_eq!(violations[1].constraint, "pattern");
+
+        // The pattern matches the whole value, not a substring
+        let mut vars = FxHashMap::default();
+        vars.insert("env".to_string(), "dev".to_string());
+        vars.insert("slug".to_string(), "app!".to_string());
+        assert!(validate_var_constraints(&formula, &vars).is_err());
+    }
+
+    #[test]
+    fn test_validate_var_length_and_format() {
+        let mut formula = typed_formula(crate::VarType::String, None);
+        formula.vars.insert(
+            "owner".to_string(),
+            crate::Var {
+                name: "owner".to_string(),
+                format: Some("email".to_string()),
+                ..Default::default()
+            },
+        );
+        formula.vars.insert(
+            "tag".to_string(),
+            crate::Var {
+                name: "tag".to_string(),
+                min_length: Some(3),
+                max_length: Some(8),
+                ..Default::default()
+            },
+        );
+
+        let mut vars = FxHashMap::default();
+        vars.insert("owner".to_string(), "ops@example.com".to_string());
+        vars.insert("tag".to_string(), "release".to_string());
+        assert!(validate_var_constraints(&formula, &vars).is_ok());
+
+        // Length and format violations are collected together
+        vars.insert("owner".to_string(), "not-an-email".to_string());
+        vars.insert("tag".to_string(), "an-overly-long-tag".to_string());
+        let err = validate_var_constraints(&formula, &vars).unwrap_err();
+        let CookError::ConstraintViolations { violations } = err else {
+            panic!("expected constraint violations");
+        };
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].var_name, "owner");
+        assert_eq!(violations[0].constraint, "format");
+        assert_eq!(violations[1].var_name, "tag");
+        assert_eq!(violations[1].constraint, "max_length");
+
+        // An unknown format name is itself reported
+        formula.vars.get_mut("owner").unwrap().format = Some("phone".to_string());
+        vars.insert("owner".to_string(), "555-0100".to_string());
+        vars.insert("tag".to_string(), "rel".to_string());
+        let err = validate_var_constraints(&formula, &vars).unwrap_err();
+        let CookError::ConstraintViolations { violations } = err else {
+            panic!("expected constraint violations");
+        };
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].expected.contains("email"));
+    }
+
+    #[test]
+    fn test_validate_var_types() {
+        let formula = typed_formula(crate::VarType::Int, None);
+
+        let mut vars = FxHashMap::default();
+        vars.insert("value".to_string(), "5".to_string());
+        assert!(validate_var_types(&formula, &vars).is_ok());
+
+        vars.insert("value".to_string(), "lots".to_string());
+        let err = validate_var_types(&formula, &vars).unwrap_err();
+        assert_eq!(
+            err,
+            CookError::ValidationFailed {
+                var_name: "value".to_string(),
+                constraint: "type".to_string(),
+                expected: "an integer".to_string(),
+                actual: "lots".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_coerce_var_value_rules() {
+        use crate::VarType;
+        use serde_json::json;
+
+        assert_eq!(coerce_var_value(VarType::Int, " 42 ").unwrap(), json!(42));
+        assert_eq!(coerce_var_value(VarType::Float, "2.5").unwrap(), json!(2.5));
+        assert_eq!(coerce_var_value(VarType::Bool, "TRUE").unwrap(), json!(true));
+        assert_eq!(coerce_var_value(VarType::Bool, "0").unwrap(), json!(false));
+        assert_eq!(
+            coerce_var_value(VarType::List, "[1, 2]").unwrap(),
+            json!([1, 2])
+        );
+        assert_eq!(
+            coerce_var_value(VarType::List, "a, b").unwrap(),
+            json!(["a", "b"])
+        );
+        assert_eq!(
+            coerce_var_value(VarType::Map, r#"{"k": 1}"#).unwrap(),
+            json!({"k": 1})
+        );
+        assert!(coerce_var_value(VarType::Map, "not json").is_err());
+    }
+
+    #[test]
+    fn test_cook_records_typed_vars() {
+        let formula = typed_formula(crate::VarType::Int, Some("3"));
+
+        // Supplied value wins over the default and lands as a native int
+        let mut vars = FxHashMap::default();
+        vars.insert("value".to_string(), "7".to_string());
+        let cooked = cook_formula_internal(&formula, &vars);
+        assert_eq!(cooked.typed_vars["value"], serde_json::json!(7));
+        // The text form stays in cooked_vars for backward compatibility
+        assert_eq!(cooked.cooked_vars["value"], "7");
+
+        // The default is typed too when no value is supplied
+        let cooked = cook_formula_internal(&formula, &FxHashMap::default());
+        assert_eq!(cooked.typed_vars["value"], serde_json::json!(3));
+
+        // String-typed vars stay out of typed_vars
+        let formula = typed_formula(crate::VarType::String, Some("text"));
+        let cooked = cook_formula_internal(&formula, &FxHashMap::default());
+        assert!(cooked.typed_vars.is_empty());
+    }
+
+    #[test]
+    fn test_eval_expression_arithmetic_and_precedence() {
+        let mut vars = FxHashMap::default();
+        vars.insert("count".to_string(), "4".to_string());
+
+        assert_eq!(eval_expression("count * 2", &vars).unwrap(), "8");
+        assert_eq!(eval_expression("1 + 2 * 3", &vars).unwrap(), "7");
+        assert_eq!(eval_expression("(1 + 2) * 3", &vars).unwrap(), "9");
+        assert_eq!(eval_expression("-count + 10", &vars).unwrap(), "6");
+        assert_eq!(eval_expression("7 % 4", &vars).unwrap(), "3");
+        assert_eq!(eval_expression("5 / 2", &vars).unwrap(), "2.5");
+    }
+
+    #[test]
+    fn test_eval_expression_comparison_and_ternary() {
+        let mut vars = FxHashMap::default();
+        vars.insert("count".to_string(), "4".to_string());
+        vars.insert("env".to_string(), "prod".to_string());
+
+        assert_eq!(eval_expression("count > 3", &vars).unwrap(), "true");
+        assert_eq!(eval_expression("env == 'prod'", &vars).unwrap(), "true");
+        assert_eq!(
+            eval_expression("count > 3 ? 'big' : 'small'", &vars).unwrap(),
+            "big"
+        );
+        assert_eq!(
+            eval_expression("env != 'prod' ? 1 : count * 10", &vars).unwrap(),
+            "40"
+        );
+    }
+
+    #[test]
+    fn test_eval_expression_default_filter() {
+        let mut vars = FxHashMap::default();
+        vars.insert("name".to_string(), "edge".to_string());
+
+        assert_eq!(
+            eval_expression("name | default(\"core\")", &vars).unwrap(),
+            "edge"
+        );
+        assert_eq!(
+            eval_expression("missing | default(\"core\")", &vars).unwrap(),
+            "core"
+        );
+        assert_eq!(eval_expression("name | upper()", &vars).unwrap(), "EDGE");
+
+        // A missing var without a default is an error, not empty output
+        let err = eval_expression("missing + 1", &vars).unwrap_err();
+        assert_eq!(err, "Unknown variable 'missing'");
+    }
+
+    #[test]
+    fn test_eval_expression_filters() {
+        let mut vars = FxHashMap::default();
+        vars.insert("name".to_string(), "Edge Region 1".to_string());
+        vars.insert("padded".to_string(), "  x  ".to_string());
+
+        assert_eq!(eval_expression("name|slug", &vars).unwrap(), "edge-region-1");
+        assert_eq!(eval_expression("padded | trim", &vars).unwrap(), "x");
+        assert_eq!(eval_expression("name | trim()", &vars).unwrap(), "Edge Region 1");
+        assert_eq!(
+            eval_expression("name | replace(' ', '_')", &vars).unwrap(),
+            "Edge_Region_1"
+        );
+        assert_eq!(
+            eval_expression("name | slug | upper", &vars).unwrap(),
+            "EDGE-REGION-1"
+        );
+
+        let err = eval_expression("name | replace('a')", &vars).unwrap_err();
+        assert_eq!(err, "Filter 'replace' expects 2 argument(s), got 1");
+    }
+
+    #[test]
+    fn test_eval_expression_errors() {
+        let vars = FxHashMap::default();
+
+        assert_eq!(
+            eval_expression("1 / 0", &vars).unwrap_err(),
+            "Division by zero"
+        );
+        assert!(eval_expression("1 +", &vars).is_err());
+        assert!(eval_expression("'a' * 2", &vars).is_err());
+        assert!(eval_expression("1 ? 2 : 3", &vars).is_err());
+        assert!(eval_expression("x | shout()", &vars).is_err());
+    }
+
+    #[test]
+    fn test_cook_expands_expressions() {
+        let formula = Formula {
+            name: "expr-test".to_string(),
+            description: "Deploy ${count * 2} replicas to ${env | default(\"staging\")}"
+                .to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let mut vars = FxHashMap::default();
+        vars.insert("count".to_string(), "3".to_string());
+        let cooked = cook_formula_internal(&formula, &vars);
+        assert_eq!(
+            cooked.formula.description,
+            "Deploy 6 replicas to staging"
+        );
+    }
+
+    #[test]
+    fn test_validate_expressions_rejects_invalid() {
+        let formula = Formula {
+            name: "expr-test".to_string(),
+            description: "${1 +}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let err = validate_expressions(&formula, &FxHashMap::default()).unwrap_err();
+        assert!(matches!(
+            err,
+            CookError::InvalidExpression { ref expression, .. } if expression == "1 +"
+        ));
+    }
+
+    #[test]
+    fn test_cook_drops_steps_failing_when_condition() {
+        let step = |id: &str, when: Option<&str>| Step {
+            id: id.to_string(),
+            title: id.to_string(),
+            description: "d".to_string(),
+            needs: vec![],
+            duration: None,
+            requires: vec![],
+            when: when.map(str::to_string),
+            foreach: None,
+        };
+        let formula = Formula {
+            name: "conditional".to_string(),
+            description: "d".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![
+                step("always", None),
+                step("prod-only", Some("env == 'prod'")),
+                step("big-only", Some("count > 5")),
+            ],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let mut vars = FxHashMap::default();
+        vars.insert("env".to_string(), "prod".to_string());
+        vars.insert("count".to_string(), "3".to_string());
+        let cooked = cook_formula_internal(&formula, &vars);
+        let ids: Vec<&str> = cooked.formula.steps.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["always", "prod-only"]);
+
+        vars.insert("env".to_string(), "dev".to_string());
+        vars.insert("count".to_string(), "10".to_string());
+        let cooked = cook_formula_internal(&formula, &vars);
+        let ids: Vec<&str> = cooked.formula.steps.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["always", "big-only"]);
+    }
+
+    #[test]
+    fn test_validate_expressions_rejects_non_boolean_when() {
+        let formula = Formula {
+            name: "conditional".to_string(),
+            description: "d".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![Leg {
+                id: "leg".to_string(),
+                title: "Leg".to_string(),
+                focus: "focus".to_string(),
+                description: "d".to_string(),
+                agent: None,
+                order: None,
+                when: Some("1 + 1".to_string()),
+                vars: std::collections::HashMap::new(),
+            }],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let err = validate_expressions(&formula, &FxHashMap::default()).unwrap_err();
+        assert!(matches!(
+            err,
+            CookError::InvalidExpression { ref message, .. }
+                if message.contains("must evaluate to a boolean")
+        ));
+    }
+
+    #[test]
+    fn test_foreach_expands_steps_and_rewrites_needs() {
+        let mut formula_vars = std::collections::HashMap::new();
+        formula_vars.insert(
+            "targets".to_string(),
+            crate::Var {
+                name: "targets".to_string(),
+                var_type: crate::VarType::List,
+                default: Some("linux, macos".to_string()),
+                ..Default::default()
+            },
+        );
+        let formula = Formula {
+            name: "matrix".to_string(),
+            description: "d".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![
+                Step {
+                    id: "build".to_string(),
+                    title: "Build for {{item}}".to_string(),
+                    description: "Build the {{item}} target".to_string(),
+                    needs: vec![],
+                    duration: None,
+                    requires: vec![],
+                    when: None,
+                    foreach: Some("targets".to_string()),
+                },
+                Step {
+                    id: "release".to_string(),
+                    title: "Release".to_string(),
+                    description: "d".to_string(),
+                    needs: vec!["build".to_string()],
+                    duration: None,
+                    requires: vec![],
+                    when: None,
+                    foreach: None,
+                },
+            ],
+            vars: formula_vars,
+        };
+
+        // The default list expands; a supplied value overrides it
+        let cooked = cook_formula_internal(&formula, &FxHashMap::default());
+        let ids: Vec<&str> = cooked.formula.steps.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["build-linux", "build-macos", "release"]);
+        assert_eq!(cooked.formula.steps[0].title, "Build for linux");
+        assert_eq!(
+            cooked.formula.steps[2].needs,
+            vec!["build-linux", "build-macos"]
+        );
+
+        let mut vars = FxHashMap::default();
+        vars.insert("targets".to_string(), r#"["windows"]"#.to_string());
+        let cooked = cook_formula_internal(&formula, &vars);
+        let ids: Vec<&str> = cooked.formula.steps.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["build-windows", "release"]);
     }
 
-    result.push_str(&text[last_end..]);
-    result
-}
+    #[test]
+    fn test_validate_foreach_requires_list() {
+        let formula = Formula {
+            name: "matrix".to_string(),
+            description: "d".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![Step {
+                id: "build".to_string(),
+                title: "Build".to_string(),
+                description: "d".to_string(),
+                needs: vec![],
+                duration: None,
+                requires: vec![],
+                when: None,
+                foreach: Some("targets".to_string()),
+            }],
+            vars: std::collections::HashMap::new(),
+        };
 
-/// Find the end of a pattern (closing }})
-#[inline(always)]
-fn find_pattern_end(bytes: &[u8]) -> Option<usize> {
-    let len = bytes.len();
-    let mut i = 2; // Skip opening {{
+        let err = validate_foreach(&formula, &FxHashMap::default()).unwrap_err();
+        assert_eq!(
+            err,
+            CookError::ValidationFailed {
+                var_name: "targets".to_string(),
+                constraint: "foreach".to_string(),
+                expected: "a list value".to_string(),
+                actual: "<missing>".to_string(),
+            }
+        );
+    }
 
-    while i + 1 < len {
-        if bytes[i] == b'}' && bytes[i + 1] == b'}' {
-            return Some(i);
-        }
-        i += 1;
+    #[test]
+    fn test_slugify_item() {
+        assert_eq!(slugify_item("us-east 1"), "us-east-1");
+        assert_eq!(slugify_item("Linux"), "linux");
+        assert_eq!(slugify_item("  a/b  "), "a-b");
     }
 
-    None
-}
+    #[test]
+    fn test_resolve_var_references() {
+        let mut formula = typed_formula(crate::VarType::String, None);
+        formula.vars.insert(
+            "project".to_string(),
+            crate::Var {
+                name: "project".to_string(),
+                default: Some("gastown".to_string()),
+                ..Default::default()
+            },
+        );
+        formula.vars.insert(
+            "worker".to_string(),
+            crate::Var {
+                name: "worker".to_string(),
+                default: Some("${project}-worker".to_string()),
+                ..Default::default()
+            },
+        );
 
-/// Simple timestamp without chrono dependency
-#[inline(always)]
-fn chrono_lite_now() -> String {
-    #[cfg(target_arch = "wasm32")]
-    {
-        let date = js_sys::Date::new_0();
-        date.to_iso_string().into()
-    }
-    #[cfg(not(target_arch = "wasm32"))]
-    {
-        "2026-01-24T00:00:00Z".to_string()
-    }
-}
+        // Defaults resolve transitively
+        let resolved = resolve_var_references(&formula, &FxHashMap::default()).unwrap();
+        assert_eq!(resolved["worker"], "gastown-worker");
 
-/// Cook a single field (for external use)
-#[inline]
-pub fn cook_field(text: &str, vars: &FxHashMap<String, String>) -> String {
-    if vars.is_empty() || !text.contains("{{") {
-        return text.to_string();
-    }
+        // A supplied value overrides the referenced default
+        let mut vars = FxHashMap::default();
+        vars.insert("project".to_string(), "edge".to_string());
+        let resolved = resolve_var_references(&formula, &vars).unwrap();
+        assert_eq!(resolved["worker"], "edge-worker");
 
-    let mut result = text.to_string();
-    for (key, value) in vars {
-        let pattern = format!("{{{{{}}}}}", key);
-        if result.contains(&pattern) {
-            result = result.replace(&pattern, value);
-        }
+        // Non-reference expressions are left for the evaluator
+        vars.insert("calc".to_string(), "${1 + 2}".to_string());
+        let resolved = resolve_var_references(&formula, &vars).unwrap();
+        assert_eq!(resolved["calc"], "${1 + 2}");
     }
-    result
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::FormulaType;
+    #[test]
+    fn test_resolve_var_references_detects_cycles() {
+        let mut vars = FxHashMap::default();
+        vars.insert("a".to_string(), "${b}".to_string());
+        vars.insert("b".to_string(), "${a}".to_string());
+
+        let formula = typed_formula(crate::VarType::String, None);
+        let err = resolve_var_references(&formula, &vars).unwrap_err();
+        let CookError::VarReferenceCycle { chain } = err else {
+            panic!("expected a cycle error, got {:?}", err);
+        };
+        // Either var can be visited first; the chain closes on itself
+        assert_eq!(chain.len(), 3);
+        assert_eq!(chain.first(), chain.last());
+    }
 
     #[test]
-    fn test_cook_formula() {
+    fn test_custom_expression_delimiters() {
         let formula = Formula {
-            name: "{{project}}-workflow".to_string(),
-            description: "Workflow for {{project}}".to_string(),
+            name: "shell".to_string(),
+            description: "Run for %{env | upper} with ${HOME} intact".to_string(),
             formula_type: FormulaType::Workflow,
             version: 1,
             legs: vec![],
             synthesis: None,
-            steps: vec![
-                Step {
-                    id: "step1".to_string(),
-                    title: "Build {{project}}".to_string(),
-                    description: "Build the {{project}} project".to_string(),
-                    needs: vec![],
-                    duration: None,
-                    requires: vec![],
-                },
-            ],
+            steps: vec![],
             vars: std::collections::HashMap::new(),
         };
 
         let mut vars = FxHashMap::default();
-        vars.insert("project".to_string(), "auth-service".to_string());
+        vars.insert("env".to_string(), "prod".to_string());
+        let options = CookOptions {
+            expr_open: "%{".to_string(),
+            expr_close: "}".to_string(),
+            ..Default::default()
+        };
+        let cooked = cook_formula_with_options(&formula, &vars, &options);
 
-        let cooked = cook_formula_internal(&formula, &vars);
+        // Shell syntax passes through untouched; %{...} cooks
+        assert_eq!(
+            cooked.formula.description,
+            "Run for PROD with ${HOME} intact"
+        );
+    }
 
-        assert_eq!(cooked.formula.name, "auth-service-workflow");
-        assert_eq!(cooked.formula.description, "Workflow for auth-service");
-        assert_eq!(cooked.formula.steps[0].title, "Build auth-service");
+    #[test]
+    fn test_escaped_expression_delimiter() {
+        let mut vars = FxHashMap::default();
+        vars.insert("env".to_string(), "prod".to_string());
+
+        // A doubled first character emits the delimiter literally
+        assert_eq!(
+            substitute_expressions_delim("cost is $${env} in ${env}", &vars, "${", "}"),
+            "cost is ${env} in prod"
+        );
+        assert_eq!(
+            substitute_expressions_delim("literal %%{x}, real %{env}", &vars, "%{", "}"),
+            "literal %{x}, real prod"
+        );
+
+        // Escaped delimiters are invisible to validation too
+        assert!(find_expressions_delim("$${not + an + expr}", "${", "}").is_empty());
     }
 
     #[test]
-    fn test_cook_batch() {
-        let formulas = vec![
-            Formula {
-                name: "{{name}}-1".to_string(),
-                description: "First {{name}}".to_string(),
-                formula_type: FormulaType::Workflow,
-                version: 1,
-                legs: vec![],
-                synthesis: None,
-                steps: vec![],
-                vars: std::collections::HashMap::new(),
+    fn test_layer_env_vars_precedence() {
+        let mut env = std::collections::HashMap::new();
+        env.insert("region".to_string(), "us-east".to_string());
+        env.insert("env".to_string(), "staging".to_string());
+
+        let mut vars = FxHashMap::default();
+        vars.insert("env".to_string(), "prod".to_string());
+
+        let layered = layer_env_vars(&vars, Some(&env));
+        // Provided vars win; env fills in the rest
+        assert_eq!(layered["env"], "prod");
+        assert_eq!(layered["region"], "us-east");
+
+        assert_eq!(layer_env_vars(&vars, None).len(), 1);
+    }
+
+    #[test]
+    fn test_cook_opts_layers_env_vars() {
+        let formula_json = serde_json::json!({
+            "formula": "env-test",
+            "description": "Deploy to {{region}} as {{env}}",
+            "type": "workflow"
+        })
+        .to_string();
+        let options_json = serde_json::json!({
+            "env": {"region": "us-east", "env": "staging"}
+        })
+        .to_string();
+
+        let cooked_json =
+            cook_formula_opts_impl(&formula_json, r#"{"env": "prod"}"#, &options_json).unwrap();
+        let cooked: CookedFormula = serde_json::from_str(&cooked_json).unwrap();
+        assert_eq!(cooked.formula.description, "Deploy to us-east as prod");
+    }
+
+    #[test]
+    fn test_analyze_vars() {
+        let mut formula_vars = std::collections::HashMap::new();
+        formula_vars.insert(
+            "region".to_string(),
+            crate::Var {
+                name: "region".to_string(),
+                default: Some("us-east".to_string()),
+                ..Default::default()
             },
-            Formula {
-                name: "{{name}}-2".to_string(),
-                description: "Second {{name}}".to_string(),
-                formula_type: FormulaType::Workflow,
-                version: 1,
-                legs: vec![],
-                synthesis: None,
-                steps: vec![],
-                vars: std::collections::HashMap::new(),
+        );
+        formula_vars.insert(
+            "orphan".to_string(),
+            crate::Var {
+                name: "orphan".to_string(),
+                ..Default::default()
             },
-        ];
+        );
+        formula_vars.insert(
+            "tenant".to_string(),
+            crate::Var {
+                name: "tenant".to_string(),
+                required: true,
+                ..Default::default()
+            },
+        );
+        let formula = Formula {
+            name: "analysis-test".to_string(),
+            description: "Deploy {{app}} to {{region}} for {{tenant}}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: formula_vars,
+        };
 
-        let formulas_json = serde_json::to_string(&formulas).unwrap();
+        let analysis = analyze_vars_internal(&formula);
+        assert_eq!(analysis.undeclared, vec!["app"]);
+        assert_eq!(analysis.unused, vec!["orphan"]);
+        assert_eq!(analysis.required_without_default, vec!["tenant"]);
+    }
 
-        let vars_list: Vec<FxHashMap<String, String>> = vec![
-            {
-                let mut m = FxHashMap::default();
-                m.insert("name".to_string(), "alpha".to_string());
-                m
-            },
-            {
-                let mut m = FxHashMap::default();
-                m.insert("name".to_string(), "beta".to_string());
-                m
+    #[test]
+    fn test_substitution_report() {
+        let mut formula_vars = std::collections::HashMap::new();
+        formula_vars.insert(
+            "region".to_string(),
+            crate::Var {
+                name: "region".to_string(),
+                default: Some("us-east".to_string()),
+                ..Default::default()
             },
-        ];
-        let vars_json = serde_json::to_string(&vars_list).unwrap();
+        );
+        let formula = Formula {
+            name: "report-test".to_string(),
+            description: "Deploy {{app}} to {{region}} for {{tenant}}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: formula_vars,
+        };
 
-        let result = cook_batch_impl(&formulas_json, &vars_json).unwrap();
-        let cooked: Vec<CookedFormula> = serde_json::from_str(&result).unwrap();
+        let mut vars = FxHashMap::default();
+        vars.insert("app".to_string(), "gateway".to_string());
+        vars.insert("typo".to_string(), "x".to_string());
 
-        assert_eq!(cooked.len(), 2);
-        assert_eq!(cooked[0].formula.name, "alpha-1");
-        assert_eq!(cooked[1].formula.name, "beta-2");
+        let report = substitution_report_internal(&formula, &vars);
+        assert_eq!(report.sites.len(), 3);
+        assert_eq!(report.sites[0].source, "vars");
+        assert_eq!(report.sites[0].value.as_deref(), Some("gateway"));
+        assert_eq!(report.sites[1].source, "default");
+        assert_eq!(report.sites[1].value.as_deref(), Some("us-east"));
+        assert_eq!(report.sites[2].source, "unresolved");
+        assert_eq!(report.unresolved, vec!["tenant"]);
+        assert_eq!(report.unused_vars, vec!["typo"]);
     }
 
     #[test]
-    fn test_no_substitution_needed() {
+    fn test_cook_formula_with_options() {
         let formula = Formula {
-            name: "static-workflow".to_string(),
-            description: "No variables here".to_string(),
+            name: "provenance-test".to_string(),
+            description: "d".to_string(),
             formula_type: FormulaType::Workflow,
             version: 1,
             legs: vec![],
@@ -351,11 +4566,229 @@ mod tests {
             vars: std::collections::HashMap::new(),
         };
 
-        let vars = FxHashMap::default();
+        let options = CookOptions {
+            source_url: Some("https://registry.example.com/provenance-test.toml".to_string()),
+            cooked_by: Some("deploy-agent".to_string()),
+            ..Default::default()
+        };
+        let cooked = cook_formula_with_options(&formula, &FxHashMap::default(), &options);
+
+        assert_eq!(
+            cooked.formula_url.as_deref(),
+            Some("https://registry.example.com/provenance-test.toml")
+        );
+        assert_eq!(cooked.cooked_by.as_deref(), Some("deploy-agent"));
+
+        // Provenance fields are omitted from JSON when absent
+        let plain = cook_formula_internal(&formula, &FxHashMap::default());
+        let json = serde_json::to_string(&plain).unwrap();
+        assert!(!json.contains("formula_url"));
+        assert!(!json.contains("cooked_by"));
+    }
+
+    #[test]
+    fn test_compiled_template_matches_general_path() {
+        let formula = Formula {
+            name: "{{project}} pipeline".to_string(),
+            description: "Deploy {{project}} to {{env}}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![Step {
+                id: "build".to_string(),
+                title: "Build {{project}}".to_string(),
+                description: "missing {{other}} stays".to_string(),
+                needs: vec![],
+                duration: None,
+                requires: vec![],
+                when: None,
+                foreach: None,
+            }],
+            vars: std::collections::HashMap::new(),
+        };
+        let template = CompiledTemplate::compile(formula.clone());
+
+        // Cook the same template with two different var sets
+        for (project, env) in [("api", "prod"), ("web", "staging")] {
+            let mut vars = FxHashMap::default();
+            vars.insert("project".to_string(), project.to_string());
+            vars.insert("env".to_string(), env.to_string());
+
+            let fast = template.cook(&vars);
+            let general = cook_formula_internal(&formula, &vars);
+
+            assert_eq!(fast.formula.name, general.formula.name);
+            assert_eq!(fast.formula.description, general.formula.description);
+            assert_eq!(fast.formula.steps[0].title, general.formula.steps[0].title);
+            assert_eq!(fast.formula.steps[0].title, format!("Build {}", project));
+            // Unknown placeholders survive identically on both paths
+            assert_eq!(fast.formula.steps[0].description, "missing {{other}} stays");
+            assert_eq!(fast.substitution_count, general.substitution_count);
+            assert_eq!(fast.unresolved_count, general.unresolved_count);
+        }
+    }
+
+    #[test]
+    fn test_compile_text_nested_braces() {
+        let mut vars = FxHashMap::default();
+        vars.insert("x".to_string(), "v".to_string());
+        let mut count = 0;
+        let rendered = compile_text("{{{x}}}").render(&vars, &mut count, "${", "}");
+        assert_eq!(rendered, "{v}");
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_per_leg_var_overrides() {
+        let leg = |id: &str, vars: std::collections::HashMap<String, String>| Leg {
+            id: id.to_string(),
+            title: "Uses {{model}}".to_string(),
+            focus: "f".to_string(),
+            description: "d".to_string(),
+            agent: None,
+            order: None,
+            when: None,
+            vars,
+        };
+
+        let mut overrides = std::collections::HashMap::new();
+        overrides.insert("model".to_string(), "opus".to_string());
+
+        let formula = Formula {
+            name: "convoy".to_string(),
+            description: "d".to_string(),
+            formula_type: FormulaType::Convoy,
+            version: 1,
+            legs: vec![
+                leg("inherits", std::collections::HashMap::new()),
+                leg("overrides", overrides.clone()),
+            ],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let mut vars = FxHashMap::default();
+        vars.insert("model".to_string(), "haiku".to_string());
         let cooked = cook_formula_internal(&formula, &vars);
 
-        assert_eq!(cooked.formula.name, "static-workflow");
-        assert_eq!(cooked.formula.description, "No variables here");
+        // First leg inherits the global var; second substitutes its override
+        assert_eq!(cooked.formula.legs[0].title, "Uses haiku");
+        assert_eq!(cooked.formula.legs[1].title, "Uses opus");
+        // Overrides are scoped to their leg and round-trip on the cooked leg
+        assert!(cooked.formula.legs[0].vars.is_empty());
+        assert_eq!(cooked.formula.legs[1].vars, overrides);
+    }
+
+    #[test]
+    fn test_var_value_size_limit() {
+        let mut vars = FxHashMap::default();
+        vars.insert("small".to_string(), "ok".to_string());
+        assert!(check_var_value_sizes(&vars, default_max_var_value_bytes()).is_ok());
+
+        vars.insert("huge".to_string(), "x".repeat(100));
+        let err = check_var_value_sizes(&vars, 64).unwrap_err();
+        assert_eq!(
+            err,
+            CookError::VarValueTooLarge {
+                var_name: "huge".to_string(),
+                size: 100,
+                max: 64,
+            }
+        );
+
+        // The default limit is 64KB
+        assert_eq!(CookOptions::default().max_var_value_bytes, 64 * 1024);
+    }
+
+    #[test]
+    fn test_normalize_newlines() {
+        let value = "line one\r\nline two\nline three";
+        assert_eq!(normalize_newlines(value, NewlineStyle::Preserve), value);
+        assert_eq!(
+            normalize_newlines(value, NewlineStyle::Unix),
+            "line one\nline two\nline three"
+        );
+        assert_eq!(
+            normalize_newlines(value, NewlineStyle::Windows),
+            "line one\r\nline two\r\nline three"
+        );
+        assert_eq!(
+            normalize_newlines(value, NewlineStyle::HtmlBreak),
+            "line one<br>line two<br>line three"
+        );
+        assert_eq!(
+            normalize_newlines(value, NewlineStyle::MarkdownBreak),
+            "line one  \nline two  \nline three"
+        );
+    }
+
+    #[test]
+    fn test_cook_with_newline_style() {
+        let formula = Formula {
+            name: "newline-test".to_string(),
+            description: "{{notes}}".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let mut vars = FxHashMap::default();
+        vars.insert("notes".to_string(), "first\nsecond".to_string());
+
+        let options = CookOptions {
+            newline_style: NewlineStyle::HtmlBreak,
+            ..Default::default()
+        };
+        let cooked = cook_formula_with_options(&formula, &vars, &options);
+        assert_eq!(cooked.formula.description, "first<br>second");
+
+        // Default options preserve the value as-is
+        let cooked = cook_formula_with_options(&formula, &vars, &CookOptions::default());
+        assert_eq!(cooked.formula.description, "first\nsecond");
+    }
+
+    #[test]
+    fn test_list_substitution_sites() {
+        let formula = Formula {
+            name: "{{project}}-workflow".to_string(),
+            description: "plain".to_string(),
+            formula_type: FormulaType::Workflow,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![Step {
+                id: "step1".to_string(),
+                title: "Build {{project}} in {{env}}".to_string(),
+                description: "d".to_string(),
+                needs: vec![],
+                duration: None,
+                requires: vec![],
+                when: None,
+                foreach: None,
+            }],
+            vars: std::collections::HashMap::new(),
+        };
+
+        let sites = list_substitution_sites(&formula);
+        assert_eq!(sites.len(), 3);
+        assert_eq!(
+            sites[0],
+            SubstitutionSite {
+                var_name: "project".to_string(),
+                field_path: "name".to_string(),
+                byte_offset: 0,
+            }
+        );
+        assert_eq!(sites[1].var_name, "project");
+        assert_eq!(sites[1].field_path, "steps[0].title");
+        assert_eq!(sites[1].byte_offset, 6);
+        assert_eq!(sites[2].var_name, "env");
+        assert_eq!(sites[2].byte_offset, 21);
     }
 
     #[test]
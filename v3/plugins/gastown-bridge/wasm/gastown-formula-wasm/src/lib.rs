@@ -30,15 +30,16 @@
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use gastown_shared::FxHashMap;
 
 mod parser;
 mod cooker;
 mod molecule;
+mod lint;
 
 pub use parser::*;
 pub use cooker::*;
 pub use molecule::*;
+pub use lint::*;
 
 // ============================================================================
 // Core Types
@@ -46,6 +47,7 @@ pub use molecule::*;
 
 /// Formula type enumeration
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
 #[serde(rename_all = "lowercase")]
 pub enum FormulaType {
     Convoy,
@@ -54,8 +56,21 @@ pub enum FormulaType {
     Aspect,
 }
 
+impl FormulaType {
+    /// Canonical lowercase name, matching the serde representation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FormulaType::Convoy => "convoy",
+            FormulaType::Workflow => "workflow",
+            FormulaType::Expansion => "expansion",
+            FormulaType::Aspect => "aspect",
+        }
+    }
+}
+
 /// Workflow step definition
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
 pub struct Step {
     pub id: String,
     pub title: String,
@@ -66,10 +81,18 @@ pub struct Step {
     pub duration: Option<u32>,
     #[serde(default)]
     pub requires: Vec<String>,
+    /// Cook-time condition; when it evaluates false the step is dropped
+    #[serde(default)]
+    pub when: Option<String>,
+    /// Name of a list variable; the cooker expands this step into one
+    /// concrete step per item, exposing the value as `{{item}}`
+    #[serde(default)]
+    pub foreach: Option<String>,
 }
 
 /// Convoy leg definition
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
 pub struct Leg {
     pub id: String,
     pub title: String,
@@ -79,12 +102,45 @@ pub struct Leg {
     pub agent: Option<String>,
     #[serde(default)]
     pub order: Option<u32>,
+    /// Cook-time condition; when it evaluates false the leg is dropped
+    #[serde(default)]
+    pub when: Option<String>,
+    /// Per-leg var overrides; this leg's fields substitute from the
+    /// global vars with these values layered on top
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub vars: HashMap<String, String>,
+}
+
+/// Declared type of a variable's value
+///
+/// Defaults to `String`, which keeps the original all-text cooking
+/// behavior. A non-string type makes the cook coerce the supplied text
+/// into a native JSON value, recorded in the cooked formula's
+/// `typed_vars`; values that do not coerce fail validation.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum VarType {
+    #[default]
+    String,
+    Int,
+    Float,
+    Bool,
+    List,
+    Map,
 }
 
 /// Variable definition
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
 pub struct Var {
+    /// Var name; may be omitted in TOML, in which case the parser fills
+    /// it from the `[vars.*]` key
+    #[serde(default)]
     pub name: String,
+    /// Declared value type; see `VarType` for coercion behavior
+    #[serde(default, rename = "type")]
+    pub var_type: VarType,
     #[serde(default)]
     pub description: Option<String>,
     #[serde(default)]
@@ -95,10 +151,37 @@ pub struct Var {
     pub pattern: Option<String>,
     #[serde(default, rename = "enum")]
     pub enum_values: Option<Vec<String>>,
+    /// Inclusive lower bound for numeric values
+    #[serde(default)]
+    pub min: Option<f64>,
+    /// Inclusive upper bound for numeric values
+    #[serde(default)]
+    pub max: Option<f64>,
+    /// Exclusive lower bound for numeric values
+    #[serde(default)]
+    pub min_exclusive: Option<f64>,
+    /// Exclusive upper bound for numeric values
+    #[serde(default)]
+    pub max_exclusive: Option<f64>,
+    /// Minimum value length in characters
+    #[serde(default)]
+    pub min_length: Option<u32>,
+    /// Maximum value length in characters
+    #[serde(default)]
+    pub max_length: Option<u32>,
+    /// Named value format (`email`, `uri`, `date`, `uuid`, or `slug`);
+    /// checked alongside `pattern` and `enum`
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Mask this var's value as `***` in cooked output and diagnostics;
+    /// the real value is retrievable via `cook_formula_secrets`
+    #[serde(default)]
+    pub secret: bool,
 }
 
 /// Synthesis configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
 pub struct Synthesis {
     pub strategy: String,
     #[serde(default)]
@@ -108,7 +191,8 @@ pub struct Synthesis {
 }
 
 /// Formula definition
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
 pub struct Formula {
     #[serde(rename = "formula")]
     pub name: String,
@@ -123,23 +207,357 @@ pub struct Formula {
     pub synthesis: Option<Synthesis>,
     #[serde(default)]
     pub steps: Vec<Step>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_nullable_vars")]
     pub vars: HashMap<String, Var>,
 }
 
+/// Treat `"vars": null` the same as a missing or empty `vars` table
+///
+/// Both are valid JSON spellings of "no vars", and formulas serialized by
+/// other toolchains commonly emit `null`.
+fn deserialize_nullable_vars<'de, D>(deserializer: D) -> Result<HashMap<String, Var>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let vars: Option<HashMap<String, Var>> = Option::deserialize(deserializer)?;
+    Ok(vars.unwrap_or_default())
+}
+
 #[inline(always)]
-fn default_version() -> u32 {
+pub(crate) fn default_version() -> u32 {
     1
 }
 
+// ============================================================================
+// TypeScript Definitions
+// ============================================================================
+
+/// Hand-authored TypeScript declarations for the JSON payloads crossing
+/// the WASM boundary
+///
+/// wasm-bindgen only sees `string`/`JsValue` at the boundary, so without
+/// these the generated `.d.ts` types every structured payload as `any`.
+/// Shapes mirror the serde representations (field renames, optionals,
+/// and skip-if-empty fields included); keep them in sync when the Rust
+/// types change.
+#[wasm_bindgen(typescript_custom_section)]
+const TYPESCRIPT_TYPES: &str = r#"
+export type FormulaType = "convoy" | "workflow" | "expansion" | "aspect";
+export type VarType = "string" | "int" | "float" | "bool" | "list" | "map";
+export type LintSeverity = "error" | "warning" | "hint";
+
+export interface Step {
+  id: string;
+  title: string;
+  description: string;
+  needs: string[];
+  duration?: number | null;
+  requires: string[];
+  when?: string | null;
+  foreach?: string | null;
+}
+
+export interface Leg {
+  id: string;
+  title: string;
+  focus: string;
+  description: string;
+  agent?: string | null;
+  order?: number | null;
+  when?: string | null;
+  vars?: Record<string, string>;
+}
+
+export interface Var {
+  name: string;
+  type: VarType;
+  description?: string | null;
+  default?: string | null;
+  required: boolean;
+  pattern?: string | null;
+  enum?: string[] | null;
+  min?: number | null;
+  max?: number | null;
+  min_exclusive?: number | null;
+  max_exclusive?: number | null;
+  min_length?: number | null;
+  max_length?: number | null;
+  format?: string | null;
+  secret: boolean;
+}
+
+export interface Synthesis {
+  strategy: string;
+  format?: string | null;
+  description?: string | null;
+}
+
+export interface Formula {
+  formula: string;
+  description: string;
+  type: FormulaType;
+  version: number;
+  legs: Leg[];
+  synthesis?: Synthesis | null;
+  steps: Step[];
+  vars: Record<string, Var>;
+}
+
+export interface CookedVar {
+  name: string;
+  value: string;
+}
+
+export interface VarProvenance {
+  source: "supplied" | "env" | "default";
+  default?: string;
+}
+
+export interface CookedFormula extends Formula {
+  cooked_at: string;
+  cooked_vars: CookedVar[];
+  original_name: string;
+  cook_duration_us: number;
+  substitution_count: number;
+  unresolved_count: number;
+  formula_url?: string;
+  cooked_by?: string;
+  var_provenance?: Record<string, VarProvenance>;
+  typed_vars?: Record<string, unknown>;
+  warnings?: string[];
+}
+
+export interface MoleculeBead {
+  id: string;
+  title: string;
+  description: string;
+  labels: string[];
+  depends_on: number[];
+  duration?: number | null;
+  requires: string[];
+  metadata?: Record<string, unknown>;
+  tier: number;
+  tier_position: number;
+  content_hash?: string;
+  agent?: string;
+}
+
+export interface Molecule {
+  id: string;
+  formula_name: string;
+  formula_type: FormulaType;
+  beads: MoleculeBead[];
+  bead_count: number;
+  has_cycle: boolean;
+  execution_order: number[];
+  waves: number[][];
+  critical_path: number[];
+  wave_durations: number[];
+  estimated_duration: number;
+  content_hash?: string;
+}
+
+export interface MoleculeCheckpoint {
+  molecule_id: string;
+  content_hash?: string;
+  completed: string[];
+  failed: string[];
+  pending: string[];
+}
+
+export interface MoleculeDiffEdge {
+  from: string;
+  to: string;
+}
+
+export interface MoleculeDiff {
+  added: string[];
+  removed: string[];
+  modified: string[];
+  added_edges: MoleculeDiffEdge[];
+  removed_edges: MoleculeDiffEdge[];
+}
+
+export interface ParseDiagnostic {
+  code: string;
+  message: string;
+  byte_offset: number;
+  byte_len: number;
+  line: number;
+  col: number;
+  end_line: number;
+  end_col: number;
+}
+
+export interface LintWarning {
+  code: string;
+  rule_code?: string;
+  message: string;
+  severity: LintSeverity;
+}
+
+export interface FormulaFix {
+  id: string;
+  description: string;
+}
+
+export interface FormulaReport {
+  index: number;
+  ok: boolean;
+  error?: string;
+  warnings: LintWarning[];
+}
+
+export interface BatchValidationReport {
+  reports: FormulaReport[];
+  total: number;
+  affected: number;
+  error_count: number;
+  warning_count: number;
+  hint_count: number;
+}
+
+export interface CookCacheStats {
+  size: number;
+  capacity: number;
+  hits: number;
+  misses: number;
+}
+"#;
+
+// ============================================================================
+// Throughput Counters
+// ============================================================================
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Total bytes received by parse_formula calls
+pub(crate) static TOTAL_INPUT_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Total bytes emitted by cook_formula calls
+pub(crate) static TOTAL_OUTPUT_BYTES: AtomicU64 = AtomicU64::new(0);
+
+#[inline(always)]
+pub(crate) fn record_input_bytes(n: usize) {
+    TOTAL_INPUT_BYTES.fetch_add(n as u64, Ordering::Relaxed);
+}
+
+#[inline(always)]
+pub(crate) fn record_output_bytes(n: usize) {
+    TOTAL_OUTPUT_BYTES.fetch_add(n as u64, Ordering::Relaxed);
+}
+
 /// Cooked formula with substituted variables
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
 pub struct CookedFormula {
     #[serde(flatten)]
     pub formula: Formula,
     pub cooked_at: String,
+    /// Serialized as a name-sorted array of `{name, value}` objects so
+    /// version-controlled cooked formulas diff deterministically; the old
+    /// object form is still accepted on deserialization
+    #[serde(
+        serialize_with = "serialize_cooked_vars",
+        deserialize_with = "deserialize_cooked_vars"
+    )]
     pub cooked_vars: HashMap<String, String>,
     pub original_name: String,
+    /// How long the cook took, in microseconds (for SLA monitoring)
+    #[serde(default)]
+    pub cook_duration_us: u64,
+    /// Number of `{{VAR}}` tokens that were replaced during the cook
+    #[serde(default)]
+    pub substitution_count: u32,
+    /// Number of `{{...}}` tokens still present after the cook
+    #[serde(default)]
+    pub unresolved_count: u32,
+    /// Canonical URL of the source formula (provenance)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub formula_url: Option<String>,
+    /// Agent or user that triggered the cook (provenance)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cooked_by: Option<String>,
+    /// Per-var provenance for audit trails: where each effective value
+    /// came from (`supplied`, `env`, or `default`) plus the declared
+    /// default, keyed by var name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub var_provenance: HashMap<String, VarProvenance>,
+    /// Native values for vars declaring a non-string `type`, keyed by
+    /// var name; string-typed vars stay in `cooked_vars` only
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub typed_vars: HashMap<String, serde_json::Value>,
+    /// Informational warnings emitted during the cook (e.g. unresolved
+    /// `{{...}}` tokens in lenient mode)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// Where one effective var value came from during a cook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
+pub struct VarProvenance {
+    /// `supplied` (caller vars), `env` (options env map), or `default`
+    /// (declared on the formula)
+    pub source: String,
+    /// The declared default, when the formula has one — recorded even
+    /// when a supplied or env value overrode it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CookedVarEntry<'a> {
+    name: &'a str,
+    value: &'a str,
+}
+
+/// Serialize cooked vars as a name-sorted `[{name, value}]` array
+fn serialize_cooked_vars<S>(
+    vars: &HashMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    use serde::ser::SerializeSeq;
+
+    let mut entries: Vec<(&String, &String)> = vars.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut seq = serializer.serialize_seq(Some(entries.len()))?;
+    for (name, value) in entries {
+        seq.serialize_element(&CookedVarEntry { name, value })?;
+    }
+    seq.end()
+}
+
+/// Accept cooked vars as either the legacy object form or the sorted
+/// array form
+fn deserialize_cooked_vars<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct OwnedEntry {
+        name: String,
+        value: String,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Object(HashMap<String, String>),
+        Array(Vec<OwnedEntry>),
+    }
+
+    match Repr::deserialize(deserializer)? {
+        Repr::Object(map) => Ok(map),
+        Repr::Array(entries) => Ok(entries
+            .into_iter()
+            .map(|entry| (entry.name, entry.value))
+            .collect()),
+    }
 }
 
 // ============================================================================
@@ -169,135 +587,1381 @@ pub fn parse_formula(content: &str) -> Result<JsValue, JsValue> {
     parser::parse_formula_impl(content)
 }
 
-/// Cook a formula with variable substitution
+/// Parse a JSON formula document into a Formula struct
 ///
 /// # Arguments
-/// * `formula_json` - Formula as JSON string
-/// * `vars_json` - Variables as JSON string
+/// * `content` - JSON formula content
 ///
 /// # Returns
-/// * `String` - Cooked formula as JSON string
+/// * `JsValue` - Parsed formula as JavaScript object
+#[wasm_bindgen]
+#[inline]
+pub fn parse_formula_json(content: &str) -> Result<JsValue, JsValue> {
+    parser::parse_formula_json_impl(content)
+}
+
+/// Parse a YAML formula string into a Formula struct
 ///
-/// # Performance
-/// Target: <0.05ms (500x faster than JavaScript)
+/// # Arguments
+/// * `content` - YAML formula content
+///
+/// # Returns
+/// * `JsValue` - Parsed formula as JavaScript object
 #[wasm_bindgen]
 #[inline]
-pub fn cook_formula(formula_json: &str, vars_json: &str) -> Result<String, JsValue> {
-    cooker::cook_formula_impl(formula_json, vars_json)
+pub fn parse_formula_yaml(content: &str) -> Result<JsValue, JsValue> {
+    parser::parse_formula_yaml_impl(content)
 }
 
-/// Batch cook multiple formulas
+/// Parse a formula string in either TOML or YAML, autodetecting the format
 ///
 /// # Arguments
-/// * `formulas_json` - Array of formulas as JSON string
-/// * `vars_json` - Array of variable maps as JSON string
+/// * `content` - TOML or YAML formula content
 ///
 /// # Returns
-/// * `String` - Array of cooked formulas as JSON string
+/// * `JsValue` - Parsed formula as JavaScript object
+#[wasm_bindgen]
+#[inline]
+pub fn parse_formula_any(content: &str) -> Result<JsValue, JsValue> {
+    parser::parse_formula_any_impl(content)
+}
+
+/// Parse a formula leniently, returning a partial AST plus diagnostics
 ///
-/// # Performance
-/// Target: <1ms for 100 formulas (500x faster with batch optimization)
+/// # Arguments
+/// * `content` - TOML formula content (possibly mid-edit)
+///
+/// # Returns
+/// * `JsValue` - `{formula, diagnostics, complete}` where `formula` is as
+///   much of the document as could be recovered
 #[wasm_bindgen]
 #[inline]
-pub fn cook_batch(formulas_json: &str, vars_json: &str) -> Result<String, JsValue> {
-    cooker::cook_batch_impl(formulas_json, vars_json)
+pub fn parse_formula_lenient(content: &str) -> Result<JsValue, JsValue> {
+    parser::parse_formula_lenient_impl(content)
 }
 
-/// Generate a molecule (bead chain) from a cooked formula
+/// Parse a TOML formula string, normalizing the name to kebab-case
 ///
 /// # Arguments
-/// * `formula_json` - Cooked formula as JSON string
+/// * `content` - TOML formula content
 ///
 /// # Returns
-/// * `String` - Molecule definition as JSON string
+/// * `JsValue` - Parsed formula with `name` normalized (e.g.
+///   `MyWorkflow` -> `my-workflow`)
+#[wasm_bindgen]
+#[inline]
+pub fn parse_formula_normalized(content: &str) -> Result<JsValue, JsValue> {
+    parser::parse_formula_normalized_impl(content)
+}
+
+/// Parse a TOML formula string, keeping unrecognized top-level fields
 ///
-/// # Performance
-/// Target: <0.1ms
+/// # Arguments
+/// * `content` - TOML formula content
+///
+/// # Returns
+/// * `JsValue` - `{formula, extra_fields}` where `extra_fields` holds all
+///   top-level keys the `Formula` struct does not consume
 #[wasm_bindgen]
 #[inline]
-pub fn generate_molecule(formula_json: &str) -> Result<String, JsValue> {
-    molecule::generate_molecule_impl(formula_json)
+pub fn parse_formula_with_extra(content: &str) -> Result<JsValue, JsValue> {
+    parser::parse_formula_with_extra_impl(content)
 }
 
-/// Validate formula syntax
+/// Parse an array of formula documents in one call
+///
+/// # Arguments
+/// * `contents_json` - JSON array of TOML formula strings
+///
+/// # Returns
+/// * `JsValue` - `{ formulas: (Formula|null)[], errors: {index, message}[] }`
+///   with slot `i` aligned to input `i`
+#[wasm_bindgen]
+pub fn parse_formula_batch(contents_json: &str) -> Result<JsValue, JsValue> {
+    parser::parse_formula_batch_impl(contents_json)
+}
+
+/// List the mechanical fixes available for a formula document
 ///
 /// # Arguments
 /// * `content` - TOML formula content
 ///
 /// # Returns
-/// * `bool` - True if valid
+/// * `JsValue` - Array of fixes (`id`, `description`): a missing
+///   `version` key, misordered `[[legs]]` blocks, and unknown keys with
+///   an obvious correction
+#[wasm_bindgen]
+pub fn list_fixes(content: &str) -> Result<JsValue, JsValue> {
+    parser::list_fixes_impl(content)
+}
+
+/// Apply fixes to a formula document, returning corrected TOML
 ///
-/// # Performance
-/// Faster than full parse (skips serialization)
+/// Fixes edit the source textually, so comments and formatting outside
+/// the fixed spots are preserved.
+///
+/// # Arguments
+/// * `content` - TOML formula content
+/// * `fix_ids_json` - JSON array of fix ids from `list_fixes`
+///
+/// # Returns
+/// * `String` - Corrected TOML content
 #[wasm_bindgen]
-#[inline(always)]
-pub fn validate_formula(content: &str) -> bool {
-    parser::validate_formula_impl(content)
+pub fn apply_fixes(content: &str, fix_ids_json: &str) -> Result<String, JsValue> {
+    parser::apply_fixes_impl(content, fix_ids_json)
 }
 
-/// Get formula type from TOML content
+/// Report `needs` entries that reference no existing step
 ///
 /// # Arguments
 /// * `content` - TOML formula content
 ///
 /// # Returns
-/// * `String` - Formula type ("convoy", "workflow", "expansion", "aspect")
+/// * `JsValue` - Array of `ParseDiagnostic` entries, one per dangling
+///   reference, with "did you mean" suggestions where a step id is close
+#[wasm_bindgen]
+pub fn check_dangling_needs(content: &str) -> Result<JsValue, JsValue> {
+    parser::check_dangling_needs_impl(content)
+}
+
+/// Report unknown keys in a formula document
 ///
-/// # Performance
-/// Uses fast extraction without full parsing when possible
+/// # Arguments
+/// * `content` - TOML formula content
+///
+/// # Returns
+/// * `JsValue` - Array of `ParseDiagnostic` entries, one per key outside
+///   the schema, with "did you mean" suggestions where a known key is close
 #[wasm_bindgen]
-#[inline]
-pub fn get_formula_type(content: &str) -> Result<String, JsValue> {
-    parser::get_formula_type_impl(content)
+pub fn check_unknown_keys(content: &str) -> Result<JsValue, JsValue> {
+    parser::check_unknown_keys_impl(content)
 }
 
-/// Get performance metrics
+/// Parse a multi-document formula bundle
 ///
-/// Returns timing information for benchmarking
+/// # Arguments
+/// * `content` - Formula documents separated by `+++` (or `---`) lines
+///
+/// # Returns
+/// * `JsValue` - `{ formulas: Formula[], errors: string[] }` with one
+///   error entry per document that failed to parse
 #[wasm_bindgen]
-pub fn get_metrics() -> JsValue {
-    let metrics = serde_json::json!({
-        "version": "3.0.0-alpha.1",
-        "targets": {
-            "parse_toml_ms": 0.1,
-            "cook_formula_ms": 0.05,
-            "batch_100_ms": 1.0,
-            "generate_molecule_ms": 0.1
-        },
-        "optimizations": [
-            "fxhash",
-            "arena_allocation",
-            "memory_pools",
-            "inline_hot_paths",
-            "zero_copy_parsing",
-            "simd_patterns"
-        ]
-    });
+pub fn parse_formula_bundle(content: &str) -> Result<JsValue, JsValue> {
+    parser::parse_formula_bundle_impl(content)
+}
 
-    serde_wasm_bindgen::to_value(&metrics).unwrap_or(JsValue::NULL)
+/// Parse a formula and resolve its `include` directives
+///
+/// # Arguments
+/// * `content` - TOML formula content with an optional top-level
+///   `include = ["name"]` list
+/// * `resolver` - Callback `(name: string) => string` returning the
+///   content of an included document
+///
+/// # Returns
+/// * `JsValue` - Parsed formula with includes merged in
+#[wasm_bindgen]
+pub fn parse_formula_with_includes(
+    content: &str,
+    resolver: &js_sys::Function,
+) -> Result<JsValue, JsValue> {
+    let mut resolve = |name: &str| -> Result<String, String> {
+        let result = resolver
+            .call1(&JsValue::NULL, &JsValue::from_str(name))
+            .map_err(|_| format!("Include resolver threw for '{}'", name))?;
+        result
+            .as_string()
+            .ok_or_else(|| format!("Include resolver returned a non-string for '{}'", name))
+    };
+
+    let formula = parser::parse_formula_with_includes(content, &mut resolve)
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    serde_wasm_bindgen::to_value(&formula)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Serialize a formula back to TOML
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+///
+/// # Returns
+/// * `String` - TOML content that round-trips through `parse_formula`
+#[wasm_bindgen]
+#[inline]
+pub fn formula_to_toml(formula_json: &str) -> Result<String, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid formula JSON: {}", e)))?;
+    parser::formula_to_toml(&formula).map_err(|e| JsValue::from_str(&e))
+}
 
-    #[test]
-    fn test_parse_formula() {
-        let content = r#"
-formula = "test-workflow"
-description = "Test workflow"
-type = "workflow"
-version = 1
+/// Render a formula's dependency graph as Graphviz DOT
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+///
+/// # Returns
+/// * `String` - DOT digraph of steps/legs and their `needs` edges,
+///   styled by formula type
+#[wasm_bindgen]
+#[inline]
+pub fn formula_to_dot(formula_json: &str) -> Result<String, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+    Ok(parser::formula_to_dot(&formula))
+}
 
-[[steps]]
-id = "step1"
-title = "Step 1"
-description = "First step"
+/// Reformat a TOML formula into the canonical style
+///
+/// # Arguments
+/// * `content` - TOML formula content
+///
+/// # Returns
+/// * `String` - Canonically formatted TOML (idempotent; comments dropped)
+#[wasm_bindgen]
+#[inline]
+pub fn format_formula(content: &str) -> Result<String, JsValue> {
+    parser::format_formula_impl(content)
+}
+
+/// Cook a formula with variable substitution
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+/// * `vars_json` - Variables as JSON string
+///
+/// # Returns
+/// * `String` - Cooked formula as JSON string
+///
+/// # Performance
+/// Target: <0.05ms (500x faster than JavaScript)
+#[wasm_bindgen]
+#[inline]
+pub fn cook_formula(formula_json: &str, vars_json: &str) -> Result<String, JsValue> {
+    cooker::cook_formula_impl(formula_json, vars_json)
+}
+
+/// Cook a formula, returning a structured object
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+/// * `vars_json` - Variables as JSON string
+///
+/// # Returns
+/// * `JsValue` - Cooked formula as a JS object (no `JSON.parse` needed)
+///
+/// # Performance
+/// Faster than `cook_formula` for large formulas: the result crosses the
+/// boundary as a structured value instead of a JSON string that must be
+/// re-parsed. Use `cook_formula` when JSON text is the end goal.
+#[wasm_bindgen]
+#[inline]
+pub fn cook_formula_js(formula_json: &str, vars_json: &str) -> Result<JsValue, JsValue> {
+    cooker::cook_formula_js_impl(formula_json, vars_json)
+}
+
+/// Pre-compiled formula handle for repeated cooking
+///
+/// Parses the formula and scans its `{{name}}` placeholders once in the
+/// constructor; each `cook` call then substitutes a var set without
+/// re-parsing or re-scanning the template text. Use this when cooking
+/// the same formula with many different var sets.
+#[wasm_bindgen]
+pub struct CompiledFormula {
+    template: cooker::CompiledTemplate,
+}
+
+#[wasm_bindgen]
+impl CompiledFormula {
+    /// Compile a formula for repeated cooking
+    ///
+    /// # Arguments
+    /// * `formula_json` - Formula as JSON string (e.g. from `parse_formula`)
+    #[wasm_bindgen(constructor)]
+    pub fn new(formula_json: &str) -> Result<CompiledFormula, JsValue> {
+        Ok(CompiledFormula {
+            template: cooker::compile_formula_impl(formula_json)?,
+        })
+    }
+
+    /// Cook the compiled formula with one var set
+    ///
+    /// Applies the same strict validation as `cook_formula`.
+    ///
+    /// # Arguments
+    /// * `vars_json` - Variables as JSON string
+    ///
+    /// # Returns
+    /// * `JsValue` - Cooked formula as a JS object
+    pub fn cook(&self, vars_json: &str) -> Result<JsValue, JsValue> {
+        cooker::compiled_cook_impl(&self.template, vars_json)
+    }
+}
+
+/// Parsed formula handle held inside WASM
+///
+/// Parses once in the constructor; every subsequent operation works on
+/// the retained Rust struct, so chaining `.validate()`, `.cook(vars)`,
+/// and `.toMolecule()` on the same formula pays the parse and boundary
+/// cost once instead of a JSON round trip per call.
+#[wasm_bindgen]
+pub struct FormulaHandle {
+    formula: Formula,
+}
+
+#[wasm_bindgen]
+impl FormulaHandle {
+    /// Parse formula content into a handle
+    ///
+    /// # Arguments
+    /// * `content` - Formula content (TOML or YAML, autodetected)
+    #[wasm_bindgen(constructor)]
+    pub fn new(content: &str) -> Result<FormulaHandle, JsValue> {
+        let formula =
+            parser::parse_formula_any_internal(content).map_err(|e| JsValue::from_str(&e))?;
+        Ok(FormulaHandle { formula })
+    }
+
+    /// Formula name
+    pub fn name(&self) -> String {
+        self.formula.name.clone()
+    }
+
+    /// Formula type as its canonical lowercase name
+    #[wasm_bindgen(js_name = formulaType)]
+    pub fn formula_type(&self) -> String {
+        self.formula.formula_type.as_str().to_string()
+    }
+
+    /// Cook the held formula with one var set
+    ///
+    /// Applies the same strict validation as `cook_formula`.
+    ///
+    /// # Arguments
+    /// * `vars_json` - Variables as JSON string
+    ///
+    /// # Returns
+    /// * `JsValue` - Cooked formula as a JS object
+    pub fn cook(&self, vars_json: &str) -> Result<JsValue, JsValue> {
+        cooker::cook_parsed_js_impl(&self.formula, vars_json)
+    }
+
+    /// Run the full validator against the held formula
+    ///
+    /// # Returns
+    /// * `JsValue` - Array of lint warnings as JS objects
+    pub fn validate(&self) -> Result<JsValue, JsValue> {
+        let warnings = lint::FormulaValidator::new().validate(&self.formula);
+        serde_wasm_bindgen::to_value(&warnings)
+            .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+    }
+
+    /// Generate a molecule from the held formula
+    ///
+    /// # Returns
+    /// * `JsValue` - Molecule as a JS object
+    #[wasm_bindgen(js_name = toMolecule)]
+    pub fn to_molecule(&self) -> Result<JsValue, JsValue> {
+        let molecule = molecule::generate_molecule_for(&self.formula)?;
+        serde_wasm_bindgen::to_value(&molecule)
+            .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+    }
+
+    /// Steps of the held formula
+    ///
+    /// # Returns
+    /// * `JsValue` - Array of steps as JS objects
+    pub fn steps(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.formula.steps)
+            .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+    }
+
+    /// Legs of the held formula
+    ///
+    /// # Returns
+    /// * `JsValue` - Array of legs as JS objects
+    pub fn legs(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.formula.legs)
+            .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+    }
+
+    /// The held formula as a JS object
+    #[wasm_bindgen(js_name = toObject)]
+    pub fn to_object(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&self.formula)
+            .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+    }
+}
+
+/// Cook a formula with variable substitution and cook options
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+/// * `vars_json` - Variables as JSON string
+/// * `options_json` - Cook options as JSON string (`source_url`, `cooked_by`)
+///
+/// # Returns
+/// * `String` - Cooked formula as JSON string with provenance fields
+#[wasm_bindgen]
+#[inline]
+pub fn cook_formula_opts(
+    formula_json: &str,
+    vars_json: &str,
+    options_json: &str,
+) -> Result<String, JsValue> {
+    cooker::cook_formula_opts_impl(formula_json, vars_json, options_json)
+}
+
+/// Cook a formula for preview with deterministic output
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+/// * `vars_json` - Variables as JSON string
+///
+/// # Returns
+/// * `String` - Cooked formula with `cooked_at = "DRY_RUN"` and zeroed
+///   timing, so identical inputs always produce identical output
+#[wasm_bindgen]
+#[inline]
+pub fn cook_formula_dry_run(formula_json: &str, vars_json: &str) -> Result<String, JsValue> {
+    cooker::cook_formula_dry_run_impl(formula_json, vars_json)
+}
+
+/// Analyze a formula's variable declarations against its references
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+///
+/// # Returns
+/// * `JsValue` - `{ undeclared, unused, required_without_default }`:
+///   vars referenced but not declared, declared but never referenced,
+///   and required vars lacking a default
+#[wasm_bindgen]
+pub fn analyze_vars(formula_json: &str) -> Result<JsValue, JsValue> {
+    cooker::analyze_vars_impl(formula_json)
+}
+
+/// Report how a cook would resolve every placeholder, without cooking
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+/// * `vars_json` - Variables as JSON string
+///
+/// # Returns
+/// * `JsValue` - `{ sites, unresolved, unused_vars }`: each site records
+///   its field path, which source satisfies it (`vars`, `default`, or
+///   `unresolved`) and the value that would be substituted
+#[wasm_bindgen]
+pub fn cook_formula_substitution_report(
+    formula_json: &str,
+    vars_json: &str,
+) -> Result<JsValue, JsValue> {
+    cooker::substitution_report_impl(formula_json, vars_json)
+}
+
+/// Upgrade a stored cooked formula to the current storage format
+///
+/// # Arguments
+/// * `old_json` - Cooked formula JSON (legacy object `cooked_vars` accepted)
+///
+/// # Returns
+/// * `String` - Cooked formula JSON with name-sorted array `cooked_vars`
+#[wasm_bindgen]
+#[inline]
+pub fn upgrade_cooked_formula_format(old_json: &str) -> Result<String, JsValue> {
+    cooker::upgrade_cooked_formula_format_impl(old_json)
+}
+
+/// Batch cook multiple formulas
+///
+/// # Arguments
+/// * `formulas_json` - Array of formulas as JSON string
+/// * `vars_json` - Array of variable maps as JSON string
+///
+/// # Returns
+/// * `String` - Array of cooked formulas as JSON string
+///
+/// # Performance
+/// Target: <1ms for 100 formulas (500x faster with batch optimization)
+#[wasm_bindgen]
+#[inline]
+pub fn cook_batch(formulas_json: &str, vars_json: &str) -> Result<String, JsValue> {
+    cooker::cook_batch_impl(formulas_json, vars_json)
+}
+
+/// Resolve the real values of secret vars for one cook
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+/// * `vars_json` - Variables as JSON string
+///
+/// # Returns
+/// * `String` - Name-sorted JSON object of secret var name -> real value
+///   (cooked output itself masks these as `***`)
+#[wasm_bindgen]
+#[inline]
+pub fn cook_formula_secrets(formula_json: &str, vars_json: &str) -> Result<String, JsValue> {
+    cooker::cook_formula_secrets_impl(formula_json, vars_json)
+}
+
+/// Cook result cache statistics
+///
+/// # Returns
+/// * `JsValue` - `{size, capacity, hits, misses}` for the internal
+///   content-hash cook cache
+#[wasm_bindgen]
+pub fn cache_stats() -> JsValue {
+    serde_wasm_bindgen::to_value(&cooker::cook_cache_stats()).unwrap_or(JsValue::NULL)
+}
+
+/// Clear the cook result cache and reset its counters
+#[wasm_bindgen]
+pub fn cache_clear() {
+    cooker::cook_cache_clear();
+}
+
+/// Batch cook in chunks, reporting progress to a callback
+///
+/// Cooks `chunk_size` formulas at a time and invokes `on_chunk` after
+/// each chunk, so UIs can render results incrementally instead of
+/// blocking on the whole batch.
+///
+/// # Arguments
+/// * `formulas_json` - Array of formulas as JSON string
+/// * `vars_json` - Array of variable maps as JSON string
+/// * `chunk_size` - Formulas cooked per chunk (0 uses the default of 64)
+/// * `on_chunk` - Callback `(chunkJson: string, percent: number) => void`
+///   receiving each cooked chunk as a JSON array; a throwing callback
+///   aborts the remaining chunks
+///
+/// # Returns
+/// * `u32` - Total number of formulas cooked
+#[wasm_bindgen]
+pub fn cook_batch_with_progress(
+    formulas_json: &str,
+    vars_json: &str,
+    chunk_size: u32,
+    on_chunk: &js_sys::Function,
+) -> Result<u32, JsValue> {
+    let mut emit = |chunk_json: &str, percent: f64| -> Result<(), String> {
+        on_chunk
+            .call2(
+                &JsValue::NULL,
+                &JsValue::from_str(chunk_json),
+                &JsValue::from_f64(percent),
+            )
+            .map(|_| ())
+            .map_err(|_| "Progress callback threw".to_string())
+    };
+    cooker::cook_batch_chunked_impl(formulas_json, vars_json, chunk_size, &mut emit)
+}
+
+/// Batch cook NDJSON byte payloads for zero-copy Worker transfer
+///
+/// Accepts one JSON document per line in each payload and returns the
+/// cooked formulas the same way, one per line in input order. The
+/// returned `Uint8Array`'s buffer can be passed to `postMessage` as a
+/// transferable, so Web Workers hand batches back and forth without
+/// re-stringifying them.
+///
+/// # Arguments
+/// * `formulas_ndjson` - Formulas as newline-delimited JSON bytes
+/// * `vars_ndjson` - Variable maps as newline-delimited JSON bytes
+///
+/// # Returns
+/// * `Uint8Array` - Cooked formulas as newline-delimited JSON bytes
+#[wasm_bindgen]
+pub fn cook_batch_bytes(formulas_ndjson: &[u8], vars_ndjson: &[u8]) -> Result<Vec<u8>, JsValue> {
+    cooker::cook_batch_bytes_impl(formulas_ndjson, vars_ndjson)
+}
+
+/// Batch cook on the JS event loop without freezing it
+///
+/// Cooks `chunk_size` formulas at a time (0 uses the default of 64) and
+/// yields back to the event loop between chunks, so very large batches
+/// stay responsive on the browser main thread.
+///
+/// # Arguments
+/// * `formulas_json` - Array of formulas as JSON string
+/// * `vars_json` - Array of variable maps as JSON string
+/// * `chunk_size` - Formulas cooked per event-loop turn (0 uses the default)
+///
+/// # Returns
+/// * `Promise<String>` - Resolves to the cooked formula array as a JSON string
+#[wasm_bindgen]
+pub async fn cook_batch_async(
+    formulas_json: String,
+    vars_json: String,
+    chunk_size: u32,
+) -> Result<String, JsValue> {
+    cooker::cook_batch_async_impl(&formulas_json, &vars_json, chunk_size).await
+}
+
+/// Cook a formula and return only the projected fields
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+/// * `vars_json` - Variables as JSON string
+/// * `projection_json` - JSON array of dot-paths to include in the output
+///
+/// # Returns
+/// * `String` - Projected cooked formula as JSON string
+#[wasm_bindgen]
+#[inline]
+pub fn cook_formula_project(
+    formula_json: &str,
+    vars_json: &str,
+    projection_json: &str,
+) -> Result<String, JsValue> {
+    cooker::cook_formula_project_impl(formula_json, vars_json, projection_json)
+}
+
+/// Cook one formula with many var sets
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+/// * `var_sets_json` - Array of variable maps as JSON string
+///
+/// # Returns
+/// * `String` - Array of cooked formulas as JSON string, one per var set
+///
+/// # Performance
+/// The formula is parsed once and reused across all var sets
+#[wasm_bindgen]
+#[inline]
+pub fn cook_formula_multi(formula_json: &str, var_sets_json: &str) -> Result<String, JsValue> {
+    cooker::cook_formula_multi_impl(formula_json, var_sets_json)
+}
+
+/// List every `{{VAR}}` substitution site in a formula
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+///
+/// # Returns
+/// * `JsValue` - Array of `{var_name, field_path, byte_offset}`
+#[wasm_bindgen]
+pub fn list_substitution_sites(formula_json: &str) -> Result<JsValue, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+
+    serde_wasm_bindgen::to_value(&cooker::list_substitution_sites(&formula))
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Generate a molecule (bead chain) from a cooked formula
+///
+/// # Arguments
+/// * `formula_json` - Cooked formula as JSON string
+///
+/// # Returns
+/// * `String` - Molecule definition as JSON string
+///
+/// # Performance
+/// Target: <0.1ms
+#[wasm_bindgen]
+#[inline]
+pub fn generate_molecule(formula_json: &str) -> Result<String, JsValue> {
+    molecule::generate_molecule_impl(formula_json)
+}
+
+/// Generate a molecule, returning a structured object
+///
+/// # Arguments
+/// * `formula_json` - Cooked formula as JSON string
+///
+/// # Returns
+/// * `JsValue` - Molecule as a JS object (no `JSON.parse` needed)
+///
+/// # Performance
+/// Faster than `generate_molecule` for large formulas: the result crosses
+/// the boundary as a structured value instead of a JSON string that must
+/// be re-parsed. Use `generate_molecule` when JSON text is the end goal.
+#[wasm_bindgen]
+#[inline]
+pub fn generate_molecule_js(formula_json: &str) -> Result<JsValue, JsValue> {
+    molecule::generate_molecule_js_impl(formula_json)
+}
+
+/// Generate a molecule without blocking the current microtask
+///
+/// Yields once to the JS event loop before generating, so callers can
+/// schedule large molecule builds behind pending UI work.
+///
+/// # Arguments
+/// * `formula_json` - Cooked formula as JSON string
+///
+/// # Returns
+/// * `Promise<String>` - Resolves to the molecule as a JSON string
+#[wasm_bindgen]
+pub async fn generate_molecule_async(formula_json: String) -> Result<String, JsValue> {
+    molecule::generate_molecule_async_impl(&formula_json).await
+}
+
+/// Generate a molecule with generation options
+///
+/// # Arguments
+/// * `formula_json` - Cooked formula as JSON string
+/// * `options_json` - Molecule options as JSON string (`molecule_id`,
+///   `assignment` with an agent roster and a `round_robin`,
+///   `capability`, or `load_weighted` strategy)
+///
+/// # Returns
+/// * `String` - Molecule definition as JSON string
+#[wasm_bindgen]
+#[inline]
+pub fn generate_molecule_opts(formula_json: &str, options_json: &str) -> Result<String, JsValue> {
+    molecule::generate_molecule_opts_impl(formula_json, options_json)
+}
+
+/// Split a molecule into connected chunks of bounded size
+///
+/// # Arguments
+/// * `molecule_json` - Molecule as JSON string
+/// * `max_beads` - Maximum beads per chunk
+///
+/// # Returns
+/// * `String` - Array of chunk molecules as JSON string
+#[wasm_bindgen]
+#[inline]
+pub fn split_molecule(molecule_json: &str, max_beads: usize) -> Result<String, JsValue> {
+    molecule::split_molecule_impl(molecule_json, max_beads)
+}
+
+/// Merge tooling annotations into bead metadata
+///
+/// # Arguments
+/// * `molecule_json` - Molecule as JSON string
+/// * `annotations_json` - `{bead_id: {key: value}}` as JSON string
+///
+/// # Returns
+/// * `String` - Annotated molecule as JSON string
+#[wasm_bindgen]
+#[inline]
+pub fn annotate_molecule(molecule_json: &str, annotations_json: &str) -> Result<String, JsValue> {
+    molecule::annotate_molecule(molecule_json, annotations_json)
+}
+
+/// Remove one tooling annotation key from all beads
+///
+/// # Arguments
+/// * `molecule_json` - Molecule as JSON string
+/// * `annotation_key` - Metadata key to remove
+///
+/// # Returns
+/// * `String` - Stripped molecule as JSON string
+#[wasm_bindgen]
+#[inline]
+pub fn strip_annotations(molecule_json: &str, annotation_key: &str) -> Result<String, JsValue> {
+    molecule::strip_annotations(molecule_json, annotation_key)
+}
+
+/// Diff two molecules
+///
+/// # Arguments
+/// * `a_json` - Baseline molecule as JSON string
+/// * `b_json` - Regenerated molecule as JSON string
+///
+/// # Returns
+/// * `String` - JSON diff with `added`/`removed`/`modified` bead ids and
+///   `added_edges`/`removed_edges` dependency changes
+#[wasm_bindgen]
+#[inline]
+pub fn diff_molecules(a_json: &str, b_json: &str) -> Result<String, JsValue> {
+    molecule::diff_molecules_impl(a_json, b_json)
+}
+
+/// Capture a checkpoint blob for a partially executed molecule
+///
+/// # Arguments
+/// * `molecule_json` - Molecule as JSON string
+/// * `completed_json` - JSON array of bead ids that finished successfully
+/// * `failed_json` - JSON array of bead ids that failed
+///
+/// # Returns
+/// * `String` - Checkpoint blob as JSON, recording completed, failed,
+///   and pending bead ids plus the molecule's content hash
+#[wasm_bindgen]
+#[inline]
+pub fn checkpoint_molecule(
+    molecule_json: &str,
+    completed_json: &str,
+    failed_json: &str,
+) -> Result<String, JsValue> {
+    molecule::checkpoint_molecule_impl(molecule_json, completed_json, failed_json)
+}
+
+/// Build the sub-molecule of remaining work from a checkpoint
+///
+/// Completed beads are dropped; failed and pending beads are kept for
+/// retry, with ordering metadata re-derived for the remaining graph.
+///
+/// # Arguments
+/// * `molecule_json` - Molecule as JSON string
+/// * `checkpoint_json` - Checkpoint blob from `checkpoint_molecule`
+///
+/// # Returns
+/// * `String` - Resumable molecule as JSON; errors when the checkpoint's
+///   content hash does not match the molecule's
+#[wasm_bindgen]
+#[inline]
+pub fn resume_molecule(molecule_json: &str, checkpoint_json: &str) -> Result<String, JsValue> {
+    molecule::resume_molecule_impl(molecule_json, checkpoint_json)
+}
+
+/// Merge multiple molecules into one execution graph
+///
+/// Beads with the same id deduplicate onto their first occurrence, and
+/// cross-molecule ordering follows `strategy`.
+///
+/// # Arguments
+/// * `molecules_json` - JSON array of molecules to merge, in order
+/// * `strategy` - `sequential` (each molecule waits for the previous
+///   one), `parallel` (no cross-molecule edges), or `interleaved`
+///   (molecules run in lockstep waves)
+///
+/// # Returns
+/// * `String` - Merged molecule as JSON with execution order, tiers,
+///   waves, and durations re-derived for the combined graph
+#[wasm_bindgen]
+#[inline]
+pub fn merge_molecules(molecules_json: &str, strategy: &str) -> Result<String, JsValue> {
+    molecule::merge_molecules_impl(molecules_json, strategy)
+}
+
+/// Render a molecule as a Mermaid flowchart
+///
+/// # Arguments
+/// * `molecule_json` - Molecule as JSON string
+///
+/// # Returns
+/// * `String` - Mermaid `flowchart TD` text with one node per bead and
+///   one arrow per dependency edge
+#[wasm_bindgen]
+#[inline]
+pub fn molecule_to_mermaid(molecule_json: &str) -> Result<String, JsValue> {
+    molecule::molecule_to_mermaid_impl(molecule_json)
+}
+
+/// Validate a molecule's dependency graph
+///
+/// # Arguments
+/// * `molecule_json` - Molecule as JSON string
+///
+/// # Returns
+/// * `String` - Array of warnings as JSON string
+#[wasm_bindgen]
+#[inline]
+pub fn validate_molecule(molecule_json: &str) -> Result<String, JsValue> {
+    molecule::validate_molecule_impl(molecule_json)
+}
+
+/// Compute shape statistics for a molecule
+///
+/// # Arguments
+/// * `molecule_json` - Molecule as JSON string
+///
+/// # Returns
+/// * `JsValue` - `{bead_count, source_count, sink_count, max_depth}`
+#[wasm_bindgen]
+#[inline]
+pub fn get_molecule_stats(molecule_json: &str) -> Result<JsValue, JsValue> {
+    molecule::get_molecule_stats_impl(molecule_json)
+}
+
+/// Get the source formula type of a molecule
+///
+/// # Arguments
+/// * `molecule_json` - Molecule as JSON string
+///
+/// # Returns
+/// * `String` - Formula type (`convoy`, `workflow`, `expansion`, `aspect`)
+#[wasm_bindgen]
+#[inline]
+pub fn get_molecule_formula_type(molecule_json: &str) -> Result<String, JsValue> {
+    molecule::get_molecule_formula_type_impl(molecule_json)
+}
+
+/// Find beads not on any source-to-sink execution path
+///
+/// # Arguments
+/// * `molecule_json` - Molecule as JSON string
+///
+/// # Returns
+/// * `String` - Array of unreachable bead ids as JSON string
+#[wasm_bindgen]
+#[inline]
+pub fn find_unreachable_beads(molecule_json: &str) -> Result<String, JsValue> {
+    let molecule: Molecule = serde_json::from_str(molecule_json)
+        .map_err(|e| JsValue::from_str(&format!("Molecule parse error: {}", e)))?;
+
+    serde_json::to_string(&molecule::find_unreachable_beads(&molecule))
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Lint a formula for likely mistakes
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+///
+/// # Returns
+/// * `String` - Array of lint warnings as JSON string
+#[wasm_bindgen]
+#[inline]
+pub fn lint_formula(formula_json: &str) -> Result<String, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+
+    let warnings = lint::lint_formula_internal(&formula);
+
+    serde_json::to_string(&warnings)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Validate a batch of formula documents in one call
+///
+/// # Arguments
+/// * `contents_json` - JSON array of TOML formula contents
+///
+/// # Returns
+/// * `String` - Aggregated report as JSON: per-formula outcomes plus
+///   totals (errors, warnings, hints, formulas affected)
+#[wasm_bindgen]
+#[inline]
+pub fn validate_batch(contents_json: &str) -> Result<String, JsValue> {
+    lint::validate_batch_report_impl(contents_json)
+}
+
+/// Lint a formula and emit the findings as SARIF 2.1 JSON
+///
+/// Runs the full validator with `config_json` applied (pass `{}` for
+/// defaults), then renders the findings for code-scanning UIs.
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+/// * `config_json` - Lint configuration (`disabled`, `severity`)
+/// * `file_uri` - URI of the formula source file, written into each
+///   result's artifact location
+///
+/// # Returns
+/// * `String` - SARIF 2.1 log as JSON string, with rule metadata and
+///   one result per finding
+#[wasm_bindgen]
+#[inline]
+pub fn lint_formula_sarif(
+    formula_json: &str,
+    config_json: &str,
+    file_uri: &str,
+) -> Result<String, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+
+    let config: lint::LintConfig = serde_json::from_str(config_json)
+        .map_err(|e| JsValue::from_str(&format!("Config parse error: {}", e)))?;
+
+    let warnings = lint::lint_formula_config_internal(&formula, &config);
+
+    serde_json::to_string(&lint::lint_to_sarif(&warnings, file_uri))
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Lint a formula with a configuration applied
+///
+/// Runs the full validator (standard lints plus duplicate id and
+/// missing description detection). Each finding carries a stable
+/// numeric rule code (e.g. `GT001` for duplicate ids) alongside its
+/// name, so CI gates and editor suppressions survive message changes.
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+/// * `config_json` - Lint configuration: `disabled` (rule names or
+///   numeric codes to suppress) and `severity` (per-rule overrides)
+///
+/// # Returns
+/// * `String` - Array of lint warnings as JSON string
+#[wasm_bindgen]
+#[inline]
+pub fn lint_formula_with_config(formula_json: &str, config_json: &str) -> Result<String, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+
+    let config: lint::LintConfig = serde_json::from_str(config_json)
+        .map_err(|e| JsValue::from_str(&format!("Config parse error: {}", e)))?;
+
+    let warnings = lint::lint_formula_config_internal(&formula, &config);
+
+    serde_json::to_string(&warnings)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Explain a formula as a structured info card
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+///
+/// # Returns
+/// * `JsValue` - `{name, type, description, var_count, step_count, leg_count,
+///   has_synthesis, complexity_score}`
+#[wasm_bindgen]
+pub fn explain_formula(formula_json: &str) -> Result<JsValue, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+
+    serde_wasm_bindgen::to_value(&parser::explain_formula_internal(&formula))
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Extract just the formula header (top-level scalar fields)
+///
+/// # Arguments
+/// * `content` - TOML formula content
+///
+/// # Returns
+/// * `JsValue` - `{name, description, formula_type, version, tags}`
+///
+/// # Performance
+/// Faster than parse_formula as it stops at the first section header
+#[wasm_bindgen]
+#[inline]
+pub fn get_formula_header(content: &str) -> Result<JsValue, JsValue> {
+    let header = parser::extract_formula_header(content)?;
+    serde_wasm_bindgen::to_value(&header)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Summarize a collection of formulas for registry dashboards
+///
+/// # Arguments
+/// * `formulas_json` - Array of formulas as JSON string
+///
+/// # Returns
+/// * `JsValue` - `{total, by_type, by_version, avg_step_count,
+///   avg_leg_count, formulas_with_vars, formulas_with_synthesis}`
+///
+/// # Performance
+/// Single pass; formulas are deserialized one at a time
+#[wasm_bindgen]
+pub fn summarize_formula_collection(formulas_json: &str) -> Result<JsValue, JsValue> {
+    let summary = parser::summarize_formula_collection_internal(formulas_json)
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    serde_wasm_bindgen::to_value(&summary)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Pack formulas into a ZIP archive blob
+///
+/// # Arguments
+/// * `formulas_json` - Array of formulas as JSON string
+///
+/// # Returns
+/// * `Uint8Array` - ZIP archive with one TOML file per formula
+#[wasm_bindgen]
+pub fn pack_formulas(formulas_json: &str) -> Result<Vec<u8>, JsValue> {
+    let formulas: Vec<Formula> = serde_json::from_str(formulas_json)
+        .map_err(|e| JsValue::from_str(&format!("Formulas parse error: {}", e)))?;
+
+    Ok(parser::pack_formula_archive(&formulas)?)
+}
+
+/// Unpack a ZIP archive blob into formulas
+///
+/// # Arguments
+/// * `bytes` - ZIP archive produced by `pack_formulas`
+///
+/// # Returns
+/// * `String` - Array of formulas as JSON string
+#[wasm_bindgen]
+pub fn unpack_formulas(bytes: &[u8]) -> Result<String, JsValue> {
+    let formulas = parser::unpack_formula_archive(bytes)?;
+
+    serde_json::to_string(&formulas)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Check raw TOML content for deprecated fields
+///
+/// # Arguments
+/// * `content` - TOML formula content
+///
+/// # Returns
+/// * `String` - Array of deprecation warnings as JSON string
+#[wasm_bindgen]
+pub fn check_deprecated_fields(content: &str) -> Result<String, JsValue> {
+    let warnings = parser::check_deprecated_fields(content);
+    serde_json::to_string(&warnings)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Identify the primary expansion variable of an Expansion formula
+///
+/// # Arguments
+/// * `formula_json` - Formula as JSON string
+///
+/// # Returns
+/// * `String` - Name of the expansion target var
+#[wasm_bindgen]
+pub fn get_expansion_target_var(formula_json: &str) -> Result<String, JsValue> {
+    let formula: Formula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+
+    lint::expansion_target_var(&formula)
+        .ok_or_else(|| JsValue::from_str("No expansion target var found"))
+}
+
+/// List all registered synthesis strategies
+///
+/// # Returns
+/// * `JsValue` - Sorted array of strategy names
+#[wasm_bindgen]
+pub fn get_synthesis_strategies() -> JsValue {
+    serde_wasm_bindgen::to_value(&lint::known_synthesis_strategies()).unwrap_or(JsValue::NULL)
+}
+
+/// Validate formula syntax
+///
+/// # Arguments
+/// * `content` - TOML formula content
+///
+/// # Returns
+/// * `bool` - True if valid
+///
+/// # Performance
+/// Faster than full parse (skips serialization)
+#[wasm_bindgen]
+#[inline(always)]
+pub fn validate_formula(content: &str) -> bool {
+    parser::validate_formula_impl(content)
+}
+
+/// Get formula type from TOML content
+///
+/// # Arguments
+/// * `content` - TOML formula content
+///
+/// # Returns
+/// * `String` - Formula type ("convoy", "workflow", "expansion", "aspect")
+///
+/// # Performance
+/// Uses fast extraction without full parsing when possible
+#[wasm_bindgen]
+#[inline]
+pub fn get_formula_type(content: &str) -> Result<String, JsValue> {
+    parser::get_formula_type_impl(content)
+}
+
+/// Generate the JSON Schema for a public type
+///
+/// # Arguments
+/// * `type_name` - One of `formula`, `cooked_formula`, `molecule`,
+///   `lint_warning`
+///
+/// # Returns
+/// * `String` - JSON Schema document as JSON string
+///
+/// Only available with the `schemars-support` feature (on by default in
+/// the WASM distribution).
+#[cfg(feature = "schemars-support")]
+#[wasm_bindgen]
+pub fn generate_json_schema(type_name: &str) -> Result<String, JsValue> {
+    generate_json_schema_internal(type_name).map_err(|e| JsValue::from_str(&e))
+}
+
+/// JSON Schema for the formula format
+///
+/// Emits a draft 2020-12 schema describing `Formula`, with `Step`,
+/// `Leg`, `Var`, and `Synthesis` under `$defs`, generated from the Rust
+/// types. External tools and editors can validate formulas against it
+/// without embedding the WASM module.
+///
+/// # Returns
+/// * `String` - JSON Schema document as JSON string
+///
+/// Only available with the `schemars-support` feature (on by default in
+/// the WASM distribution).
+#[cfg(feature = "schemars-support")]
+#[wasm_bindgen]
+pub fn get_formula_json_schema() -> Result<String, JsValue> {
+    generate_json_schema_internal("formula").map_err(|e| JsValue::from_str(&e))
+}
+
+/// Generate the JSON Schema for a public type (native entry point)
+#[cfg(feature = "schemars-support")]
+pub fn generate_json_schema_internal(type_name: &str) -> Result<String, String> {
+    let schema = match type_name {
+        "formula" => schemars::schema_for!(Formula),
+        "cooked_formula" => schemars::schema_for!(CookedFormula),
+        "molecule" => schemars::schema_for!(Molecule),
+        "lint_warning" => schemars::schema_for!(LintWarning),
+        other => {
+            return Err(format!(
+                "Unknown schema type '{}' (expected formula, cooked_formula, molecule, or lint_warning)",
+                other
+            ))
+        }
+    };
+
+    serde_json::to_string(&schema).map_err(|e| format!("Serialize error: {}", e))
+}
+
+/// Streaming parser handle for delimiter-separated formula bundles
+///
+/// Push chunks of a concatenated bundle (documents separated by a `---`
+/// line), then drain parsed formulas with `next_formula`. One JS->WASM
+/// call per chunk instead of per formula keeps boundary overhead flat
+/// for bundles of thousands of formulas.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct FormulaStream {
+    inner: parser::FormulaBundleStream,
+}
+
+#[wasm_bindgen]
+impl FormulaStream {
+    /// Create an empty stream
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> FormulaStream {
+        FormulaStream::default()
+    }
+
+    /// Feed the next chunk; chunk boundaries can fall anywhere
+    pub fn push(&mut self, chunk: &str) -> Result<(), JsValue> {
+        self.inner.push(chunk).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Signal end of input, parsing any trailing document
+    pub fn finish(&mut self) -> Result<(), JsValue> {
+        self.inner.finish().map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Drain the next parsed formula, or `null` when none are ready
+    pub fn next_formula(&mut self) -> Result<JsValue, JsValue> {
+        match self.inner.next_formula() {
+            Some(formula) => serde_wasm_bindgen::to_value(&formula)
+                .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e))),
+            None => Ok(JsValue::NULL),
+        }
+    }
+
+    /// Number of parsed formulas waiting to be drained
+    pub fn pending_count(&self) -> usize {
+        self.inner.pending_count()
+    }
+}
+
+/// Get performance metrics
+///
+/// Returns timing information for benchmarking
+#[wasm_bindgen]
+pub fn get_metrics() -> JsValue {
+    let metrics = serde_json::json!({
+        "version": "3.0.0-alpha.1",
+        "targets": {
+            "parse_toml_ms": 0.1,
+            "cook_formula_ms": 0.05,
+            "batch_100_ms": 1.0,
+            "generate_molecule_ms": 0.1
+        },
+        "optimizations": [
+            "fxhash",
+            "arena_allocation",
+            "memory_pools",
+            "inline_hot_paths",
+            "zero_copy_parsing",
+            "simd_patterns"
+        ],
+        "total_input_bytes": TOTAL_INPUT_BYTES.load(Ordering::Relaxed),
+        "total_output_bytes": TOTAL_OUTPUT_BYTES.load(Ordering::Relaxed)
+    });
+
+    serde_wasm_bindgen::to_value(&metrics).unwrap_or(JsValue::NULL)
+}
+
+/// Reset the cumulative throughput counters
+#[wasm_bindgen]
+pub fn reset_metrics() {
+    TOTAL_INPUT_BYTES.store(0, Ordering::Relaxed);
+    TOTAL_OUTPUT_BYTES.store(0, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_formula() {
+        let content = r#"
+formula = "test-workflow"
+description = "Test workflow"
+type = "workflow"
+version = 1
+
+[[steps]]
+id = "step1"
+title = "Step 1"
+description = "First step"
 "#;
-        let result = parse_formula(content);
+        let result = parser::parse_formula_internal(content);
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_io_byte_counters() {
+        let content = "formula = \"counter-test\"\ndescription = \"d\"\ntype = \"workflow\"\n";
+        let before_in = TOTAL_INPUT_BYTES.load(Ordering::Relaxed);
+        let formula = parser::parse_formula_internal(content).unwrap();
+        assert!(
+            TOTAL_INPUT_BYTES.load(Ordering::Relaxed) >= before_in + content.len() as u64
+        );
+
+        let formula_json = serde_json::to_string(&formula).unwrap();
+        let before_out = TOTAL_OUTPUT_BYTES.load(Ordering::Relaxed);
+        let cooked = cooker::cook_formula_impl(&formula_json, "{}").unwrap();
+        assert!(
+            TOTAL_OUTPUT_BYTES.load(Ordering::Relaxed) >= before_out + cooked.len() as u64
+        );
+    }
+
+    #[cfg(feature = "schemars-support")]
+    #[test]
+    fn test_generate_json_schema() {
+        let schema = generate_json_schema_internal("formula").unwrap();
+        let value: serde_json::Value = serde_json::from_str(&schema).unwrap();
+        assert!(value["properties"]["formula"].is_object());
+        assert!(value["properties"]["type"].is_object());
+
+        assert!(generate_json_schema_internal("not-a-type").is_err());
+
+        // The formula schema is self-contained: draft 2020-12 with the
+        // referenced component types under $defs
+        assert!(value["$schema"]
+            .as_str()
+            .unwrap()
+            .contains("2020-12"));
+        for component in ["Step", "Leg", "Var", "Synthesis"] {
+            assert!(value["$defs"][component].is_object(), "{}", component);
+        }
+    }
+
+    #[test]
+    fn test_formula_handle_parses_once() {
+        let content = concat!(
+            "formula = \"handled\"\n",
+            "description = \"Handle test\"\n",
+            "type = \"workflow\"\n",
+            "\n",
+            "[[steps]]\n",
+            "id = \"build\"\n",
+            "title = \"Build\"\n",
+            "description = \"Build it\"\n",
+        );
+        let handle = FormulaHandle::new(content).unwrap();
+        assert_eq!(handle.name(), "handled");
+        assert_eq!(handle.formula_type(), "workflow");
+        assert_eq!(handle.formula.steps.len(), 1);
+    }
+
     #[test]
     fn test_formula_types() {
         assert_eq!(
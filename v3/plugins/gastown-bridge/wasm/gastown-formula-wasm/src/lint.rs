@@ -0,0 +1,988 @@
+//! Formula Linter
+//!
+//! Non-fatal diagnostics for formulas that parse successfully but contain
+//! constructs that are likely mistakes (empty formulas, unknown strategies,
+//! etc). Lint results never block parsing or cooking.
+
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+use serde::{Deserialize, Serialize};
+use gastown_shared::FxHashSet;
+use crate::Formula;
+
+// Registered synthesis strategies (WASM is single-threaded, so a
+// thread-local set is effectively global)
+thread_local! {
+    static KNOWN_STRATEGIES: RefCell<FxHashSet<String>> = RefCell::new(
+        ["merge", "concat", "aggregate", "custom"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+    );
+}
+
+/// Register a synthesis strategy as known to the linter
+#[wasm_bindgen]
+pub fn register_synthesis_strategy(name: &str) {
+    KNOWN_STRATEGIES.with(|s| {
+        s.borrow_mut().insert(name.to_string());
+    });
+}
+
+/// Check whether a synthesis strategy has been registered
+#[inline]
+pub fn is_known_strategy(name: &str) -> bool {
+    KNOWN_STRATEGIES.with(|s| s.borrow().contains(name))
+}
+
+/// List all registered synthesis strategies (sorted for determinism)
+pub fn known_synthesis_strategies() -> Vec<String> {
+    let mut strategies: Vec<String> =
+        KNOWN_STRATEGIES.with(|s| s.borrow().iter().cloned().collect());
+    strategies.sort();
+    strategies
+}
+
+/// Lint severity level
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Hint,
+}
+
+/// A single lint finding
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
+pub struct LintWarning {
+    /// Stable rule code (e.g. "EmptyFormula")
+    pub code: String,
+    /// Stable numeric rule code (e.g. "GT001") for CI gating and editor
+    /// integrations; empty for custom rules without one
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub rule_code: String,
+    /// Human-readable message
+    pub message: String,
+    /// Severity of the finding
+    pub severity: Severity,
+}
+
+impl LintWarning {
+    #[inline]
+    pub fn new(code: &str, message: impl Into<String>, severity: Severity) -> Self {
+        Self {
+            code: code.to_string(),
+            rule_code: rule_code_for(code).unwrap_or("").to_string(),
+            message: message.into(),
+            severity,
+        }
+    }
+}
+
+/// Numeric rule code for a built-in rule name
+///
+/// These are append-only: codes never change meaning or get reused, so
+/// CI configurations and editor suppressions stay valid across releases.
+pub fn rule_code_for(code: &str) -> Option<&'static str> {
+    match code {
+        "DuplicateId" => Some("GT001"),
+        "EmptyFormula" => Some("GT002"),
+        "UnknownSynthesisStrategy" => Some("GT003"),
+        "OPTIONAL_VAR_NO_DEFAULT" => Some("GT004"),
+        "MissingDescription" => Some("GT005"),
+        "AspectWithoutVars" => Some("GT006"),
+        "AspectWithoutSynthesis" => Some("GT007"),
+        "AspectWithLegs" => Some("GT008"),
+        "ExpansionWithoutSteps" => Some("GT009"),
+        "ExpansionStepMissingTarget" => Some("GT010"),
+        "ExpansionTargetUndeclared" => Some("GT011"),
+        _ => None,
+    }
+}
+
+/// Configuration for `lint_formula_with_config`
+///
+/// Rules may be referenced by name (`DuplicateId`) or numeric code
+/// (`GT001`).
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LintConfig {
+    /// Rules to suppress entirely
+    #[serde(default)]
+    pub disabled: Vec<String>,
+    /// Per-rule severity overrides (e.g. promote a hint to an error for
+    /// CI gating)
+    #[serde(default)]
+    pub severity: std::collections::HashMap<String, Severity>,
+}
+
+impl LintConfig {
+    fn matches(reference: &str, warning: &LintWarning) -> bool {
+        reference == warning.code || (!warning.rule_code.is_empty() && reference == warning.rule_code)
+    }
+}
+
+/// Run the full validator with a lint configuration applied
+///
+/// Covers the standard lints plus duplicate id and missing description
+/// detection, then drops disabled rules and applies severity overrides.
+pub fn lint_formula_config_internal(formula: &Formula, config: &LintConfig) -> Vec<LintWarning> {
+    FormulaValidator::new()
+        .validate(formula)
+        .into_iter()
+        .filter(|warning| {
+            !config
+                .disabled
+                .iter()
+                .any(|reference| LintConfig::matches(reference, warning))
+        })
+        .map(|mut warning| {
+            for (reference, severity) in &config.severity {
+                if LintConfig::matches(reference, &warning) {
+                    warning.severity = *severity;
+                }
+            }
+            warning
+        })
+        .collect()
+}
+
+/// Lint a parsed formula, returning all findings
+pub fn lint_formula_internal(formula: &Formula) -> Vec<LintWarning> {
+    let mut warnings = Vec::new();
+
+    check_empty_formula(formula, &mut warnings);
+    check_synthesis_strategy(formula, &mut warnings);
+    check_optional_vars_without_default(formula, &mut warnings);
+    check_aspect_rules(formula, &mut warnings);
+    check_expansion_rules(formula, &mut warnings);
+
+    warnings
+}
+
+/// EmptyFormula: a formula with neither steps nor legs generates a molecule
+/// with zero beads, which is valid but almost certainly unintentional
+fn check_empty_formula(formula: &Formula, warnings: &mut Vec<LintWarning>) {
+    if formula.steps.is_empty() && formula.legs.is_empty() {
+        warnings.push(LintWarning::new(
+            "EmptyFormula",
+            format!(
+                "Formula '{}' has no steps and no legs; it will generate an empty molecule",
+                formula.name
+            ),
+            Severity::Hint,
+        ));
+    }
+}
+
+/// UnknownSynthesisStrategy: the strategy is free-text, but teams use a
+/// controlled vocabulary — anything unregistered is probably a typo
+fn check_synthesis_strategy(formula: &Formula, warnings: &mut Vec<LintWarning>) {
+    if let Some(synthesis) = &formula.synthesis {
+        if !is_known_strategy(&synthesis.strategy) {
+            warnings.push(LintWarning::new(
+                "UnknownSynthesisStrategy",
+                format!(
+                    "Synthesis strategy '{}' is not registered (known: {})",
+                    synthesis.strategy,
+                    known_synthesis_strategies().join(", ")
+                ),
+                Severity::Warning,
+            ));
+        }
+    }
+}
+
+/// OPTIONAL_VAR_NO_DEFAULT: an optional var with no default silently
+/// leaves `{{VAR}}` tokens in the output when the caller supplies no value
+fn check_optional_vars_without_default(formula: &Formula, warnings: &mut Vec<LintWarning>) {
+    let mut names: Vec<&String> = formula
+        .vars
+        .iter()
+        .filter(|(_, var)| !var.required && var.default.is_none())
+        .map(|(name, _)| name)
+        .collect();
+    names.sort();
+
+    for name in names {
+        warnings.push(LintWarning::new(
+            "OPTIONAL_VAR_NO_DEFAULT",
+            format!(
+                "Var '{}' is optional but has no default; unsupplied values leave '{{{{{}}}}}' \
+                 tokens in the output. Set required = true or add a default",
+                name, name
+            ),
+            Severity::Hint,
+        ));
+    }
+}
+
+/// Aspect formulas parametrize behavior and always produce output, so they
+/// need vars and a synthesis; they apply to workflows, not convoys, so
+/// legs make no sense on them
+fn check_aspect_rules(formula: &Formula, warnings: &mut Vec<LintWarning>) {
+    if formula.formula_type != crate::FormulaType::Aspect {
+        return;
+    }
+
+    if formula.vars.is_empty() {
+        warnings.push(LintWarning::new(
+            "AspectWithoutVars",
+            format!(
+                "Aspect formula '{}' must declare at least one var",
+                formula.name
+            ),
+            Severity::Error,
+        ));
+    }
+
+    if formula.synthesis.is_none() {
+        warnings.push(LintWarning::new(
+            "AspectWithoutSynthesis",
+            format!(
+                "Aspect formula '{}' must define a synthesis",
+                formula.name
+            ),
+            Severity::Error,
+        ));
+    }
+
+    if !formula.legs.is_empty() {
+        warnings.push(LintWarning::new(
+            "AspectWithLegs",
+            format!(
+                "Aspect formula '{}' must not have legs; aspects apply to workflows, not convoys",
+                formula.name
+            ),
+            Severity::Error,
+        ));
+    }
+}
+
+/// Conventional var name an Expansion formula expands over
+pub const EXPANSION_TARGET_VAR: &str = "EXPAND_TARGET";
+
+/// Identify the primary expansion variable of a formula
+///
+/// Prefers the conventional `EXPAND_TARGET` var if any step references it;
+/// otherwise falls back to the first declared var (sorted for determinism)
+/// that every step references.
+pub fn expansion_target_var(formula: &Formula) -> Option<String> {
+    let step_references = |var: &str| -> usize {
+        let token = format!("{{{{{}}}}}", var);
+        formula
+            .steps
+            .iter()
+            .filter(|s| s.title.contains(&token) || s.description.contains(&token))
+            .count()
+    };
+
+    if step_references(EXPANSION_TARGET_VAR) > 0 {
+        return Some(EXPANSION_TARGET_VAR.to_string());
+    }
+
+    let mut declared: Vec<&String> = formula.vars.keys().collect();
+    declared.sort();
+    declared
+        .into_iter()
+        .find(|var| !formula.steps.is_empty() && step_references(var) == formula.steps.len())
+        .cloned()
+}
+
+/// Expansion formulas expand into multiple steps at runtime, so they need
+/// steps, every step must reference the expansion target var, and that var
+/// must be declared
+fn check_expansion_rules(formula: &Formula, warnings: &mut Vec<LintWarning>) {
+    if formula.formula_type != crate::FormulaType::Expansion {
+        return;
+    }
+
+    if formula.steps.is_empty() {
+        warnings.push(LintWarning::new(
+            "ExpansionWithoutSteps",
+            format!(
+                "Expansion formula '{}' must have at least one step",
+                formula.name
+            ),
+            Severity::Error,
+        ));
+        return;
+    }
+
+    let target = expansion_target_var(formula)
+        .unwrap_or_else(|| EXPANSION_TARGET_VAR.to_string());
+    let token = format!("{{{{{}}}}}", target);
+
+    for step in &formula.steps {
+        if !step.title.contains(&token) && !step.description.contains(&token) {
+            warnings.push(LintWarning::new(
+                "ExpansionStepMissingTarget",
+                format!(
+                    "Step '{}' does not reference the expansion target '{}'",
+                    step.id, token
+                ),
+                Severity::Error,
+            ));
+        }
+    }
+
+    if !formula.vars.contains_key(&target) {
+        warnings.push(LintWarning::new(
+            "ExpansionTargetUndeclared",
+            format!(
+                "Expansion target var '{}' is not declared in [vars]",
+                target
+            ),
+            Severity::Error,
+        ));
+    }
+}
+
+/// SARIF level string for a severity
+///
+/// SARIF 2.1 has no "hint"; those map to `note`.
+fn sarif_level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Hint => "note",
+    }
+}
+
+/// Render lint findings as a SARIF 2.1 log
+///
+/// Produces one run with rule metadata derived from the findings and
+/// one result per finding, so code-scanning UIs can ingest formula
+/// diagnostics directly. Formulas carry no source spans, so every
+/// result's region covers the start of `file_uri`.
+pub fn lint_to_sarif(warnings: &[LintWarning], file_uri: &str) -> serde_json::Value {
+    let mut rules: Vec<serde_json::Value> = Vec::new();
+    let mut seen = FxHashSet::default();
+    for warning in warnings {
+        let id = if warning.rule_code.is_empty() {
+            &warning.code
+        } else {
+            &warning.rule_code
+        };
+        if seen.insert(id.clone()) {
+            rules.push(serde_json::json!({
+                "id": id,
+                "name": warning.code,
+                "defaultConfiguration": { "level": sarif_level(warning.severity) },
+            }));
+        }
+    }
+
+    let results: Vec<serde_json::Value> = warnings
+        .iter()
+        .map(|warning| {
+            let id = if warning.rule_code.is_empty() {
+                &warning.code
+            } else {
+                &warning.rule_code
+            };
+            serde_json::json!({
+                "ruleId": id,
+                "level": sarif_level(warning.severity),
+                "message": { "text": warning.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": file_uri },
+                        "region": { "startLine": 1, "startColumn": 1 },
+                    }
+                }],
+            })
+        })
+        .collect();
+
+    serde_json::json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "gastown-formula-lint",
+                    "informationUri": "https://github.com/stromfee/claude-flow",
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }],
+    })
+}
+
+/// Validation outcome for one formula in a batch
+#[derive(Debug, Clone, Serialize)]
+pub struct FormulaReport {
+    /// Position of the formula in the submitted array
+    pub index: usize,
+    /// True when the formula parsed and produced no error-severity findings
+    pub ok: bool,
+    /// Parse failure, when the content did not parse at all
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Lint findings from the full validator
+    pub warnings: Vec<LintWarning>,
+}
+
+/// Aggregated result of `validate_batch`
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchValidationReport {
+    /// Per-formula outcomes, in submission order
+    pub reports: Vec<FormulaReport>,
+    /// Formulas submitted
+    pub total: usize,
+    /// Formulas with at least one parse error or finding
+    pub affected: usize,
+    /// Parse failures plus error-severity findings
+    pub error_count: usize,
+    /// Warning-severity findings
+    pub warning_count: usize,
+    /// Hint-severity findings
+    pub hint_count: usize,
+}
+
+/// Validate a batch of formula documents in one call
+///
+/// Each entry is parsed and run through the full validator; a parse
+/// failure still yields a report entry rather than aborting the batch,
+/// so repo-wide hooks get one aggregated result instead of 800 calls.
+pub fn validate_batch_report_internal(contents: &[String]) -> BatchValidationReport {
+    let mut reports = Vec::with_capacity(contents.len());
+    let (mut error_count, mut warning_count, mut hint_count, mut affected) = (0, 0, 0, 0);
+
+    for (index, content) in contents.iter().enumerate() {
+        let report = match crate::parser::parse_formula_internal(content) {
+            Ok(formula) => {
+                let warnings = FormulaValidator::new().validate(&formula);
+                let errors = warnings
+                    .iter()
+                    .filter(|w| w.severity == Severity::Error)
+                    .count();
+                error_count += errors;
+                warning_count += warnings
+                    .iter()
+                    .filter(|w| w.severity == Severity::Warning)
+                    .count();
+                hint_count += warnings
+                    .iter()
+                    .filter(|w| w.severity == Severity::Hint)
+                    .count();
+                if !warnings.is_empty() {
+                    affected += 1;
+                }
+                FormulaReport {
+                    index,
+                    ok: errors == 0,
+                    error: None,
+                    warnings,
+                }
+            }
+            Err(error) => {
+                error_count += 1;
+                affected += 1;
+                FormulaReport {
+                    index,
+                    ok: false,
+                    error: Some(error),
+                    warnings: vec![],
+                }
+            }
+        };
+        reports.push(report);
+    }
+
+    BatchValidationReport {
+        total: contents.len(),
+        reports,
+        affected,
+        error_count,
+        warning_count,
+        hint_count,
+    }
+}
+
+/// WASM wrapper for `validate_batch_report_internal`
+#[inline]
+pub fn validate_batch_report_impl(contents_json: &str) -> Result<String, JsValue> {
+    let contents: Vec<String> = serde_json::from_str(contents_json)
+        .map_err(|e| JsValue::from_str(&format!("Contents parse error: {}", e)))?;
+
+    serde_json::to_string(&validate_batch_report_internal(&contents))
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// A single configurable validation rule
+///
+/// Custom rules let tooling extend validation beyond the built-in lints
+/// without patching this module.
+pub trait ValidationRule {
+    /// Check one formula, returning any findings
+    fn check(&self, formula: &Formula) -> Vec<LintWarning>;
+}
+
+/// BuiltinLints: the standard `lint_formula` checks as a rule
+struct BuiltinLints;
+
+impl ValidationRule for BuiltinLints {
+    fn check(&self, formula: &Formula) -> Vec<LintWarning> {
+        lint_formula_internal(formula)
+    }
+}
+
+/// DuplicateId: step and leg ids must be unique within the formula
+struct DuplicateIds;
+
+impl ValidationRule for DuplicateIds {
+    fn check(&self, formula: &Formula) -> Vec<LintWarning> {
+        let mut seen = FxHashSet::default();
+        let ids = formula
+            .steps
+            .iter()
+            .map(|s| s.id.as_str())
+            .chain(formula.legs.iter().map(|l| l.id.as_str()));
+
+        ids.filter(|id| !seen.insert(*id))
+            .map(|id| {
+                LintWarning::new(
+                    "DuplicateId",
+                    format!("Id '{}' is used by more than one step or leg", id),
+                    Severity::Error,
+                )
+            })
+            .collect()
+    }
+}
+
+/// MissingDescription: steps and legs with empty descriptions make
+/// molecules hard to follow in UIs
+struct MissingDescriptions;
+
+impl ValidationRule for MissingDescriptions {
+    fn check(&self, formula: &Formula) -> Vec<LintWarning> {
+        let step_ids = formula
+            .steps
+            .iter()
+            .filter(|s| s.description.trim().is_empty())
+            .map(|s| s.id.as_str());
+        let leg_ids = formula
+            .legs
+            .iter()
+            .filter(|l| l.description.trim().is_empty())
+            .map(|l| l.id.as_str());
+
+        step_ids
+            .chain(leg_ids)
+            .map(|id| {
+                LintWarning::new(
+                    "MissingDescription",
+                    format!("'{}' has an empty description", id),
+                    Severity::Hint,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Formula validator with configurable rules
+///
+/// `new()` registers the built-in rules (the standard lints, duplicate id
+/// detection, missing descriptions); callers chain `add_rule` to extend
+/// it with their own [`ValidationRule`] implementations.
+pub struct FormulaValidator {
+    rules: Vec<Box<dyn ValidationRule>>,
+}
+
+impl FormulaValidator {
+    pub fn new() -> Self {
+        Self {
+            rules: vec![
+                Box::new(BuiltinLints),
+                Box::new(DuplicateIds),
+                Box::new(MissingDescriptions),
+            ],
+        }
+    }
+
+    /// Add a custom rule (builder style)
+    pub fn add_rule(mut self, rule: impl ValidationRule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Run all rules against a formula
+    pub fn validate(&self, formula: &Formula) -> Vec<LintWarning> {
+        self.rules
+            .iter()
+            .flat_map(|rule| rule.check(formula))
+            .collect()
+    }
+}
+
+impl Default for FormulaValidator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FormulaType;
+    use std::collections::HashMap;
+
+    fn empty_formula() -> Formula {
+        Formula {
+            name: "empty".to_string(),
+            description: "No steps or legs".to_string(),
+            formula_type: FormulaType::Expansion,
+            version: 1,
+            legs: vec![],
+            synthesis: None,
+            steps: vec![],
+            vars: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_validator_builtin_rules() {
+        let mut formula = empty_formula();
+        formula.formula_type = FormulaType::Workflow;
+        formula.steps.push(expansion_step("build", "Build"));
+        formula.steps.push(expansion_step("build", "Build again"));
+        formula.steps.push(crate::Step {
+            id: "test".to_string(),
+            title: "Test".to_string(),
+            description: "   ".to_string(),
+            needs: vec![],
+            duration: None,
+            requires: vec![],
+            when: None,
+            foreach: None,
+        });
+
+        let warnings = FormulaValidator::new().validate(&formula);
+        assert!(warnings
+            .iter()
+            .any(|w| w.code == "DuplicateId" && w.message.contains("build")));
+        assert!(warnings
+            .iter()
+            .any(|w| w.code == "MissingDescription" && w.message.contains("test")));
+    }
+
+    #[test]
+    fn test_validator_custom_rule() {
+        struct NameLengthRule;
+        impl ValidationRule for NameLengthRule {
+            fn check(&self, formula: &Formula) -> Vec<LintWarning> {
+                if formula.name.len() > 4 {
+                    vec![LintWarning::new(
+                        "NameTooLong",
+                        format!("Formula name '{}' exceeds 4 characters", formula.name),
+                        Severity::Warning,
+                    )]
+                } else {
+                    vec![]
+                }
+            }
+        }
+
+        let warnings = FormulaValidator::new()
+            .add_rule(NameLengthRule)
+            .validate(&empty_formula());
+        assert!(warnings.iter().any(|w| w.code == "NameTooLong"));
+    }
+
+    #[test]
+    fn test_empty_formula_hint() {
+        let warnings = lint_formula_internal(&empty_formula());
+        assert!(warnings.iter().any(|w| w.code == "EmptyFormula" && w.severity == Severity::Hint));
+    }
+
+    #[test]
+    fn test_optional_var_without_default() {
+        let mut formula = empty_formula();
+        formula.vars.insert(
+            "region".to_string(),
+            crate::Var {
+                name: "region".to_string(),
+                ..Default::default()
+            },
+        );
+
+        let warnings = lint_formula_internal(&formula);
+        let hint = warnings
+            .iter()
+            .find(|w| w.code == "OPTIONAL_VAR_NO_DEFAULT")
+            .unwrap();
+        assert_eq!(hint.severity, Severity::Hint);
+        assert!(hint.message.contains("required = true"));
+        assert!(hint.message.contains("{{region}}"));
+
+        // A default (or required = true) silences the hint
+        formula.vars.get_mut("region").unwrap().default = Some("us-east-1".to_string());
+        let warnings = lint_formula_internal(&formula);
+        assert!(!warnings.iter().any(|w| w.code == "OPTIONAL_VAR_NO_DEFAULT"));
+    }
+
+    #[test]
+    fn test_valid_aspect_formula() {
+        let mut formula = empty_formula();
+        formula.formula_type = crate::FormulaType::Aspect;
+        formula.vars.insert(
+            "target".to_string(),
+            crate::Var {
+                name: "target".to_string(),
+                required: true,
+                ..Default::default()
+            },
+        );
+        formula.synthesis = Some(crate::Synthesis {
+            strategy: "merge".to_string(),
+            format: None,
+            description: None,
+        });
+
+        let warnings = lint_formula_internal(&formula);
+        assert!(!warnings.iter().any(|w| w.severity == Severity::Error));
+    }
+
+    #[test]
+    fn test_invalid_aspect_formula() {
+        let mut formula = empty_formula();
+        formula.formula_type = crate::FormulaType::Aspect;
+        formula.legs.push(crate::Leg {
+            id: "leg1".to_string(),
+            title: "Leg 1".to_string(),
+            focus: "f".to_string(),
+            description: "d".to_string(),
+            agent: None,
+            order: None,
+            when: None,
+            vars: std::collections::HashMap::new(),
+        });
+
+        let warnings = lint_formula_internal(&formula);
+        let errors: Vec<&str> = warnings
+            .iter()
+            .filter(|w| w.severity == Severity::Error)
+            .map(|w| w.code.as_str())
+            .collect();
+        assert!(errors.contains(&"AspectWithoutVars"));
+        assert!(errors.contains(&"AspectWithoutSynthesis"));
+        assert!(errors.contains(&"AspectWithLegs"));
+    }
+
+    fn expansion_step(id: &str, title: &str) -> crate::Step {
+        crate::Step {
+            id: id.to_string(),
+            title: title.to_string(),
+            description: "d".to_string(),
+            needs: vec![],
+            duration: None,
+            requires: vec![],
+            when: None,
+            foreach: None,
+        }
+    }
+
+    #[test]
+    fn test_expansion_rules() {
+        let mut formula = empty_formula();
+        formula.formula_type = crate::FormulaType::Expansion;
+
+        // No steps at all
+        let warnings = lint_formula_internal(&formula);
+        assert!(warnings.iter().any(|w| w.code == "ExpansionWithoutSteps"));
+
+        // Step referencing the target, but the var is not declared
+        formula
+            .steps
+            .push(expansion_step("expand", "Process {{EXPAND_TARGET}}"));
+        let warnings = lint_formula_internal(&formula);
+        assert!(warnings.iter().any(|w| w.code == "ExpansionTargetUndeclared"));
+
+        // Declare the var, add a step that misses the target
+        formula.vars.insert(
+            "EXPAND_TARGET".to_string(),
+            crate::Var {
+                name: "EXPAND_TARGET".to_string(),
+                required: true,
+                ..Default::default()
+            },
+        );
+        formula.steps.push(expansion_step("other", "No reference"));
+        let warnings = lint_formula_internal(&formula);
+        assert!(warnings
+            .iter()
+            .any(|w| w.code == "ExpansionStepMissingTarget" && w.message.contains("other")));
+
+        // Fully valid expansion
+        formula.steps[1].title = "Also process {{EXPAND_TARGET}}".to_string();
+        let warnings = lint_formula_internal(&formula);
+        assert!(!warnings.iter().any(|w| w.severity == Severity::Error));
+    }
+
+    #[test]
+    fn test_expansion_target_var() {
+        let mut formula = empty_formula();
+        formula.formula_type = crate::FormulaType::Expansion;
+        formula
+            .steps
+            .push(expansion_step("expand", "Process {{EXPAND_TARGET}}"));
+        assert_eq!(
+            expansion_target_var(&formula),
+            Some("EXPAND_TARGET".to_string())
+        );
+
+        // Custom expansion var referenced by every step
+        formula.steps[0].title = "Process {{item}}".to_string();
+        formula.vars.insert(
+            "item".to_string(),
+            crate::Var {
+                name: "item".to_string(),
+                required: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(expansion_target_var(&formula), Some("item".to_string()));
+    }
+
+    #[test]
+    fn test_unknown_synthesis_strategy() {
+        let mut formula = empty_formula();
+        formula.synthesis = Some(crate::Synthesis {
+            strategy: "mrege".to_string(),
+            format: None,
+            description: None,
+        });
+        let warnings = lint_formula_internal(&formula);
+        assert!(warnings
+            .iter()
+            .any(|w| w.code == "UnknownSynthesisStrategy" && w.severity == Severity::Warning));
+
+        formula.synthesis.as_mut().unwrap().strategy = "merge".to_string();
+        let warnings = lint_formula_internal(&formula);
+        assert!(!warnings.iter().any(|w| w.code == "UnknownSynthesisStrategy"));
+    }
+
+    #[test]
+    fn test_register_synthesis_strategy() {
+        assert!(!is_known_strategy("blend"));
+        register_synthesis_strategy("blend");
+        assert!(is_known_strategy("blend"));
+        assert!(known_synthesis_strategies().contains(&"blend".to_string()));
+    }
+
+    #[test]
+    fn test_non_empty_formula_no_hint() {
+        let mut formula = empty_formula();
+        formula.steps.push(crate::Step {
+            id: "step1".to_string(),
+            title: "Step 1".to_string(),
+            description: "First step".to_string(),
+            needs: vec![],
+            duration: None,
+            requires: vec![],
+            when: None,
+            foreach: None,
+        });
+        let warnings = lint_formula_internal(&formula);
+        assert!(!warnings.iter().any(|w| w.code == "EmptyFormula"));
+    }
+
+    #[test]
+    fn test_lint_config_disables_and_overrides() {
+        let formula = empty_formula();
+
+        // EmptyFormula (GT002) fires as a hint by default
+        let findings = lint_formula_config_internal(&formula, &LintConfig::default());
+        let empty = findings.iter().find(|w| w.code == "EmptyFormula").unwrap();
+        assert_eq!(empty.rule_code, "GT002");
+        assert_eq!(empty.severity, Severity::Hint);
+
+        // Severity overrides accept the numeric code
+        let config = LintConfig {
+            disabled: vec![],
+            severity: [("GT002".to_string(), Severity::Error)].into_iter().collect(),
+        };
+        let findings = lint_formula_config_internal(&formula, &config);
+        let empty = findings.iter().find(|w| w.code == "EmptyFormula").unwrap();
+        assert_eq!(empty.severity, Severity::Error);
+
+        // Disabling by name suppresses the finding
+        let config = LintConfig {
+            disabled: vec!["EmptyFormula".to_string()],
+            severity: Default::default(),
+        };
+        let findings = lint_formula_config_internal(&formula, &config);
+        assert!(!findings.iter().any(|w| w.code == "EmptyFormula"));
+    }
+
+    #[test]
+    fn test_validate_batch() {
+        let valid = concat!(
+            "formula = \"good\"\n",
+            "description = \"Valid workflow\"\n",
+            "type = \"workflow\"\n",
+            "\n",
+            "[[steps]]\n",
+            "id = \"build\"\n",
+            "title = \"Build\"\n",
+            "description = \"Build it\"\n",
+        );
+        // Parses, but has no steps or legs (hint) and a step with an
+        // empty description would be a hint too
+        let hinted = concat!(
+            "formula = \"sparse\"\n",
+            "description = \"No steps\"\n",
+            "type = \"workflow\"\n",
+        );
+        let broken = "formula = ";
+
+        let report = validate_batch_report_internal(&[
+            valid.to_string(),
+            hinted.to_string(),
+            broken.to_string(),
+        ]);
+
+        assert_eq!(report.total, 3);
+        assert_eq!(report.affected, 2);
+        assert_eq!(report.error_count, 1);
+        assert_eq!(report.hint_count, 1);
+
+        assert!(report.reports[0].ok);
+        assert!(report.reports[0].warnings.is_empty());
+        assert!(report.reports[1].ok);
+        assert_eq!(report.reports[1].warnings[0].code, "EmptyFormula");
+        assert!(!report.reports[2].ok);
+        assert!(report.reports[2].error.is_some());
+    }
+
+    #[test]
+    fn test_lint_to_sarif() {
+        let findings = lint_formula_config_internal(&empty_formula(), &LintConfig::default());
+        let sarif = lint_to_sarif(&findings, "formulas/empty.formula.toml");
+
+        assert_eq!(sarif["version"], "2.1.0");
+        let run = &sarif["runs"][0];
+        assert_eq!(run["tool"]["driver"]["name"], "gastown-formula-lint");
+
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), findings.len());
+        let empty = results
+            .iter()
+            .find(|r| r["ruleId"] == "GT002")
+            .unwrap();
+        assert_eq!(empty["level"], "note");
+        assert_eq!(
+            empty["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+            "formulas/empty.formula.toml"
+        );
+
+        // Rule metadata is emitted once per rule
+        let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+        assert!(rules.iter().any(|r| r["id"] == "GT002" && r["name"] == "EmptyFormula"));
+    }
+}
@@ -5,11 +5,15 @@
 
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
-use crate::CookedFormula;
+use crate::{CookedFormula, Formula, FormulaType};
 
 /// A molecule bead definition
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
 pub struct MoleculeBead {
+    /// Stable bead identifier (from step/leg id)
+    #[serde(default)]
+    pub id: String,
     /// Bead title (from step/leg)
     pub title: String,
     /// Bead description
@@ -22,21 +26,216 @@ pub struct MoleculeBead {
     pub duration: Option<u32>,
     /// Required capabilities
     pub requires: Vec<String>,
+    /// Tooling metadata attached by post-processing tools (execution
+    /// trackers, cost estimators); not produced by generation itself
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub metadata: std::collections::HashMap<String, serde_json::Value>,
+    /// Execution tier: beads at the same dependency depth share a tier
+    /// and can run in parallel; sources are always tier 0
+    #[serde(default)]
+    pub tier: u32,
+    /// 0-indexed position of this bead within its tier
+    #[serde(default)]
+    pub tier_position: u32,
+    /// Stable content hash covering this bead's cooked content and its
+    /// dependency closure; identical across regenerations of an
+    /// unchanged formula
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub content_hash: String,
+    /// Agent assigned to execute this bead (from `Leg.agent` or an
+    /// assignment strategy); not part of the content hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
 }
 
 /// A molecule definition (chain of beads)
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
 pub struct Molecule {
+    /// Stable molecule identifier (defaults to the formula name)
+    #[serde(default)]
+    pub id: String,
     /// Formula name this molecule was generated from
     pub formula_name: String,
-    /// Formula type
-    pub formula_type: String,
+    /// Formula type of the source formula, so molecule consumers can
+    /// tell steps (workflow) from legs (convoy) without the original
+    pub formula_type: FormulaType,
     /// Ordered list of beads
     pub beads: Vec<MoleculeBead>,
+    /// Number of beads in the molecule
+    pub bead_count: usize,
     /// Whether the molecule has cycles (should be false)
     pub has_cycle: bool,
     /// Topological order of bead indices
     pub execution_order: Vec<usize>,
+    /// Parallel execution waves: wave N holds the indices of beads whose
+    /// dependencies all complete in earlier waves, so the orchestrator
+    /// can schedule each wave concurrently without re-deriving it from
+    /// the dependency edges
+    #[serde(default)]
+    pub waves: Vec<Vec<usize>>,
+    /// Bead indices along the longest-duration dependency chain, in
+    /// execution order; beads without a declared duration count as 0
+    #[serde(default)]
+    pub critical_path: Vec<usize>,
+    /// Estimated minutes per wave (the longest bead in each wave, since
+    /// a wave runs in parallel)
+    #[serde(default)]
+    pub wave_durations: Vec<u32>,
+    /// Estimated minutes to complete the whole molecule: the critical
+    /// path total, assuming each bead starts as soon as its
+    /// dependencies finish
+    #[serde(default)]
+    pub estimated_duration: u32,
+    /// Stable content hash combining the bead hashes, so an unchanged
+    /// formula regenerates to an identical hash
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub content_hash: String,
+}
+
+impl Molecule {
+    /// Number of beads in the molecule
+    pub fn len(&self) -> usize {
+        self.beads.len()
+    }
+
+    /// True when the molecule has no beads
+    pub fn is_empty(&self) -> bool {
+        self.beads.is_empty()
+    }
+
+    /// Bead ids in definition order
+    pub fn bead_ids(&self) -> impl Iterator<Item = &str> {
+        self.beads.iter().map(|b| b.id.as_str())
+    }
+
+    /// Look up a bead by id
+    pub fn find_bead(&self, id: &str) -> Option<&MoleculeBead> {
+        self.beads.iter().find(|b| b.id == id)
+    }
+}
+
+/// Errors produced while generating molecules
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum MoleculeError {
+    /// `needs` edges form a dependency cycle; `cycle` lists the bead ids
+    /// along the path with the first id repeated to close the loop
+    DependencyCycle { cycle: Vec<String> },
+}
+
+impl std::fmt::Display for MoleculeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MoleculeError::DependencyCycle { cycle } => {
+                write!(f, "Dependency cycle: {}", cycle.join(" -> "))
+            }
+        }
+    }
+}
+
+impl From<MoleculeError> for JsValue {
+    fn from(err: MoleculeError) -> Self {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
+/// Options controlling molecule generation
+///
+/// `Default` matches the plain `generate_molecule` behavior.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MoleculeOptions {
+    /// Override the derived molecule id (e.g. for content-addressed
+    /// storage); must be a valid slug
+    #[serde(default)]
+    pub molecule_id: Option<String>,
+    /// Assign agents to unassigned beads after generation
+    #[serde(default)]
+    pub assignment: Option<AssignmentOptions>,
+}
+
+/// How unassigned beads get an agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssignmentStrategy {
+    /// Cycle through the roster in bead order
+    RoundRobin,
+    /// First roster agent whose capabilities cover the bead's
+    /// `requires`; beads no agent can cover stay unassigned
+    Capability,
+    /// Agent with the least assigned minutes relative to its capacity;
+    /// beads without a duration count as one minute
+    LoadWeighted,
+}
+
+/// One agent available for assignment
+#[derive(Debug, Clone, Deserialize)]
+pub struct AgentProfile {
+    /// Agent name written into `MoleculeBead.agent`
+    pub name: String,
+    /// Capabilities this agent offers, matched against bead `requires`
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// Relative capacity for load weighting (default 1)
+    #[serde(default = "default_capacity")]
+    pub capacity: u32,
+}
+
+fn default_capacity() -> u32 {
+    1
+}
+
+/// Agent assignment configuration for `MoleculeOptions`
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssignmentOptions {
+    /// Strategy deciding which roster agent each bead gets
+    pub strategy: AssignmentStrategy,
+    /// Agents available for assignment, in preference order
+    #[serde(default)]
+    pub roster: Vec<AgentProfile>,
+}
+
+/// Assign agents to beads that have none, per the configured strategy
+///
+/// Beads with an agent already set (convoy legs carrying `Leg.agent`)
+/// are left alone. An empty roster is a no-op.
+fn assign_agents(beads: &mut [MoleculeBead], assignment: &AssignmentOptions) {
+    if assignment.roster.is_empty() {
+        return;
+    }
+
+    match assignment.strategy {
+        AssignmentStrategy::RoundRobin => {
+            for (next, bead) in beads.iter_mut().filter(|b| b.agent.is_none()).enumerate() {
+                bead.agent = Some(assignment.roster[next % assignment.roster.len()].name.clone());
+            }
+        }
+        AssignmentStrategy::Capability => {
+            for bead in beads.iter_mut().filter(|b| b.agent.is_none()) {
+                let capable = assignment.roster.iter().find(|agent| {
+                    bead.requires
+                        .iter()
+                        .all(|requirement| agent.capabilities.contains(requirement))
+                });
+                bead.agent = capable.map(|agent| agent.name.clone());
+            }
+        }
+        AssignmentStrategy::LoadWeighted => {
+            let mut load = vec![0u64; assignment.roster.len()];
+            for bead in beads.iter_mut().filter(|b| b.agent.is_none()) {
+                // Least load per unit of capacity, compared without
+                // floats: load_a / cap_a < load_b / cap_b
+                let pick = (0..assignment.roster.len())
+                    .min_by_key(|&i| {
+                        let capacity = u64::from(assignment.roster[i].capacity.max(1));
+                        (load[i].saturating_mul(1000) / capacity, i)
+                    })
+                    .unwrap_or(0);
+                load[pick] += u64::from(bead.duration.unwrap_or(1).max(1));
+                bead.agent = Some(assignment.roster[pick].name.clone());
+            }
+        }
+    }
 }
 
 /// Generate a molecule from a cooked formula
@@ -44,73 +243,1249 @@ pub fn generate_molecule_impl(formula_json: &str) -> Result<String, JsValue> {
     let cooked: CookedFormula = serde_json::from_str(formula_json)
         .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
 
-    let molecule = generate_molecule_internal(&cooked)?;
+    let molecule = generate_molecule_internal(&cooked)?;
+
+    serde_json::to_string(&molecule)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Generate a molecule, returning a structured `JsValue`
+///
+/// Like `generate_molecule_impl`, but serializes straight into a JS
+/// object via `serde_wasm_bindgen`, skipping the JSON-string round trip.
+/// Prefer this for large formulas; the string path only wins when the
+/// caller wants JSON text anyway.
+pub fn generate_molecule_js_impl(formula_json: &str) -> Result<JsValue, JsValue> {
+    let cooked: CookedFormula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+
+    let molecule = generate_molecule_internal(&cooked)?;
+
+    serde_wasm_bindgen::to_value(&molecule)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Generate a molecule after yielding once to the JS event loop
+///
+/// Lets callers schedule large molecule builds without blocking the
+/// current microtask; the generation itself runs to completion once it
+/// starts.
+pub async fn generate_molecule_async_impl(formula_json: &str) -> Result<String, JsValue> {
+    crate::cooker::yield_to_event_loop().await;
+    generate_molecule_impl(formula_json)
+}
+
+/// Generate a molecule with generation options
+pub fn generate_molecule_opts_impl(
+    formula_json: &str,
+    options_json: &str,
+) -> Result<String, JsValue> {
+    let cooked: CookedFormula = serde_json::from_str(formula_json)
+        .map_err(|e| JsValue::from_str(&format!("Formula parse error: {}", e)))?;
+
+    let options: MoleculeOptions = serde_json::from_str(options_json)
+        .map_err(|e| JsValue::from_str(&format!("Options parse error: {}", e)))?;
+
+    let mut molecule = generate_molecule_internal(&cooked)?;
+
+    if let Some(id) = &options.molecule_id {
+        if !crate::parser::is_valid_slug(id) {
+            return Err(JsValue::from_str(&format!(
+                "Molecule id '{}' is not a valid slug",
+                id
+            )));
+        }
+        molecule.id = id.clone();
+    }
+
+    if let Some(assignment) = &options.assignment {
+        assign_agents(&mut molecule.beads, assignment);
+    }
+
+    serde_json::to_string(&molecule)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Internal molecule generation
+fn generate_molecule_internal(cooked: &CookedFormula) -> Result<Molecule, MoleculeError> {
+    generate_molecule_for(&cooked.formula)
+}
+
+/// Generate a molecule straight from a parsed formula
+///
+/// The cooked wrapper adds nothing to generation, so handles holding a
+/// bare `Formula` can generate without fabricating one.
+pub(crate) fn generate_molecule_for(formula: &Formula) -> Result<Molecule, MoleculeError> {
+
+    let mut beads = Vec::new();
+    let mut id_to_index: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+
+    // Generate beads from steps (for workflow type)
+    if !formula.steps.is_empty() {
+        for (i, step) in formula.steps.iter().enumerate() {
+            id_to_index.insert(step.id.clone(), i);
+            beads.push(MoleculeBead {
+                id: step.id.clone(),
+                title: step.title.clone(),
+                description: step.description.clone(),
+                labels: vec!["molecule".to_string(), formula.name.clone()],
+                depends_on: vec![], // Will be filled after all beads are created
+                duration: step.duration,
+                requires: step.requires.clone(),
+                metadata: std::collections::HashMap::new(),
+                tier: 0, // Filled by compute_tiers below
+                tier_position: 0,
+                content_hash: String::new(), // Filled by compute_content_hashes below
+                agent: None,
+            });
+        }
+
+        // Wire dependencies
+        for (i, step) in formula.steps.iter().enumerate() {
+            let deps: Vec<usize> = step.needs.iter()
+                .filter_map(|need| id_to_index.get(need).copied())
+                .collect();
+            beads[i].depends_on = deps;
+        }
+    }
+
+    // Generate beads from legs (for convoy type)
+    if !formula.legs.is_empty() {
+        for (i, leg) in formula.legs.iter().enumerate() {
+            id_to_index.insert(leg.id.clone(), i);
+            beads.push(MoleculeBead {
+                id: leg.id.clone(),
+                title: leg.title.clone(),
+                description: leg.description.clone(),
+                labels: vec!["molecule".to_string(), "convoy".to_string(), formula.name.clone()],
+                depends_on: if i > 0 { vec![i - 1] } else { vec![] }, // Sequential by default
+                duration: None,
+                requires: vec![],
+                metadata: std::collections::HashMap::new(),
+                tier: 0, // Filled by compute_tiers below
+                tier_position: 0,
+                content_hash: String::new(), // Filled by compute_content_hashes below
+                agent: leg.agent.clone(),
+            });
+        }
+    }
+
+    // A dependency cycle would deadlock the executor downstream; fail
+    // generation with the offending path instead of emitting the beads
+    if let Some(cycle) = find_cycle(&beads) {
+        return Err(MoleculeError::DependencyCycle {
+            cycle: cycle.iter().map(|&i| beads[i].id.clone()).collect(),
+        });
+    }
+
+    // Compute execution order (topological sort)
+    let (execution_order, has_cycle) = topological_sort(&beads);
+
+    // Assign execution tiers from the dependency depths
+    for (i, (tier, position)) in compute_tiers(&beads).into_iter().enumerate() {
+        beads[i].tier = tier;
+        beads[i].tier_position = position;
+    }
+
+    let waves = compute_waves(&beads);
+    let wave_durations = compute_wave_durations(&beads, &waves);
+    let (critical_path, estimated_duration) = compute_critical_path(&beads);
+    let content_hash = compute_content_hashes(&mut beads, &execution_order);
+
+    Ok(Molecule {
+        id: formula.name.clone(),
+        formula_name: formula.name.clone(),
+        formula_type: formula.formula_type.clone(),
+        bead_count: beads.len(),
+        beads,
+        has_cycle,
+        execution_order,
+        waves,
+        critical_path,
+        wave_durations,
+        estimated_duration,
+        content_hash,
+    })
+}
+
+/// Fill each bead's `content_hash` and return the molecule-level hash
+///
+/// A bead's hash covers its cooked content (id, title, description,
+/// duration, requires) plus the hashes of the beads it depends on, so
+/// it changes exactly when the bead or its dependency closure changes.
+/// The molecule hash combines the bead hashes order-independently.
+/// Beads are hashed along `execution_order`, so callers must not pass a
+/// cyclic graph.
+fn compute_content_hashes(beads: &mut [MoleculeBead], execution_order: &[usize]) -> String {
+    use std::hash::Hasher;
+
+    let mut bead_hashes = vec![0u64; beads.len()];
+    for &i in execution_order {
+        let bead = &beads[i];
+        let mut hasher = rustc_hash::FxHasher::default();
+        hasher.write(bead.id.as_bytes());
+        // Separators so adjacent fields hash independently
+        hasher.write_u8(0);
+        hasher.write(bead.title.as_bytes());
+        hasher.write_u8(0);
+        hasher.write(bead.description.as_bytes());
+        hasher.write_u8(0);
+        hasher.write_u32(bead.duration.map_or(u32::MAX, |d| d));
+        for requirement in &bead.requires {
+            hasher.write(requirement.as_bytes());
+            hasher.write_u8(0);
+        }
+        // Sorted so the hash is independent of edge declaration order
+        let mut dep_hashes: Vec<u64> = bead
+            .depends_on
+            .iter()
+            .map(|&dep| bead_hashes[dep])
+            .collect();
+        dep_hashes.sort_unstable();
+        for dep_hash in dep_hashes {
+            hasher.write_u64(dep_hash);
+        }
+        bead_hashes[i] = hasher.finish();
+    }
+
+    for (i, bead) in beads.iter_mut().enumerate() {
+        bead.content_hash = format!("{:016x}", bead_hashes[i]);
+    }
+
+    let mut hasher = rustc_hash::FxHasher::default();
+    bead_hashes.sort_unstable();
+    for bead_hash in bead_hashes {
+        hasher.write_u64(bead_hash);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+/// Find a dependency cycle among the beads, if any
+///
+/// Depth-first search over `depends_on` edges; returns the bead indices
+/// along the first cycle found, with the entry index repeated at the
+/// end to close the loop.
+fn find_cycle(beads: &[MoleculeBead]) -> Option<Vec<usize>> {
+    // 0 = unvisited, 1 = on the current path, 2 = fully explored
+    fn visit(
+        i: usize,
+        beads: &[MoleculeBead],
+        color: &mut [u8],
+        path: &mut Vec<usize>,
+    ) -> Option<Vec<usize>> {
+        color[i] = 1;
+        path.push(i);
+        for &dep in &beads[i].depends_on {
+            if dep >= beads.len() {
+                continue;
+            }
+            if color[dep] == 1 {
+                let start = path.iter().position(|&node| node == dep)?;
+                let mut cycle = path[start..].to_vec();
+                cycle.push(dep);
+                return Some(cycle);
+            }
+            if color[dep] == 0 {
+                if let Some(cycle) = visit(dep, beads, color, path) {
+                    return Some(cycle);
+                }
+            }
+        }
+        path.pop();
+        color[i] = 2;
+        None
+    }
+
+    let mut color = vec![0u8; beads.len()];
+    let mut path = Vec::new();
+    for start in 0..beads.len() {
+        if color[start] == 0 {
+            if let Some(cycle) = visit(start, beads, &mut color, &mut path) {
+                return Some(cycle);
+            }
+        }
+    }
+    None
+}
+
+/// Find beads that are not on any source-to-sink execution path
+///
+/// Two-pass reachability analysis: forward from sources (beads with no
+/// dependencies) and backward from sinks (beads nothing depends on).
+/// Beads not reachable from both directions are unreachable in the
+/// execution sense.
+pub fn find_unreachable_beads(mol: &Molecule) -> Vec<String> {
+    let n = mol.beads.len();
+    if n == 0 {
+        return vec![];
+    }
+
+    // Successor lists (dependency edges point from dep to dependent)
+    let mut successors: Vec<Vec<usize>> = vec![vec![]; n];
+    for (i, bead) in mol.beads.iter().enumerate() {
+        for &dep in &bead.depends_on {
+            if dep < n {
+                successors[dep].push(i);
+            }
+        }
+    }
+
+    // Forward pass: everything reachable from sources
+    let mut forward = vec![false; n];
+    let mut stack: Vec<usize> = (0..n).filter(|&i| mol.beads[i].depends_on.is_empty()).collect();
+    while let Some(i) = stack.pop() {
+        if forward[i] {
+            continue;
+        }
+        forward[i] = true;
+        stack.extend(successors[i].iter().copied());
+    }
+
+    // Backward pass: everything that can reach a sink
+    let mut backward = vec![false; n];
+    let mut stack: Vec<usize> = (0..n).filter(|&i| successors[i].is_empty()).collect();
+    while let Some(i) = stack.pop() {
+        if backward[i] {
+            continue;
+        }
+        backward[i] = true;
+        stack.extend(mol.beads[i].depends_on.iter().copied().filter(|&d| d < n));
+    }
+
+    mol.beads
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !(forward[*i] && backward[*i]))
+        .map(|(_, bead)| bead.id.clone())
+        .collect()
+}
+
+/// Find a dependency cycle in the bead graph, if any
+///
+/// The `depends_on` graph should be a DAG, but manual molecule
+/// construction or migration bugs can introduce cycles. Returns the
+/// cycle path as bead ids in dependency order, with the starting bead
+/// repeated at the end (e.g. `["A", "B", "C", "A"]`), or `None` when the
+/// graph is acyclic.
+pub fn detect_bead_cycles(mol: &Molecule) -> Option<Vec<String>> {
+    let n = mol.beads.len();
+
+    // DFS colors: 0 = unvisited, 1 = on the current path, 2 = done
+    let mut color = vec![0u8; n];
+    let mut parent = vec![usize::MAX; n];
+
+    for start in 0..n {
+        if color[start] != 0 {
+            continue;
+        }
+        // Iterative DFS along depends_on edges; (node, next dep index)
+        let mut stack = vec![(start, 0usize)];
+        color[start] = 1;
+        while let Some(frame) = stack.last_mut() {
+            let node = frame.0;
+            if let Some(&dep) = mol.beads[node].depends_on.get(frame.1) {
+                frame.1 += 1;
+                if dep >= n {
+                    continue;
+                }
+                match color[dep] {
+                    0 => {
+                        color[dep] = 1;
+                        parent[dep] = node;
+                        stack.push((dep, 0));
+                    }
+                    1 => {
+                        // Back edge: walk parents from `node` back to `dep`
+                        let mut path = vec![dep];
+                        let mut cursor = node;
+                        while cursor != dep {
+                            path.push(cursor);
+                            cursor = parent[cursor];
+                        }
+                        path.push(dep);
+                        path.reverse();
+                        return Some(
+                            path.into_iter()
+                                .map(|i| mol.beads[i].id.clone())
+                                .collect(),
+                        );
+                    }
+                    _ => {}
+                }
+            } else {
+                color[node] = 2;
+                stack.pop();
+            }
+        }
+    }
+
+    None
+}
+
+/// Split a molecule into connected chunks of bounded size
+///
+/// Chunks are built per weakly-connected component, sliced along the
+/// topological order so earlier beads land in earlier chunks. Dependencies
+/// that cross a chunk boundary are dropped (the chunk ordering itself
+/// preserves them), so every chunk is a self-contained sub-graph. The
+/// split is deterministic for a given input.
+///
+/// `max_beads_per_chunk == 0` yields no chunks.
+pub fn split_molecule(mol: &Molecule, max_beads_per_chunk: usize) -> Vec<Molecule> {
+    if mol.beads.is_empty() || max_beads_per_chunk == 0 {
+        return vec![];
+    }
+    let n = mol.beads.len();
+
+    // Undirected adjacency for component discovery
+    let mut adjacent: Vec<Vec<usize>> = vec![vec![]; n];
+    for (i, bead) in mol.beads.iter().enumerate() {
+        for &dep in &bead.depends_on {
+            if dep < n {
+                adjacent[i].push(dep);
+                adjacent[dep].push(i);
+            }
+        }
+    }
+
+    // Position of each bead in the topological order; beads stuck in a
+    // cycle keep their index order at the end
+    let (topo, _) = topological_sort(&mol.beads);
+    let mut position: Vec<usize> = vec![usize::MAX; n];
+    for (pos, &i) in topo.iter().enumerate() {
+        position[i] = pos;
+    }
+
+    // Weakly-connected components, discovered in ascending index order
+    // for determinism
+    let mut component = vec![usize::MAX; n];
+    let mut components: Vec<Vec<usize>> = Vec::new();
+    for start in 0..n {
+        if component[start] != usize::MAX {
+            continue;
+        }
+        let id = components.len();
+        let mut members = Vec::new();
+        let mut stack = vec![start];
+        while let Some(i) = stack.pop() {
+            if component[i] != usize::MAX {
+                continue;
+            }
+            component[i] = id;
+            members.push(i);
+            stack.extend(adjacent[i].iter().copied());
+        }
+        members.sort_by_key(|&i| (position[i], i));
+        components.push(members);
+    }
+
+    // Slice each component along the topological order
+    let mut chunks = Vec::new();
+    for members in &components {
+        for slice in members.chunks(max_beads_per_chunk) {
+            let new_index: gastown_shared::FxHashMap<usize, usize> = slice
+                .iter()
+                .enumerate()
+                .map(|(new, &old)| (old, new))
+                .collect();
+
+            let beads: Vec<MoleculeBead> = slice
+                .iter()
+                .map(|&old| {
+                    let mut bead = mol.beads[old].clone();
+                    bead.depends_on = bead
+                        .depends_on
+                        .iter()
+                        .filter_map(|dep| new_index.get(dep).copied())
+                        .collect();
+                    bead
+                })
+                .collect();
+
+            let (execution_order, has_cycle) = topological_sort(&beads);
+
+            // Dependencies were rewritten for the chunk, so tiers and
+            // waves must be re-derived from the new edges
+            let mut beads = beads;
+            for (i, (tier, position)) in compute_tiers(&beads).into_iter().enumerate() {
+                beads[i].tier = tier;
+                beads[i].tier_position = position;
+            }
+            let waves = compute_waves(&beads);
+            let wave_durations = compute_wave_durations(&beads, &waves);
+            let (critical_path, estimated_duration) = compute_critical_path(&beads);
+            let content_hash = if has_cycle {
+                String::new()
+            } else {
+                compute_content_hashes(&mut beads, &execution_order)
+            };
+
+            chunks.push(Molecule {
+                id: format!("{}-chunk-{}", mol.id, chunks.len()),
+                formula_name: mol.formula_name.clone(),
+                formula_type: mol.formula_type.clone(),
+                bead_count: beads.len(),
+                beads,
+                has_cycle,
+                execution_order,
+                waves,
+                critical_path,
+                wave_durations,
+                estimated_duration,
+                content_hash,
+            });
+        }
+    }
+
+    chunks
+}
+
+/// Split a molecule into chunks (WASM entry point)
+pub fn split_molecule_impl(molecule_json: &str, max_beads: usize) -> Result<String, JsValue> {
+    let molecule: Molecule = serde_json::from_str(molecule_json)
+        .map_err(|e| JsValue::from_str(&format!("Molecule parse error: {}", e)))?;
+
+    serde_json::to_string(&split_molecule(&molecule, max_beads))
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Merge tooling annotations into bead metadata
+///
+/// `annotations_json` is `{bead_id: {key: value}}`. Keys already present
+/// on a bead are overwritten; bead ids with no match are ignored so
+/// trackers can annotate a superset of molecules with one payload.
+pub fn annotate_molecule(mol_json: &str, annotations_json: &str) -> Result<String, JsValue> {
+    let mut molecule: Molecule = serde_json::from_str(mol_json)
+        .map_err(|e| JsValue::from_str(&format!("Molecule parse error: {}", e)))?;
+
+    type Annotations = std::collections::HashMap<
+        String,
+        std::collections::HashMap<String, serde_json::Value>,
+    >;
+    let annotations: Annotations = serde_json::from_str(annotations_json)
+        .map_err(|e| JsValue::from_str(&format!("Annotations parse error: {}", e)))?;
+
+    for bead in &mut molecule.beads {
+        if let Some(entries) = annotations.get(&bead.id) {
+            bead.metadata
+                .extend(entries.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+    }
+
+    serde_json::to_string(&molecule)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Remove one tooling annotation key from all beads
+///
+/// Used to strip tool-specific state before a molecule is stored.
+pub fn strip_annotations(mol_json: &str, annotation_key: &str) -> Result<String, JsValue> {
+    let mut molecule: Molecule = serde_json::from_str(mol_json)
+        .map_err(|e| JsValue::from_str(&format!("Molecule parse error: {}", e)))?;
+
+    for bead in &mut molecule.beads {
+        bead.metadata.remove(annotation_key);
+    }
+
+    serde_json::to_string(&molecule)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// How `merge_molecules` wires the combined execution graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MergeStrategy {
+    /// Molecules run one after another: each molecule's source beads
+    /// depend on the previous molecule's sink beads
+    Sequential,
+    /// Molecules run side by side with no cross-molecule edges
+    Parallel,
+    /// Molecules run in lockstep waves: a bead at tier N also waits for
+    /// every other molecule's tier N-1 beads
+    Interleaved,
+}
+
+impl MergeStrategy {
+    fn parse(strategy: &str) -> Result<Self, String> {
+        match strategy {
+            "sequential" => Ok(MergeStrategy::Sequential),
+            "parallel" => Ok(MergeStrategy::Parallel),
+            "interleaved" => Ok(MergeStrategy::Interleaved),
+            other => Err(format!(
+                "Unknown merge strategy '{}' (expected sequential, parallel, or interleaved)",
+                other
+            )),
+        }
+    }
+}
+
+/// Merge multiple molecules into one execution graph
+///
+/// Beads with the same id deduplicate onto their first occurrence (with
+/// dependency edges from later occurrences folded in), and
+/// cross-molecule ordering follows `strategy`. Ordering metadata
+/// (execution order, tiers, waves, durations) is re-derived for the
+/// combined graph.
+pub fn merge_molecules_impl(molecules_json: &str, strategy: &str) -> Result<String, JsValue> {
+    let molecules: Vec<Molecule> = serde_json::from_str(molecules_json)
+        .map_err(|e| JsValue::from_str(&format!("Molecules parse error: {}", e)))?;
+
+    let strategy = MergeStrategy::parse(strategy).map_err(|e| JsValue::from_str(&e))?;
+
+    let merged = merge_molecules_internal(&molecules, strategy);
+
+    serde_json::to_string(&merged)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+fn merge_molecules_internal(molecules: &[Molecule], strategy: MergeStrategy) -> Molecule {
+    let mut beads: Vec<MoleculeBead> = Vec::new();
+    let mut id_to_global: std::collections::HashMap<String, usize> =
+        std::collections::HashMap::new();
+    // (molecule, local bead) -> merged bead index
+    let mut global_index: Vec<Vec<usize>> = Vec::with_capacity(molecules.len());
+    // Which molecule each merged bead first came from
+    let mut origin: Vec<usize> = Vec::new();
+
+    for (m, molecule) in molecules.iter().enumerate() {
+        let mut local = Vec::with_capacity(molecule.beads.len());
+        for bead in &molecule.beads {
+            let index = *id_to_global.entry(bead.id.clone()).or_insert_with(|| {
+                let mut merged = bead.clone();
+                merged.depends_on = vec![]; // Remapped below
+                beads.push(merged);
+                origin.push(m);
+                beads.len() - 1
+            });
+            local.push(index);
+        }
+        global_index.push(local);
+    }
+
+    // Remap dependency edges into merged indices, folding duplicate
+    // beads' edges together
+    for (m, molecule) in molecules.iter().enumerate() {
+        for (i, bead) in molecule.beads.iter().enumerate() {
+            let target = global_index[m][i];
+            for &dep in &bead.depends_on {
+                if dep < molecule.beads.len() {
+                    let dep = global_index[m][dep];
+                    if dep != target && !beads[target].depends_on.contains(&dep) {
+                        beads[target].depends_on.push(dep);
+                    }
+                }
+            }
+        }
+    }
+
+    match strategy {
+        MergeStrategy::Parallel => {}
+        MergeStrategy::Sequential => {
+            // Each molecule's sources wait for the previous molecule's sinks
+            for m in 1..molecules.len() {
+                let prev_sinks: Vec<usize> = sink_indices(&molecules[m - 1])
+                    .into_iter()
+                    .map(|i| global_index[m - 1][i])
+                    .collect();
+                for (i, bead) in molecules[m].beads.iter().enumerate() {
+                    let target = global_index[m][i];
+                    if origin[target] == m && bead.depends_on.is_empty() {
+                        for &sink in &prev_sinks {
+                            if sink != target && !beads[target].depends_on.contains(&sink) {
+                                beads[target].depends_on.push(sink);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        MergeStrategy::Interleaved => {
+            // Lockstep waves: a tier-N bead also waits for every other
+            // molecule's tier N-1 beads
+            for (m, molecule) in molecules.iter().enumerate() {
+                for (i, bead) in molecule.beads.iter().enumerate() {
+                    if bead.tier == 0 {
+                        continue;
+                    }
+                    let target = global_index[m][i];
+                    for (other_m, other) in molecules.iter().enumerate() {
+                        if other_m == m {
+                            continue;
+                        }
+                        for (j, other_bead) in other.beads.iter().enumerate() {
+                            if other_bead.tier == bead.tier - 1 {
+                                let dep = global_index[other_m][j];
+                                if dep != target && !beads[target].depends_on.contains(&dep) {
+                                    beads[target].depends_on.push(dep);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let (execution_order, has_cycle) = topological_sort(&beads);
+    for (i, (tier, position)) in compute_tiers(&beads).into_iter().enumerate() {
+        beads[i].tier = tier;
+        beads[i].tier_position = position;
+    }
+    let waves = compute_waves(&beads);
+    let wave_durations = compute_wave_durations(&beads, &waves);
+    let (critical_path, estimated_duration) = compute_critical_path(&beads);
+    let content_hash = if has_cycle {
+        String::new()
+    } else {
+        compute_content_hashes(&mut beads, &execution_order)
+    };
+
+    let id = molecules
+        .iter()
+        .map(|molecule| molecule.id.as_str())
+        .collect::<Vec<_>>()
+        .join("+");
+
+    Molecule {
+        formula_name: id.clone(),
+        id,
+        formula_type: molecules
+            .first()
+            .map(|molecule| molecule.formula_type.clone())
+            .unwrap_or(FormulaType::Workflow),
+        bead_count: beads.len(),
+        beads,
+        has_cycle,
+        execution_order,
+        waves,
+        critical_path,
+        wave_durations,
+        estimated_duration,
+        content_hash,
+    }
+}
+
+/// Indices of beads nothing in the molecule depends on
+fn sink_indices(molecule: &Molecule) -> Vec<usize> {
+    let n = molecule.beads.len();
+    let mut depended_on = vec![false; n];
+    for bead in &molecule.beads {
+        for &dep in &bead.depends_on {
+            if dep < n {
+                depended_on[dep] = true;
+            }
+        }
+    }
+    (0..n).filter(|&i| !depended_on[i]).collect()
+}
+
+/// Execution state captured for crash recovery
+///
+/// The blob records bead ids rather than indices so it stays valid
+/// across serialization round trips, and carries the molecule's
+/// `content_hash` so a checkpoint cannot silently resume a molecule
+/// regenerated from different content.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MoleculeCheckpoint {
+    /// Id of the checkpointed molecule
+    pub molecule_id: String,
+    /// Content hash of the checkpointed molecule (empty when unknown)
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub content_hash: String,
+    /// Bead ids that finished successfully
+    #[serde(default)]
+    pub completed: Vec<String>,
+    /// Bead ids that failed (kept in the resumed molecule for retry)
+    #[serde(default)]
+    pub failed: Vec<String>,
+    /// Bead ids not yet attempted
+    #[serde(default)]
+    pub pending: Vec<String>,
+}
+
+/// Capture a checkpoint blob for a partially executed molecule
+///
+/// `completed_json` and `failed_json` are JSON arrays of bead ids; ids
+/// not present in the molecule are ignored. Every other bead is
+/// recorded as pending.
+pub fn checkpoint_molecule_impl(
+    molecule_json: &str,
+    completed_json: &str,
+    failed_json: &str,
+) -> Result<String, JsValue> {
+    let molecule: Molecule = serde_json::from_str(molecule_json)
+        .map_err(|e| JsValue::from_str(&format!("Molecule parse error: {}", e)))?;
+    let completed: Vec<String> = serde_json::from_str(completed_json)
+        .map_err(|e| JsValue::from_str(&format!("Completed parse error: {}", e)))?;
+    let failed: Vec<String> = serde_json::from_str(failed_json)
+        .map_err(|e| JsValue::from_str(&format!("Failed parse error: {}", e)))?;
+
+    let checkpoint = checkpoint_molecule_internal(&molecule, &completed, &failed);
+
+    serde_json::to_string(&checkpoint)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+fn checkpoint_molecule_internal(
+    molecule: &Molecule,
+    completed: &[String],
+    failed: &[String],
+) -> MoleculeCheckpoint {
+    let known =
+        |ids: &[String]| -> Vec<String> {
+            molecule
+                .beads
+                .iter()
+                .filter(|bead| ids.contains(&bead.id))
+                .map(|bead| bead.id.clone())
+                .collect()
+        };
+    let completed = known(completed);
+    let failed: Vec<String> = known(failed)
+        .into_iter()
+        .filter(|id| !completed.contains(id))
+        .collect();
+    let pending = molecule
+        .beads
+        .iter()
+        .filter(|bead| !completed.contains(&bead.id) && !failed.contains(&bead.id))
+        .map(|bead| bead.id.clone())
+        .collect();
+
+    MoleculeCheckpoint {
+        molecule_id: molecule.id.clone(),
+        content_hash: molecule.content_hash.clone(),
+        completed,
+        failed,
+        pending,
+    }
+}
+
+/// Build the sub-molecule of remaining work from a checkpoint
+///
+/// Completed beads are dropped (along with edges into them); failed and
+/// pending beads are kept, with ordering metadata re-derived for the
+/// remaining graph. Fails when the checkpoint's `content_hash` does not
+/// match the molecule's, so a stale checkpoint cannot resume
+/// regenerated content.
+pub fn resume_molecule_impl(
+    molecule_json: &str,
+    checkpoint_json: &str,
+) -> Result<String, JsValue> {
+    let molecule: Molecule = serde_json::from_str(molecule_json)
+        .map_err(|e| JsValue::from_str(&format!("Molecule parse error: {}", e)))?;
+    let checkpoint: MoleculeCheckpoint = serde_json::from_str(checkpoint_json)
+        .map_err(|e| JsValue::from_str(&format!("Checkpoint parse error: {}", e)))?;
+
+    let resumed =
+        resume_molecule_internal(&molecule, &checkpoint).map_err(|e| JsValue::from_str(&e))?;
+
+    serde_json::to_string(&resumed)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+fn resume_molecule_internal(
+    molecule: &Molecule,
+    checkpoint: &MoleculeCheckpoint,
+) -> Result<Molecule, String> {
+    if !checkpoint.content_hash.is_empty()
+        && !molecule.content_hash.is_empty()
+        && checkpoint.content_hash != molecule.content_hash
+    {
+        return Err(format!(
+            "Checkpoint content hash {} does not match molecule {}",
+            checkpoint.content_hash, molecule.content_hash
+        ));
+    }
+
+    let keep: Vec<usize> = (0..molecule.beads.len())
+        .filter(|&i| !checkpoint.completed.contains(&molecule.beads[i].id))
+        .collect();
+    let new_index: gastown_shared::FxHashMap<usize, usize> = keep
+        .iter()
+        .enumerate()
+        .map(|(new, &old)| (old, new))
+        .collect();
+
+    let mut beads: Vec<MoleculeBead> = keep
+        .iter()
+        .map(|&old| {
+            let mut bead = molecule.beads[old].clone();
+            // Edges into completed beads are satisfied; drop them
+            bead.depends_on = bead
+                .depends_on
+                .iter()
+                .filter_map(|dep| new_index.get(dep).copied())
+                .collect();
+            bead
+        })
+        .collect();
+
+    let (execution_order, has_cycle) = topological_sort(&beads);
+    for (i, (tier, position)) in compute_tiers(&beads).into_iter().enumerate() {
+        beads[i].tier = tier;
+        beads[i].tier_position = position;
+    }
+    let waves = compute_waves(&beads);
+    let wave_durations = compute_wave_durations(&beads, &waves);
+    let (critical_path, estimated_duration) = compute_critical_path(&beads);
+    let content_hash = if has_cycle {
+        String::new()
+    } else {
+        compute_content_hashes(&mut beads, &execution_order)
+    };
+
+    Ok(Molecule {
+        id: format!("{}-resume", molecule.id),
+        formula_name: molecule.formula_name.clone(),
+        formula_type: molecule.formula_type.clone(),
+        bead_count: beads.len(),
+        beads,
+        has_cycle,
+        execution_order,
+        waves,
+        critical_path,
+        wave_durations,
+        estimated_duration,
+        content_hash,
+    })
+}
+
+/// One dependency edge in a molecule diff, as bead ids
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
+pub struct MoleculeDiffEdge {
+    /// Bead that must complete first
+    pub from: String,
+    /// Bead that depends on it
+    pub to: String,
+}
+
+/// Differences between two molecules, keyed by bead id
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schemars-support", derive(schemars::JsonSchema))]
+pub struct MoleculeDiff {
+    /// Bead ids present in `b` but not `a` (name-sorted)
+    pub added: Vec<String>,
+    /// Bead ids present in `a` but not `b` (name-sorted)
+    pub removed: Vec<String>,
+    /// Bead ids present in both whose title, description, duration, or
+    /// requirements changed (name-sorted)
+    pub modified: Vec<String>,
+    /// Dependency edges present in `b` but not `a`
+    pub added_edges: Vec<MoleculeDiffEdge>,
+    /// Dependency edges present in `a` but not `b`
+    pub removed_edges: Vec<MoleculeDiffEdge>,
+}
+
+/// Diff two molecules, reporting bead and edge changes
+///
+/// Beads are matched by id so reordering alone does not count as a
+/// change; edges compare as `(from_id, to_id)` pairs for the same
+/// reason. This backs the "what changes if I regenerate?" preview.
+pub fn diff_molecules_impl(a_json: &str, b_json: &str) -> Result<String, JsValue> {
+    let a: Molecule = serde_json::from_str(a_json)
+        .map_err(|e| JsValue::from_str(&format!("Molecule parse error: {}", e)))?;
+    let b: Molecule = serde_json::from_str(b_json)
+        .map_err(|e| JsValue::from_str(&format!("Molecule parse error: {}", e)))?;
+
+    let diff = diff_molecules_internal(&a, &b);
+
+    serde_json::to_string(&diff)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Collect a molecule's dependency edges as `(from_id, to_id)` pairs
+fn edge_set(mol: &Molecule) -> std::collections::BTreeSet<(String, String)> {
+    let mut edges = std::collections::BTreeSet::new();
+    for (i, bead) in mol.beads.iter().enumerate() {
+        for &dep in &bead.depends_on {
+            if dep < mol.beads.len() {
+                edges.insert((mol.beads[dep].id.clone(), mol.beads[i].id.clone()));
+            }
+        }
+    }
+    edges
+}
+
+fn diff_molecules_internal(a: &Molecule, b: &Molecule) -> MoleculeDiff {
+    let ids_a: std::collections::BTreeMap<&str, &MoleculeBead> =
+        a.beads.iter().map(|bead| (bead.id.as_str(), bead)).collect();
+    let ids_b: std::collections::BTreeMap<&str, &MoleculeBead> =
+        b.beads.iter().map(|bead| (bead.id.as_str(), bead)).collect();
+
+    let added = ids_b
+        .keys()
+        .filter(|id| !ids_a.contains_key(*id))
+        .map(|id| id.to_string())
+        .collect();
+    let removed = ids_a
+        .keys()
+        .filter(|id| !ids_b.contains_key(*id))
+        .map(|id| id.to_string())
+        .collect();
+
+    let modified = ids_a
+        .iter()
+        .filter_map(|(id, bead_a)| {
+            let bead_b = ids_b.get(id)?;
+            let changed = bead_a.title != bead_b.title
+                || bead_a.description != bead_b.description
+                || bead_a.duration != bead_b.duration
+                || bead_a.requires != bead_b.requires;
+            changed.then(|| id.to_string())
+        })
+        .collect();
+
+    let edges_a = edge_set(a);
+    let edges_b = edge_set(b);
+    let added_edges = edges_b
+        .difference(&edges_a)
+        .map(|(from, to)| MoleculeDiffEdge {
+            from: from.clone(),
+            to: to.clone(),
+        })
+        .collect();
+    let removed_edges = edges_a
+        .difference(&edges_b)
+        .map(|(from, to)| MoleculeDiffEdge {
+            from: from.clone(),
+            to: to.clone(),
+        })
+        .collect();
+
+    MoleculeDiff {
+        added,
+        removed,
+        modified,
+        added_edges,
+        removed_edges,
+    }
+}
+
+/// Render a molecule as a Mermaid flowchart
+///
+/// Bead titles become node labels (with the estimated minutes appended
+/// when declared) and `depends_on` edges become arrows, so dashboards
+/// and PR descriptions can embed the plan directly.
+pub fn molecule_to_mermaid_impl(molecule_json: &str) -> Result<String, JsValue> {
+    let molecule: Molecule = serde_json::from_str(molecule_json)
+        .map_err(|e| JsValue::from_str(&format!("Molecule parse error: {}", e)))?;
+
+    Ok(molecule_to_mermaid_internal(&molecule))
+}
+
+/// Build the Mermaid flowchart text for a molecule
+fn molecule_to_mermaid_internal(molecule: &Molecule) -> String {
+    let mut out = String::from("flowchart TD\n");
+
+    for (i, bead) in molecule.beads.iter().enumerate() {
+        let mut label = bead.title.clone();
+        if let Some(duration) = bead.duration {
+            label.push_str(&format!(" ({}m)", duration));
+        }
+        // Mermaid reserves double quotes inside labels
+        let label = label.replace('"', "#quot;");
+        out.push_str(&format!("    b{}[\"{}\"]\n", i, label));
+    }
+
+    for (i, bead) in molecule.beads.iter().enumerate() {
+        for &dep in &bead.depends_on {
+            if dep < molecule.beads.len() {
+                out.push_str(&format!("    b{} --> b{}\n", dep, i));
+            }
+        }
+    }
+
+    out
+}
+
+/// Validate a molecule, returning warnings
+pub fn validate_molecule_impl(molecule_json: &str) -> Result<String, JsValue> {
+    let molecule: Molecule = serde_json::from_str(molecule_json)
+        .map_err(|e| JsValue::from_str(&format!("Molecule parse error: {}", e)))?;
+
+    let mut warnings: Vec<crate::LintWarning> = Vec::new();
 
-    serde_json::to_string(&molecule)
+    if let Some(cycle) = detect_bead_cycles(&molecule) {
+        warnings.push(crate::LintWarning::new(
+            "BeadCycle",
+            format!("Bead dependency cycle detected: {}", cycle.join(" -> ")),
+            crate::Severity::Error,
+        ));
+    }
+
+    for id in find_unreachable_beads(&molecule) {
+        warnings.push(crate::LintWarning::new(
+            "UnreachableBead",
+            format!("Bead '{}' is not on any source-to-sink execution path", id),
+            crate::Severity::Warning,
+        ));
+    }
+
+    serde_json::to_string(&warnings)
         .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
 }
 
-/// Internal molecule generation
-fn generate_molecule_internal(cooked: &CookedFormula) -> Result<Molecule, JsValue> {
-    let formula = &cooked.formula;
-    let formula_type = match &formula.formula_type {
-        crate::FormulaType::Convoy => "convoy",
-        crate::FormulaType::Workflow => "workflow",
-        crate::FormulaType::Expansion => "expansion",
-        crate::FormulaType::Aspect => "aspect",
-    };
+/// Extract the source formula type from serialized molecule JSON
+pub fn get_molecule_formula_type_impl(molecule_json: &str) -> Result<String, JsValue> {
+    let molecule: Molecule = serde_json::from_str(molecule_json)
+        .map_err(|e| JsValue::from_str(&format!("Molecule parse error: {}", e)))?;
 
-    let mut beads = Vec::new();
-    let mut id_to_index: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+    Ok(molecule.formula_type.as_str().to_string())
+}
 
-    // Generate beads from steps (for workflow type)
-    if !formula.steps.is_empty() {
-        for (i, step) in formula.steps.iter().enumerate() {
-            id_to_index.insert(step.id.clone(), i);
-            beads.push(MoleculeBead {
-                title: step.title.clone(),
-                description: step.description.clone(),
-                labels: vec!["molecule".to_string(), formula.name.clone()],
-                depends_on: vec![], // Will be filled after all beads are created
-                duration: step.duration,
-                requires: step.requires.clone(),
-            });
-        }
+/// Aggregate shape statistics for a molecule
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct MoleculeStats {
+    /// Total number of beads
+    pub bead_count: usize,
+    /// Beads with no dependencies (execution entry points)
+    pub source_count: usize,
+    /// Beads no other bead depends on (execution exit points)
+    pub sink_count: usize,
+    /// Number of dependency tiers (longest chain length; 0 when empty)
+    pub max_depth: u32,
+}
 
-        // Wire dependencies
-        for (i, step) in formula.steps.iter().enumerate() {
-            let deps: Vec<usize> = step.needs.iter()
-                .filter_map(|need| id_to_index.get(need).copied())
-                .collect();
-            beads[i].depends_on = deps;
+/// Compute shape statistics for a molecule
+pub(crate) fn molecule_stats_internal(molecule: &Molecule) -> MoleculeStats {
+    let mut depended_on = vec![false; molecule.beads.len()];
+    for bead in &molecule.beads {
+        for &dep in &bead.depends_on {
+            if dep < depended_on.len() {
+                depended_on[dep] = true;
+            }
         }
     }
 
-    // Generate beads from legs (for convoy type)
-    if !formula.legs.is_empty() {
-        for (i, leg) in formula.legs.iter().enumerate() {
-            id_to_index.insert(leg.id.clone(), i);
-            beads.push(MoleculeBead {
-                title: leg.title.clone(),
-                description: leg.description.clone(),
-                labels: vec!["molecule".to_string(), "convoy".to_string(), formula.name.clone()],
-                depends_on: if i > 0 { vec![i - 1] } else { vec![] }, // Sequential by default
-                duration: None,
-                requires: vec![],
-            });
-        }
+    MoleculeStats {
+        bead_count: molecule.beads.len(),
+        source_count: molecule
+            .beads
+            .iter()
+            .filter(|b| b.depends_on.is_empty())
+            .count(),
+        sink_count: depended_on.iter().filter(|&&d| !d).count(),
+        max_depth: molecule
+            .beads
+            .iter()
+            .map(|b| b.tier + 1)
+            .max()
+            .unwrap_or(0),
     }
+}
 
-    // Compute execution order (topological sort)
-    let (execution_order, has_cycle) = topological_sort(&beads);
+/// Compute molecule statistics from serialized molecule JSON
+pub fn get_molecule_stats_impl(molecule_json: &str) -> Result<JsValue, JsValue> {
+    let molecule: Molecule = serde_json::from_str(molecule_json)
+        .map_err(|e| JsValue::from_str(&format!("Molecule parse error: {}", e)))?;
 
-    Ok(Molecule {
-        formula_name: formula.name.clone(),
-        formula_type: formula_type.to_string(),
-        beads,
-        has_cycle,
-        execution_order,
-    })
+    serde_wasm_bindgen::to_value(&molecule_stats_internal(&molecule))
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Compute the critical path and its total duration in minutes
+///
+/// The critical path is the dependency chain with the largest duration
+/// sum; beads without a declared duration contribute 0 but still count
+/// as links. Requires tiers to be assigned (processing in tier order
+/// guarantees dependencies are finalized first).
+fn compute_critical_path(beads: &[MoleculeBead]) -> (Vec<usize>, u32) {
+    let n = beads.len();
+    if n == 0 {
+        return (vec![], 0);
+    }
+
+    // Longest-duration chain ending at each bead, inclusive
+    let mut chain_total: Vec<u32> = vec![0; n];
+    let mut chain_prev: Vec<Option<usize>> = vec![None; n];
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by_key(|&i| beads[i].tier);
+    for &i in &order {
+        let (base, from) = beads[i]
+            .depends_on
+            .iter()
+            .filter(|&&dep| dep < n)
+            .map(|&dep| (chain_total[dep], Some(dep)))
+            .max()
+            .unwrap_or((0, None));
+        chain_total[i] = base + beads[i].duration.unwrap_or(0);
+        chain_prev[i] = from;
+    }
+
+    let end = (0..n).max_by_key(|&i| chain_total[i]).unwrap_or(0);
+    let total = chain_total[end];
+
+    let mut path = Vec::new();
+    let mut current = Some(end);
+    while let Some(i) = current {
+        path.push(i);
+        current = chain_prev[i];
+    }
+    path.reverse();
+    (path, total)
+}
+
+/// Estimated minutes per wave: the longest bead in each wave
+fn compute_wave_durations(beads: &[MoleculeBead], waves: &[Vec<usize>]) -> Vec<u32> {
+    waves
+        .iter()
+        .map(|wave| {
+            wave.iter()
+                .map(|&i| beads[i].duration.unwrap_or(0))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect()
+}
+
+/// Group bead indices into parallel execution waves
+///
+/// Wave N is exactly the set of beads at tier N, in bead index order;
+/// every bead's dependencies sit in earlier waves.
+fn compute_waves(beads: &[MoleculeBead]) -> Vec<Vec<usize>> {
+    let wave_count = beads.iter().map(|bead| bead.tier + 1).max().unwrap_or(0) as usize;
+    let mut waves: Vec<Vec<usize>> = vec![vec![]; wave_count];
+    for (i, bead) in beads.iter().enumerate() {
+        waves[bead.tier as usize].push(i);
+    }
+    waves
+}
+
+/// Compute `(tier, position_within_tier)` for every bead
+///
+/// A bead's tier is its dependency depth: sources (no dependencies) are
+/// tier 0, and every other bead sits one tier above its deepest
+/// dependency. Positions within a tier follow bead index order. Beads
+/// trapped in a cycle keep tier 0.
+fn compute_tiers(beads: &[MoleculeBead]) -> Vec<(u32, u32)> {
+    let n = beads.len();
+    let mut tiers: Vec<u32> = vec![0; n];
+
+    // Process in topological order so dependency tiers resolve first
+    let (order, _) = topological_sort(beads);
+    for &i in &order {
+        tiers[i] = beads[i]
+            .depends_on
+            .iter()
+            .filter(|&&d| d < n)
+            .map(|&d| tiers[d] + 1)
+            .max()
+            .unwrap_or(0);
+    }
+
+    // Number beads within each tier by index order
+    let mut tier_counts: std::collections::HashMap<u32, u32> = std::collections::HashMap::new();
+    tiers
+        .into_iter()
+        .map(|tier| {
+            let position = tier_counts.entry(tier).or_insert(0);
+            let pair = (tier, *position);
+            *position += 1;
+            pair
+        })
+        .collect()
 }
 
 /// Topological sort using Kahn's algorithm
@@ -139,8 +1514,8 @@ fn topological_sort(beads: &[MoleculeBead]) -> (Vec<usize>, bool) {
     }
 
     let mut queue: std::collections::VecDeque<usize> = std::collections::VecDeque::new();
-    for i in 0..n {
-        if in_degree_correct[i] == 0 {
+    for (i, &deg) in in_degree_correct.iter().enumerate() {
+        if deg == 0 {
             queue.push_back(i);
         }
     }
@@ -173,6 +1548,23 @@ mod tests {
     use crate::{Formula, FormulaType, Step};
     use std::collections::HashMap;
 
+    fn test_bead(id: &str, depends_on: Vec<usize>) -> MoleculeBead {
+        MoleculeBead {
+            id: id.to_string(),
+            title: id.to_string(),
+            description: "".to_string(),
+            labels: vec![],
+            depends_on,
+            duration: None,
+            requires: vec![],
+            metadata: std::collections::HashMap::new(),
+            tier: 0,
+            tier_position: 0,
+            content_hash: String::new(),
+            agent: None,
+        }
+    }
+
     fn create_test_formula() -> CookedFormula {
         CookedFormula {
             formula: Formula {
@@ -190,6 +1582,8 @@ mod tests {
                         needs: vec![],
                         duration: Some(30),
                         requires: vec![],
+                        when: None,
+                        foreach: None,
                     },
                     Step {
                         id: "review".to_string(),
@@ -198,6 +1592,8 @@ mod tests {
                         needs: vec!["analyze".to_string()],
                         duration: Some(60),
                         requires: vec![],
+                        when: None,
+                        foreach: None,
                     },
                     Step {
                         id: "approve".to_string(),
@@ -206,6 +1602,8 @@ mod tests {
                         needs: vec!["review".to_string()],
                         duration: Some(15),
                         requires: vec![],
+                        when: None,
+                        foreach: None,
                     },
                 ],
                 vars: HashMap::new(),
@@ -213,6 +1611,14 @@ mod tests {
             cooked_at: "2026-01-24T00:00:00Z".to_string(),
             cooked_vars: HashMap::new(),
             original_name: "test-workflow".to_string(),
+            cook_duration_us: 0,
+            substitution_count: 0,
+            unresolved_count: 0,
+            formula_url: None,
+            cooked_by: None,
+            var_provenance: std::collections::HashMap::new(),
+            typed_vars: std::collections::HashMap::new(),
+            warnings: vec![],
         }
     }
 
@@ -222,39 +1628,677 @@ mod tests {
         let molecule = generate_molecule_internal(&cooked).unwrap();
 
         assert_eq!(molecule.formula_name, "test-workflow");
-        assert_eq!(molecule.formula_type, "workflow");
+        assert_eq!(molecule.formula_type, FormulaType::Workflow);
         assert_eq!(molecule.beads.len(), 3);
         assert!(!molecule.has_cycle);
         assert_eq!(molecule.execution_order.len(), 3);
     }
 
     #[test]
-    fn test_topological_sort() {
-        let beads = vec![
-            MoleculeBead {
-                title: "A".to_string(),
-                description: "".to_string(),
-                labels: vec![],
-                depends_on: vec![],
-                duration: None,
-                requires: vec![],
+    fn test_diff_molecules() {
+        let cooked = create_test_formula();
+        let a = generate_molecule_internal(&cooked).unwrap();
+
+        // Regenerate after edits: retitle review, drop approve, add ship
+        let mut edited = create_test_formula();
+        edited.formula.steps[1].title = "Review Carefully".to_string();
+        edited.formula.steps.remove(2);
+        edited.formula.steps.push(Step {
+            id: "ship".to_string(),
+            title: "Ship".to_string(),
+            description: "Ship it".to_string(),
+            needs: vec!["review".to_string()],
+            duration: None,
+            requires: vec![],
+            when: None,
+            foreach: None,
+        });
+        let b = generate_molecule_internal(&edited).unwrap();
+
+        let diff = diff_molecules_internal(&a, &b);
+
+        assert_eq!(diff.added, vec!["ship"]);
+        assert_eq!(diff.removed, vec!["approve"]);
+        assert_eq!(diff.modified, vec!["review"]);
+        assert!(diff.added_edges.contains(&MoleculeDiffEdge {
+            from: "review".to_string(),
+            to: "ship".to_string(),
+        }));
+        assert!(diff.removed_edges.contains(&MoleculeDiffEdge {
+            from: "review".to_string(),
+            to: "approve".to_string(),
+        }));
+
+        // Identical molecules diff clean
+        let clean = diff_molecules_internal(&a, &a);
+        assert!(clean.added.is_empty());
+        assert!(clean.removed.is_empty());
+        assert!(clean.modified.is_empty());
+        assert!(clean.added_edges.is_empty());
+        assert!(clean.removed_edges.is_empty());
+    }
+
+    #[test]
+    fn test_checkpoint_and_resume() {
+        let molecule = generate_molecule_internal(&create_test_formula()).unwrap();
+
+        let checkpoint = checkpoint_molecule_internal(
+            &molecule,
+            &["analyze".to_string(), "bogus".to_string()],
+            &["review".to_string()],
+        );
+        assert_eq!(checkpoint.molecule_id, molecule.id);
+        assert_eq!(checkpoint.content_hash, molecule.content_hash);
+        assert_eq!(checkpoint.completed, vec!["analyze"]);
+        assert_eq!(checkpoint.failed, vec!["review"]);
+        assert_eq!(checkpoint.pending, vec!["approve"]);
+
+        // Resume drops the completed bead and keeps failed + pending,
+        // with the satisfied edge removed
+        let resumed = resume_molecule_internal(&molecule, &checkpoint).unwrap();
+        assert_eq!(resumed.id, format!("{}-resume", molecule.id));
+        assert_eq!(resumed.bead_count, 2);
+        assert_eq!(resumed.beads[0].id, "review");
+        assert!(resumed.beads[0].depends_on.is_empty());
+        assert_eq!(resumed.beads[1].depends_on, vec![0]);
+        assert_eq!(resumed.estimated_duration, 75);
+
+        // A checkpoint from different content is rejected
+        let mut stale = checkpoint.clone();
+        stale.content_hash = "deadbeefdeadbeef".to_string();
+        assert!(resume_molecule_internal(&molecule, &stale).is_err());
+    }
+
+    #[test]
+    fn test_assign_agents_strategies() {
+        fn roster(agents: &[(&str, &[&str], u32)]) -> Vec<AgentProfile> {
+            agents
+                .iter()
+                .map(|(name, capabilities, capacity)| AgentProfile {
+                    name: name.to_string(),
+                    capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+                    capacity: *capacity,
+                })
+                .collect()
+        }
+        fn agents(beads: &[MoleculeBead]) -> Vec<Option<&str>> {
+            beads.iter().map(|b| b.agent.as_deref()).collect()
+        }
+
+        let molecule = generate_molecule_internal(&create_test_formula()).unwrap();
+
+        // Round robin cycles the roster in bead order
+        let mut beads = molecule.beads.clone();
+        assign_agents(
+            &mut beads,
+            &AssignmentOptions {
+                strategy: AssignmentStrategy::RoundRobin,
+                roster: roster(&[("alice", &[], 1), ("bob", &[], 1)]),
             },
-            MoleculeBead {
-                title: "B".to_string(),
-                description: "".to_string(),
-                labels: vec![],
-                depends_on: vec![0],
-                duration: None,
-                requires: vec![],
+        );
+        assert_eq!(agents(&beads), vec![Some("alice"), Some("bob"), Some("alice")]);
+
+        // Capability matching honors bead requires; uncoverable beads
+        // stay unassigned
+        let mut beads = molecule.beads.clone();
+        beads[1].requires = vec!["rust".to_string()];
+        beads[2].requires = vec!["ops".to_string()];
+        assign_agents(
+            &mut beads,
+            &AssignmentOptions {
+                strategy: AssignmentStrategy::Capability,
+                roster: roster(&[("alice", &["rust"], 1)]),
             },
-            MoleculeBead {
-                title: "C".to_string(),
-                description: "".to_string(),
-                labels: vec![],
-                depends_on: vec![0, 1],
-                duration: None,
-                requires: vec![],
+        );
+        assert_eq!(agents(&beads), vec![Some("alice"), Some("alice"), None]);
+
+        // Load weighting favors the agent with spare capacity: after
+        // alice takes the 30m analyze bead, bob takes the 60m review,
+        // and alice (30m < 60m) takes approve
+        let mut beads = molecule.beads.clone();
+        assign_agents(
+            &mut beads,
+            &AssignmentOptions {
+                strategy: AssignmentStrategy::LoadWeighted,
+                roster: roster(&[("alice", &[], 1), ("bob", &[], 1)]),
+            },
+        );
+        assert_eq!(agents(&beads), vec![Some("alice"), Some("bob"), Some("alice")]);
+
+        // Already-assigned beads are left alone
+        let mut beads = molecule.beads.clone();
+        beads[0].agent = Some("queen".to_string());
+        assign_agents(
+            &mut beads,
+            &AssignmentOptions {
+                strategy: AssignmentStrategy::RoundRobin,
+                roster: roster(&[("alice", &[], 1)]),
             },
+        );
+        assert_eq!(beads[0].agent.as_deref(), Some("queen"));
+    }
+
+    #[test]
+    fn test_content_hashes_are_deterministic() {
+        let a = generate_molecule_internal(&create_test_formula()).unwrap();
+        let b = generate_molecule_internal(&create_test_formula()).unwrap();
+
+        // Regenerating an unchanged formula yields identical ids
+        assert!(!a.content_hash.is_empty());
+        assert_eq!(a.content_hash, b.content_hash);
+        for (bead_a, bead_b) in a.beads.iter().zip(&b.beads) {
+            assert_eq!(bead_a.content_hash, bead_b.content_hash);
+        }
+
+        // Editing one step changes its hash and its dependents' hashes,
+        // but not its dependencies'
+        let mut edited = create_test_formula();
+        edited.formula.steps[1].description = "Review very carefully".to_string();
+        let c = generate_molecule_internal(&edited).unwrap();
+        assert_ne!(c.content_hash, a.content_hash);
+        assert_eq!(c.beads[0].content_hash, a.beads[0].content_hash);
+        assert_ne!(c.beads[1].content_hash, a.beads[1].content_hash);
+        assert_ne!(c.beads[2].content_hash, a.beads[2].content_hash);
+    }
+
+    #[test]
+    fn test_merge_molecules() {
+        let a = generate_molecule_internal(&create_test_formula()).unwrap();
+        let mut other = create_test_formula();
+        for step in &mut other.formula.steps {
+            step.id = format!("b-{}", step.id);
+            step.needs = step.needs.iter().map(|n| format!("b-{}", n)).collect();
+        }
+        let b = generate_molecule_internal(&other).unwrap();
+
+        // Parallel: union of beads, no cross-molecule edges
+        let parallel =
+            merge_molecules_internal(&[a.clone(), b.clone()], MergeStrategy::Parallel);
+        assert_eq!(parallel.bead_count, 6);
+        assert_eq!(parallel.id, format!("{}+{}", a.id, b.id));
+        assert!(!parallel.has_cycle);
+        assert!(parallel
+            .beads
+            .iter()
+            .find(|bead| bead.id == "b-analyze")
+            .unwrap()
+            .depends_on
+            .is_empty());
+
+        // Sequential: the second molecule's source waits for the first's sink
+        let sequential =
+            merge_molecules_internal(&[a.clone(), b.clone()], MergeStrategy::Sequential);
+        let approve = sequential
+            .beads
+            .iter()
+            .position(|bead| bead.id == "approve")
+            .unwrap();
+        assert_eq!(
+            sequential
+                .beads
+                .iter()
+                .find(|bead| bead.id == "b-analyze")
+                .unwrap()
+                .depends_on,
+            vec![approve]
+        );
+        assert_eq!(sequential.waves.len(), 6);
+
+        // Interleaved: tier-1 beads also wait on the other molecule's tier 0
+        let interleaved =
+            merge_molecules_internal(&[a.clone(), b.clone()], MergeStrategy::Interleaved);
+        let analyze = interleaved
+            .beads
+            .iter()
+            .position(|bead| bead.id == "analyze")
+            .unwrap();
+        assert!(interleaved
+            .beads
+            .iter()
+            .find(|bead| bead.id == "b-review")
+            .unwrap()
+            .depends_on
+            .contains(&analyze));
+        assert_eq!(interleaved.waves.len(), 3);
+
+        // Duplicate ids deduplicate onto the first occurrence
+        let deduped = merge_molecules_internal(&[a.clone(), a], MergeStrategy::Parallel);
+        assert_eq!(deduped.bead_count, 3);
+
+        assert!(MergeStrategy::parse("bogus").is_err());
+    }
+
+    #[test]
+    fn test_molecule_to_mermaid() {
+        let cooked = create_test_formula();
+        let mut molecule = generate_molecule_internal(&cooked).unwrap();
+        molecule.beads[0].duration = Some(15);
+        molecule.beads[1].title = "Review \"all\" changes".to_string();
+
+        let mermaid = molecule_to_mermaid_internal(&molecule);
+
+        assert!(mermaid.starts_with("flowchart TD\n"));
+        assert!(mermaid.contains("b0[\"Analyze (15m)\"]"));
+        // Quotes in titles are escaped for Mermaid
+        assert!(mermaid.contains("b1[\"Review #quot;all#quot; changes (60m)\"]"));
+        assert!(mermaid.contains("b0 --> b1"));
+        assert!(mermaid.contains("b1 --> b2"));
+    }
+
+    #[test]
+    fn test_critical_path_and_durations() {
+        // Diamond: A feeds B and C, both feed D; C is the long branch
+        let mut beads = vec![
+            test_bead("A", vec![]),
+            test_bead("B", vec![0]),
+            test_bead("C", vec![0]),
+            test_bead("D", vec![1, 2]),
+        ];
+        beads[0].duration = Some(10);
+        beads[1].duration = Some(5);
+        beads[2].duration = Some(20);
+        beads[3].duration = Some(1);
+        for (i, (tier, position)) in compute_tiers(&beads).into_iter().enumerate() {
+            beads[i].tier = tier;
+            beads[i].tier_position = position;
+        }
+
+        let (path, total) = compute_critical_path(&beads);
+        assert_eq!(path, vec![0, 2, 3]);
+        assert_eq!(total, 31);
+
+        // A wave takes as long as its slowest bead
+        let waves = compute_waves(&beads);
+        assert_eq!(compute_wave_durations(&beads, &waves), vec![10, 20, 1]);
+
+        // An undeclared duration counts as 0 but keeps its link in the path
+        beads[2].duration = None;
+        let (path, total) = compute_critical_path(&beads);
+        assert_eq!(total, 16);
+        assert_eq!(path, vec![0, 1, 3]);
+    }
+
+    #[test]
+    fn test_generate_molecule_waves() {
+        let cooked = create_test_formula();
+        let molecule = generate_molecule_internal(&cooked).unwrap();
+
+        // analyze -> review -> approve is a straight chain: one bead per wave
+        assert_eq!(molecule.waves, vec![vec![0], vec![1], vec![2]]);
+
+        // Every bead's dependencies sit in strictly earlier waves
+        for (wave_index, wave) in molecule.waves.iter().enumerate() {
+            for &bead in wave {
+                for &dep in &molecule.beads[bead].depends_on {
+                    let dep_wave = molecule.beads[dep].tier as usize;
+                    assert!(dep_wave < wave_index);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_molecule_rejects_cycle() {
+        let mut cooked = create_test_formula();
+        // analyze -> review -> approve already chain; close the loop
+        cooked.formula.steps[0].needs = vec!["approve".to_string()];
+
+        let err = generate_molecule_internal(&cooked).unwrap_err();
+        let MoleculeError::DependencyCycle { cycle } = err;
+        // The path closes on its entry bead
+        assert_eq!(cycle.first(), cycle.last());
+        assert!(cycle.len() >= 3);
+        for id in ["analyze", "review", "approve"] {
+            assert!(cycle.iter().any(|c| c == id), "missing {id} in {cycle:?}");
+        }
+    }
+
+    #[test]
+    fn test_molecule_collection_methods() {
+        let cooked = create_test_formula();
+        let molecule = generate_molecule_internal(&cooked).unwrap();
+
+        assert_eq!(molecule.len(), 3);
+        assert!(!molecule.is_empty());
+        let ids: Vec<&str> = molecule.bead_ids().collect();
+        assert_eq!(ids, vec!["analyze", "review", "approve"]);
+        assert_eq!(molecule.find_bead("review").unwrap().title, "Review");
+        assert!(molecule.find_bead("missing").is_none());
+    }
+
+    #[test]
+    fn test_get_molecule_formula_type_all_variants() {
+        for (formula_type, expected) in [
+            (FormulaType::Convoy, "convoy"),
+            (FormulaType::Workflow, "workflow"),
+            (FormulaType::Expansion, "expansion"),
+            (FormulaType::Aspect, "aspect"),
+        ] {
+            let mut cooked = create_test_formula();
+            cooked.formula.formula_type = formula_type.clone();
+
+            let molecule = generate_molecule_internal(&cooked).unwrap();
+            assert_eq!(molecule.formula_type, formula_type);
+
+            let json = serde_json::to_string(&molecule).unwrap();
+            assert_eq!(get_molecule_formula_type_impl(&json).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_molecule_stats() {
+        let cooked = create_test_formula();
+        let molecule = generate_molecule_internal(&cooked).unwrap();
+        let stats = molecule_stats_internal(&molecule);
+
+        // Linear chain: one source, one sink, depth 3
+        assert_eq!(
+            stats,
+            MoleculeStats {
+                bead_count: 3,
+                source_count: 1,
+                sink_count: 1,
+                max_depth: 3,
+            }
+        );
+
+        let empty = Molecule {
+            id: "empty".to_string(),
+            formula_name: "empty".to_string(),
+            formula_type: FormulaType::Workflow,
+            beads: vec![],
+            bead_count: 0,
+            has_cycle: false,
+            execution_order: vec![],
+            waves: vec![],
+            critical_path: vec![],
+            wave_durations: vec![],
+            estimated_duration: 0,
+            content_hash: String::new(),
+        };
+        assert!(empty.is_empty());
+        assert_eq!(molecule_stats_internal(&empty).max_depth, 0);
+    }
+
+    #[test]
+    fn test_generate_molecule_opts_id_override() {
+        let cooked = create_test_formula();
+        let json = serde_json::to_string(&cooked).unwrap();
+
+        // Default id derives from the formula name
+        let molecule: Molecule =
+            serde_json::from_str(&generate_molecule_impl(&json).unwrap()).unwrap();
+        assert_eq!(molecule.id, "test-workflow");
+
+        // Explicit override wins
+        let result =
+            generate_molecule_opts_impl(&json, r#"{"molecule_id": "content-abc123"}"#).unwrap();
+        let molecule: Molecule = serde_json::from_str(&result).unwrap();
+        assert_eq!(molecule.id, "content-abc123");
+
+        // Empty options keep the derivation
+        let result = generate_molecule_opts_impl(&json, "{}").unwrap();
+        let molecule: Molecule = serde_json::from_str(&result).unwrap();
+        assert_eq!(molecule.id, "test-workflow");
+    }
+
+    #[test]
+    fn test_execution_tiers() {
+        let cooked = create_test_formula();
+        let molecule = generate_molecule_internal(&cooked).unwrap();
+
+        // Linear chain: analyze (source) -> review -> approve
+        assert_eq!(molecule.beads[0].tier, 0);
+        assert_eq!(molecule.beads[1].tier, 1);
+        assert_eq!(molecule.beads[2].tier, 2);
+        assert!(molecule.beads.iter().all(|b| b.tier_position == 0));
+    }
+
+    #[test]
+    fn test_execution_tiers_diamond() {
+        // A fans out to B and C, which join at D
+        let beads = vec![
+            test_bead("A", vec![]),
+            test_bead("B", vec![0]),
+            test_bead("C", vec![0]),
+            test_bead("D", vec![1, 2]),
+        ];
+
+        let tiers = compute_tiers(&beads);
+        assert_eq!(tiers[0], (0, 0));
+        assert_eq!(tiers[1], (1, 0));
+        assert_eq!(tiers[2], (1, 1));
+        assert_eq!(tiers[3], (2, 0));
+    }
+
+    #[test]
+    fn test_generate_molecule_no_steps_no_legs() {
+        let mut cooked = create_test_formula();
+        cooked.formula.formula_type = FormulaType::Expansion;
+        cooked.formula.steps.clear();
+
+        let json = serde_json::to_string(&cooked).unwrap();
+        let result = generate_molecule_impl(&json).unwrap();
+        let molecule: Molecule = serde_json::from_str(&result).unwrap();
+
+        assert_eq!(molecule.bead_count, 0);
+        assert!(molecule.beads.is_empty());
+        assert!(molecule.execution_order.is_empty());
+        assert!(!molecule.has_cycle);
+    }
+
+    #[test]
+    fn test_find_unreachable_beads() {
+        // A is a normal source/sink; B and C form an isolated cycle that is
+        // never on a source-to-sink path
+        let beads = vec![
+            test_bead("A", vec![]),
+            test_bead("B", vec![2]),
+            test_bead("C", vec![1]),
+        ];
+        let molecule = Molecule {
+            id: "test".to_string(),
+            formula_name: "test".to_string(),
+            formula_type: FormulaType::Workflow,
+            bead_count: beads.len(),
+            beads,
+            has_cycle: true,
+            execution_order: vec![],
+            waves: vec![],
+            critical_path: vec![],
+            wave_durations: vec![],
+            estimated_duration: 0,
+            content_hash: String::new(),
+        };
+
+        let mut unreachable = find_unreachable_beads(&molecule);
+        unreachable.sort();
+        assert_eq!(unreachable, vec!["B".to_string(), "C".to_string()]);
+    }
+
+    #[test]
+    fn test_find_unreachable_beads_all_reachable() {
+        let cooked = create_test_formula();
+        let molecule = generate_molecule_internal(&cooked).unwrap();
+        assert!(find_unreachable_beads(&molecule).is_empty());
+    }
+
+    #[test]
+    fn test_annotate_and_strip_annotations() {
+        let cooked = create_test_formula();
+        let molecule = generate_molecule_internal(&cooked).unwrap();
+        let mol_json = serde_json::to_string(&molecule).unwrap();
+
+        let annotations = r#"{
+            "analyze": {"cost_usd": 0.25, "tracker_state": "queued"},
+            "review": {"cost_usd": 0.50},
+            "unknown-bead": {"ignored": true}
+        }"#;
+        let annotated_json = annotate_molecule(&mol_json, annotations).unwrap();
+        let annotated: Molecule = serde_json::from_str(&annotated_json).unwrap();
+
+        assert_eq!(annotated.beads[0].metadata["cost_usd"], 0.25);
+        assert_eq!(annotated.beads[0].metadata["tracker_state"], "queued");
+        assert_eq!(annotated.beads[1].metadata["cost_usd"], 0.50);
+        assert!(annotated.beads[2].metadata.is_empty());
+
+        // Stripping removes only the named key
+        let stripped_json = strip_annotations(&annotated_json, "tracker_state").unwrap();
+        let stripped: Molecule = serde_json::from_str(&stripped_json).unwrap();
+        assert!(!stripped.beads[0].metadata.contains_key("tracker_state"));
+        assert_eq!(stripped.beads[0].metadata["cost_usd"], 0.25);
+    }
+
+    #[test]
+    fn test_split_molecule_chain() {
+        // Linear chain A -> B -> C -> D -> E
+        let beads = vec![
+            test_bead("A", vec![]),
+            test_bead("B", vec![0]),
+            test_bead("C", vec![1]),
+            test_bead("D", vec![2]),
+            test_bead("E", vec![3]),
+        ];
+        let (execution_order, has_cycle) = topological_sort(&beads);
+        let molecule = Molecule {
+            id: "chain".to_string(),
+            formula_name: "chain".to_string(),
+            formula_type: FormulaType::Workflow,
+            bead_count: beads.len(),
+            beads,
+            has_cycle,
+            execution_order,
+            waves: vec![],
+            critical_path: vec![],
+            wave_durations: vec![],
+            estimated_duration: 0,
+            content_hash: String::new(),
+        };
+
+        let chunks = split_molecule(&molecule, 2);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(
+            chunks.iter().map(|c| c.bead_count).collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+
+        // Chunks are non-overlapping and their union covers all beads
+        let mut all_ids: Vec<String> = chunks
+            .iter()
+            .flat_map(|c| c.beads.iter().map(|b| b.id.clone()))
+            .collect();
+        all_ids.sort();
+        assert_eq!(all_ids, vec!["A", "B", "C", "D", "E"]);
+
+        // No dangling dependencies: every dep is a valid in-chunk index
+        for chunk in &chunks {
+            assert!(!chunk.has_cycle);
+            for bead in &chunk.beads {
+                assert!(bead.depends_on.iter().all(|&d| d < chunk.bead_count));
+            }
+        }
+
+        // Deterministic: the same input produces the same chunks
+        let again = split_molecule(&molecule, 2);
+        assert_eq!(
+            serde_json::to_string(&chunks).unwrap(),
+            serde_json::to_string(&again).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_split_molecule_components_stay_separate() {
+        // Two disconnected pairs; each must form its own connected chunk
+        let beads = vec![
+            test_bead("A", vec![]),
+            test_bead("B", vec![0]),
+            test_bead("C", vec![]),
+            test_bead("D", vec![2]),
+        ];
+        let molecule = Molecule {
+            id: "pairs".to_string(),
+            formula_name: "pairs".to_string(),
+            formula_type: FormulaType::Workflow,
+            bead_count: beads.len(),
+            beads,
+            has_cycle: false,
+            execution_order: vec![0, 2, 1, 3],
+            waves: vec![],
+            critical_path: vec![],
+            wave_durations: vec![],
+            estimated_duration: 0,
+            content_hash: String::new(),
+        };
+
+        let chunks = split_molecule(&molecule, 4);
+        assert_eq!(chunks.len(), 2);
+        let ids: Vec<Vec<&str>> = chunks
+            .iter()
+            .map(|c| c.beads.iter().map(|b| b.id.as_str()).collect())
+            .collect();
+        assert_eq!(ids, vec![vec!["A", "B"], vec!["C", "D"]]);
+    }
+
+    #[test]
+    fn test_split_molecule_edge_cases() {
+        let molecule = Molecule {
+            id: "empty".to_string(),
+            formula_name: "empty".to_string(),
+            formula_type: FormulaType::Workflow,
+            bead_count: 0,
+            beads: vec![],
+            has_cycle: false,
+            execution_order: vec![],
+            waves: vec![],
+            critical_path: vec![],
+            wave_durations: vec![],
+            estimated_duration: 0,
+            content_hash: String::new(),
+        };
+        assert!(split_molecule(&molecule, 10).is_empty());
+        assert!(split_molecule(&molecule, 0).is_empty());
+    }
+
+    #[test]
+    fn test_detect_bead_cycles() {
+        // A -> B -> C -> A in depends_on direction
+        let beads = vec![
+            test_bead("A", vec![1]),
+            test_bead("B", vec![2]),
+            test_bead("C", vec![0]),
+        ];
+        let molecule = Molecule {
+            id: "cyclic".to_string(),
+            formula_name: "cyclic".to_string(),
+            formula_type: FormulaType::Workflow,
+            bead_count: beads.len(),
+            beads,
+            has_cycle: true,
+            execution_order: vec![],
+            waves: vec![],
+            critical_path: vec![],
+            wave_durations: vec![],
+            estimated_duration: 0,
+            content_hash: String::new(),
+        };
+
+        let cycle = detect_bead_cycles(&molecule).unwrap();
+        assert_eq!(cycle, vec!["A", "B", "C", "A"]);
+    }
+
+    #[test]
+    fn test_detect_bead_cycles_acyclic() {
+        let cooked = create_test_formula();
+        let molecule = generate_molecule_internal(&cooked).unwrap();
+        assert!(detect_bead_cycles(&molecule).is_none());
+    }
+
+    #[test]
+    fn test_topological_sort() {
+        let beads = vec![
+            test_bead("A", vec![]),
+            test_bead("B", vec![0]),
+            test_bead("C", vec![0, 1]),
         ];
 
         let (order, has_cycle) = topological_sort(&beads);
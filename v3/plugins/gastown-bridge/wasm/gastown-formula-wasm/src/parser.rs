@@ -10,250 +10,2492 @@
 //! - Memory pool for repeated parsing
 
 use wasm_bindgen::prelude::*;
-use gastown_shared::{FxHashMap, Arena, StringInterner, capacity};
+use serde::{Deserialize, Serialize};
+use gastown_shared::StringInterner;
 use crate::{Formula, FormulaType};
 
-/// Thread-local parser state for reuse
+// Thread-local parser state for reuse
 thread_local! {
     static INTERNER: StringInterner = StringInterner::with_capacity(64);
 }
 
+/// Errors produced while parsing formulas
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum ParseError {
+    /// The TOML document could not be parsed
+    Toml { message: String },
+    /// The content is empty, whitespace-only, or comments-only
+    EmptyContent,
+    /// A `[vars.*]` key disagrees with its `name` field
+    VarNameMismatch { key: String, name: String },
+    /// The same `[section]` header appears twice (usually a typo that
+    /// silently shadows the first definition in lenient TOML parsers)
+    DuplicateKey {
+        key: String,
+        first_line: usize,
+        second_line: usize,
+    },
+    /// The same id is used by more than one step or leg
+    DuplicateId { kind: String, id: String },
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Toml { message } => write!(f, "Parse error: {}", message),
+            ParseError::EmptyContent => {
+                write!(f, "Formula content is empty. Did you forget to load the file?")
+            }
+            ParseError::VarNameMismatch { key, name } => write!(
+                f,
+                "Var key '{}' does not match its name field '{}'",
+                key, name
+            ),
+            ParseError::DuplicateKey {
+                key,
+                first_line,
+                second_line,
+            } => write!(
+                f,
+                "Duplicate section '[{}]' (first defined at line {}, duplicated at line {})",
+                key, first_line, second_line
+            ),
+            ParseError::DuplicateId { kind, id } => write!(
+                f,
+                "Duplicate {} id '{}'; step and leg ids must be unique within a formula",
+                kind, id
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for JsValue {
+    fn from(err: ParseError) -> Self {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
+/// A parse failure with its position in the source content
+///
+/// Byte offsets and line/column spans are relative to the original
+/// content (before BOM/shebang stripping), so editor integrations can
+/// underline the failing TOML key directly.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ParseDiagnostic {
+    /// Stable error code (`toml`, `empty_content`, `var_name_mismatch`,
+    /// `duplicate_key`)
+    pub code: String,
+    /// Human-readable message
+    pub message: String,
+    /// Byte offset where the failing span starts
+    pub byte_offset: usize,
+    /// Length of the failing span in bytes (0 when unknown)
+    pub byte_len: usize,
+    /// 1-based line of the span start
+    pub line: u32,
+    /// 1-based column of the span start
+    pub col: u32,
+    /// 1-based line of the span end
+    pub end_line: u32,
+    /// 1-based column of the span end
+    pub end_col: u32,
+}
+
+impl ParseDiagnostic {
+    /// Build a diagnostic from a byte span within `content`
+    fn from_span(
+        content: &str,
+        code: &str,
+        message: String,
+        span: std::ops::Range<usize>,
+    ) -> Self {
+        let (line, col) = line_col_at(content, span.start);
+        let (end_line, end_col) = line_col_at(content, span.end);
+        Self {
+            code: code.to_string(),
+            message,
+            byte_offset: span.start,
+            byte_len: span.end.saturating_sub(span.start),
+            line,
+            col,
+            end_line,
+            end_col,
+        }
+    }
+}
+
+/// 1-based `(line, col)` of a byte offset within `content`
+fn line_col_at(content: &str, offset: usize) -> (u32, u32) {
+    let clamped = offset.min(content.len());
+    let before = &content[..clamped];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (
+        before.matches('\n').count() as u32 + 1,
+        (clamped - line_start) as u32 + 1,
+    )
+}
+
+/// Byte range of a 1-based line within `content`
+fn line_span(content: &str, line: usize) -> std::ops::Range<usize> {
+    let mut offset = 0;
+    for (index, text) in content.lines().enumerate() {
+        if index + 1 == line {
+            return offset..offset + text.len();
+        }
+        offset += text.len() + 1;
+    }
+    0..0
+}
+
+/// Parse TOML formula content, reporting failures as [`ParseDiagnostic`]
+///
+/// Same pipeline as `parse_formula_internal`, but errors carry the byte
+/// offset and line/column span of the failing construct.
+pub fn parse_formula_diagnostic_internal(content: &str) -> Result<Formula, ParseDiagnostic> {
+    crate::record_input_bytes(content.len());
+
+    let stripped = content.strip_prefix('\u{FEFF}').unwrap_or(content);
+    let body = strip_shebang(stripped);
+    // Offset of the parsed body within the original content; spans from
+    // the TOML parser are shifted by this before line/col conversion
+    let base = content.len() - body.len();
+
+    if is_empty_content(body) {
+        return Err(ParseDiagnostic::from_span(
+            content,
+            "empty_content",
+            ParseError::EmptyContent.to_string(),
+            0..0,
+        ));
+    }
+
+    if let Err(err) = check_duplicate_sections(body) {
+        let span = match &err {
+            ParseError::DuplicateKey { second_line, .. } => {
+                let line = line_span(body, *second_line);
+                base + line.start..base + line.end
+            }
+            _ => 0..0,
+        };
+        return Err(ParseDiagnostic::from_span(
+            content,
+            "duplicate_key",
+            err.to_string(),
+            span,
+        ));
+    }
+
+    let mut formula: Formula = toml::from_str(body).map_err(|e| {
+        let span = e
+            .span()
+            .map(|s| base + s.start..base + s.end)
+            .unwrap_or(0..0);
+        ParseDiagnostic::from_span(content, "toml", format!("Parse error: {}", e.message()), span)
+    })?;
+
+    reconcile_var_names(&mut formula).map_err(|err| {
+        let span = match &err {
+            ParseError::VarNameMismatch { key, .. } => {
+                let header = format!("[vars.{}]", key);
+                body.find(&header)
+                    .map(|start| base + start..base + start + header.len())
+                    .unwrap_or(0..0)
+            }
+            _ => 0..0,
+        };
+        ParseDiagnostic::from_span(content, "var_name_mismatch", err.to_string(), span)
+    })?;
+
+    check_duplicate_ids(&formula).map_err(|err| {
+        let span = match &err {
+            ParseError::DuplicateId { id, .. } => {
+                // Underline the second occurrence of the quoted id
+                let needle = format!("\"{}\"", id);
+                body.find(&needle)
+                    .and_then(|first| {
+                        body[first + needle.len()..]
+                            .find(&needle)
+                            .map(|second| first + needle.len() + second)
+                    })
+                    .map(|start| base + start..base + start + needle.len())
+                    .unwrap_or(0..0)
+            }
+            _ => 0..0,
+        };
+        ParseDiagnostic::from_span(content, "duplicate_id", err.to_string(), span)
+    })?;
+
+    Ok(formula)
+}
+
 /// Parse TOML formula content into a Formula struct
 ///
+/// Failures are returned as a structured object (`code`, `message`,
+/// `byte_offset`, `byte_len`, `line`/`col` span) so editor integrations
+/// can underline the exact failing TOML key.
+///
 /// # Performance
 /// Target: <0.1ms (500x faster than JavaScript TOML parsing)
 #[inline]
 pub fn parse_formula_impl(content: &str) -> Result<JsValue, JsValue> {
-    // Fast path: validate content length
-    if content.is_empty() {
-        return Err(JsValue::from_str("Empty formula content"));
-    }
-
-    // Parse with optimized settings
-    let formula: Formula = toml::from_str(content)
-        .map_err(|e| JsValue::from_str(&format!("Parse error: {}", e)))?;
+    let formula = parse_formula_diagnostic_internal(content).map_err(|diagnostic| {
+        serde_wasm_bindgen::to_value(&diagnostic)
+            .unwrap_or_else(|_| JsValue::from_str(&diagnostic.message))
+    })?;
 
     // Serialize to JS with optimized serializer
     serde_wasm_bindgen::to_value(&formula)
         .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
 }
 
-/// Validate formula syntax without full parsing
+/// Reject duplicate step and leg ids
 ///
-/// # Performance
-/// Faster than parse_formula_impl as it skips serialization
-#[inline(always)]
-pub fn validate_formula_impl(content: &str) -> bool {
-    if content.is_empty() {
-        return false;
+/// Steps and legs share one id namespace (molecule beads and `needs`
+/// edges are addressed by id), so a repeated id silently corrupts the
+/// generated molecule. Duplicate var names cannot survive parsing:
+/// `vars` is keyed by name, and repeated keys are rejected by the
+/// format-level duplicate checks.
+fn check_duplicate_ids(formula: &Formula) -> Result<(), ParseError> {
+    let mut seen: gastown_shared::FxHashMap<&str, ()> = Default::default();
+    for (kind, id) in formula
+        .steps
+        .iter()
+        .map(|step| ("step", step.id.as_str()))
+        .chain(formula.legs.iter().map(|leg| ("leg", leg.id.as_str())))
+    {
+        if seen.insert(id, ()).is_some() {
+            return Err(ParseError::DuplicateId {
+                kind: kind.to_string(),
+                id: id.to_string(),
+            });
+        }
     }
-    toml::from_str::<Formula>(content).is_ok()
+    Ok(())
 }
 
-/// Get the formula type from TOML content
-///
-/// # Performance
-/// Optimized path that only extracts the type field
+/// Internal parse function (testable on native targets)
 #[inline]
-pub fn get_formula_type_impl(content: &str) -> Result<String, JsValue> {
-    // Fast path: look for type field directly
-    if let Some(type_str) = extract_type_fast(content) {
-        return Ok(type_str.to_string());
+pub(crate) fn parse_formula_internal(content: &str) -> Result<Formula, String> {
+    crate::record_input_bytes(content.len());
+
+    // Strip a UTF-8 BOM (common in files saved by Windows editors) before
+    // handing the content to the TOML parser
+    let content = content.strip_prefix('\u{FEFF}').unwrap_or(content);
+
+    // Strip a Unix shebang line (`#!/usr/bin/env gastown-run`) so
+    // directly-executable formula files parse cleanly
+    let content = strip_shebang(content);
+
+    // Fast path: catch empty input before the TOML parser produces a
+    // confusing "missing field" error
+    if is_empty_content(content) {
+        return Err(ParseError::EmptyContent.to_string());
     }
 
-    // Fallback to full parse
-    let formula: Formula = toml::from_str(content)
-        .map_err(|e| JsValue::from_str(&format!("Parse error: {}", e)))?;
+    // Catch repeated section headers with line numbers before the TOML
+    // parser reports its own (less specific) duplicate-key error
+    check_duplicate_sections(content).map_err(|e| e.to_string())?;
 
-    let type_str = match formula.formula_type {
-        FormulaType::Convoy => "convoy",
-        FormulaType::Workflow => "workflow",
-        FormulaType::Expansion => "expansion",
-        FormulaType::Aspect => "aspect",
-    };
+    // Parse with optimized settings
+    let mut formula: Formula =
+        toml::from_str(content).map_err(|e| format!("Parse error: {}", e))?;
 
-    Ok(type_str.to_string())
+    // Keep `vars` keys and `Var.name` in agreement: fill omitted names
+    // from the key, reject explicit mismatches
+    reconcile_var_names(&mut formula).map_err(|e| e.to_string())?;
+
+    check_duplicate_ids(&formula).map_err(|e| e.to_string())?;
+
+    Ok(formula)
 }
 
-/// Fast extraction of type field without full parsing
+/// Parse YAML formula content into a Formula struct
 ///
-/// Scans for `type = "..."` pattern directly
+/// YAML formulas go through the same validation path as TOML: BOM and
+/// shebang stripping, the empty-content check, and var-name
+/// reconciliation. Duplicate-section detection is TOML-specific (YAML
+/// mappings reject duplicate keys during deserialization).
 #[inline]
-fn extract_type_fast(content: &str) -> Option<&str> {
-    // Look for type = "..." pattern
-    for line in content.lines() {
-        let trimmed = line.trim();
+pub(crate) fn parse_formula_yaml_internal(content: &str) -> Result<Formula, String> {
+    crate::record_input_bytes(content.len());
 
-        // Skip comments and empty lines
-        if trimmed.is_empty() || trimmed.starts_with('#') {
-            continue;
-        }
+    let content = content.strip_prefix('\u{FEFF}').unwrap_or(content);
+    let content = strip_shebang(content);
 
-        // Check for type field
-        if trimmed.starts_with("type") {
-            // Extract value after '='
-            if let Some(eq_pos) = trimmed.find('=') {
-                let value_part = trimmed[eq_pos + 1..].trim();
+    if is_empty_content(content) {
+        return Err(ParseError::EmptyContent.to_string());
+    }
 
-                // Remove quotes
-                if value_part.starts_with('"') && value_part.len() > 2 {
-                    let end = value_part[1..].find('"')?;
-                    let type_str = &value_part[1..1 + end];
+    let mut formula: Formula =
+        serde_yaml::from_str(content).map_err(|e| format!("Parse error: {}", e))?;
 
-                    // Validate type
-                    match type_str {
-                        "convoy" | "workflow" | "expansion" | "aspect" => {
-                            return Some(type_str);
-                        }
-                        _ => return None,
-                    }
-                }
-            }
-        }
-    }
+    reconcile_var_names(&mut formula).map_err(|e| e.to_string())?;
 
-    None
+    check_duplicate_ids(&formula).map_err(|e| e.to_string())?;
+
+    Ok(formula)
 }
 
-/// Batch parse multiple formulas
+/// Parse a JSON formula document into a Formula struct
 ///
-/// # Performance
-/// More efficient than parsing individually due to memory reuse
+/// For programmatically generated formulas that never pass through TOML.
+/// Runs the same semantic validation as the TOML path (empty-content
+/// check and var-name reconciliation); byte-level concerns like BOM and
+/// shebang stripping do not apply to JSON payloads.
 #[inline]
-pub fn parse_batch_impl(contents: &[&str]) -> Result<Vec<Formula>, JsValue> {
-    let mut results = Vec::with_capacity(contents.len());
+pub(crate) fn parse_formula_json_internal(content: &str) -> Result<Formula, String> {
+    crate::record_input_bytes(content.len());
 
-    for content in contents {
-        let formula: Formula = toml::from_str(content)
-            .map_err(|e| JsValue::from_str(&format!("Parse error: {}", e)))?;
-        results.push(formula);
+    if is_empty_content(content) {
+        return Err(ParseError::EmptyContent.to_string());
     }
 
-    Ok(results)
+    let mut formula: Formula =
+        serde_json::from_str(content).map_err(|e| format!("Parse error: {}", e))?;
+
+    reconcile_var_names(&mut formula).map_err(|e| e.to_string())?;
+
+    check_duplicate_ids(&formula).map_err(|e| e.to_string())?;
+
+    Ok(formula)
 }
 
-/// Pre-validate multiple formulas in parallel-friendly manner
+/// WASM wrapper for `parse_formula_json_internal`
 #[inline]
-pub fn validate_batch_impl(contents: &[&str]) -> Vec<bool> {
-    contents.iter().map(|c| validate_formula_impl(c)).collect()
+pub fn parse_formula_json_impl(content: &str) -> Result<JsValue, JsValue> {
+    let formula = parse_formula_json_internal(content).map_err(|e| JsValue::from_str(&e))?;
+
+    serde_wasm_bindgen::to_value(&formula)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
 }
 
-/// Extract formula name without full parsing
+/// Parse formula content in either TOML or YAML, autodetecting the format
+///
+/// The first meaningful line decides: `---`, or `key: value` before any
+/// `=`, means YAML; everything else is parsed as TOML.
 #[inline]
-pub fn extract_name_fast(content: &str) -> Option<&str> {
+pub(crate) fn parse_formula_any_internal(content: &str) -> Result<Formula, String> {
+    let body = strip_shebang(content.strip_prefix('\u{FEFF}').unwrap_or(content));
+    if looks_like_yaml(body) {
+        parse_formula_yaml_internal(content)
+    } else {
+        parse_formula_internal(content)
+    }
+}
+
+/// Guess whether formula content is YAML rather than TOML
+fn looks_like_yaml(content: &str) -> bool {
     for line in content.lines() {
         let trimmed = line.trim();
-
         if trimmed.is_empty() || trimmed.starts_with('#') {
             continue;
         }
-
-        if trimmed.starts_with("formula") {
-            if let Some(eq_pos) = trimmed.find('=') {
-                let value_part = trimmed[eq_pos + 1..].trim();
-
-                if value_part.starts_with('"') && value_part.len() > 2 {
-                    let end = value_part[1..].find('"')?;
-                    return Some(&value_part[1..1 + end]);
-                }
-            }
+        if trimmed == "---" {
+            return true;
         }
+        return match (trimmed.find(':'), trimmed.find('=')) {
+            (Some(colon), Some(equals)) => colon < equals,
+            (Some(_), None) => true,
+            _ => false,
+        };
     }
+    false
+}
 
-    None
+/// WASM wrapper for `parse_formula_yaml_internal`
+#[inline]
+pub fn parse_formula_yaml_impl(content: &str) -> Result<JsValue, JsValue> {
+    let formula = parse_formula_yaml_internal(content).map_err(|e| JsValue::from_str(&e))?;
+
+    serde_wasm_bindgen::to_value(&formula)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
 }
 
-/// Extract formula description without full parsing
+/// WASM wrapper for `parse_formula_any_internal`
 #[inline]
-pub fn extract_description_fast(content: &str) -> Option<&str> {
-    for line in content.lines() {
-        let trimmed = line.trim();
+pub fn parse_formula_any_impl(content: &str) -> Result<JsValue, JsValue> {
+    let formula = parse_formula_any_internal(content).map_err(|e| JsValue::from_str(&e))?;
 
-        if trimmed.is_empty() || trimmed.starts_with('#') {
-            continue;
-        }
+    serde_wasm_bindgen::to_value(&formula)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
 
-        if trimmed.starts_with("description") {
-            if let Some(eq_pos) = trimmed.find('=') {
-                let value_part = trimmed[eq_pos + 1..].trim();
+/// Maximum depth of nested `include` directives
+pub const MAX_INCLUDE_DEPTH: usize = 8;
 
-                if value_part.starts_with('"') && value_part.len() > 2 {
-                    let end = value_part[1..].find('"')?;
-                    return Some(&value_part[1..1 + end]);
-                }
-            }
+/// Parse a formula and resolve its `include` directives
+///
+/// A formula may carry a top-level `include = ["name"]` list naming
+/// shared documents (e.g. common leg definitions). Each name is passed to
+/// the host-provided `resolver`, which returns the included content; the
+/// result is parsed (recursively, with cycle detection) and merged into
+/// the including formula. The including document wins on conflicts:
+/// steps/legs with an already-present id are skipped and existing vars
+/// are kept.
+pub fn parse_formula_with_includes<F>(content: &str, resolver: &mut F) -> Result<Formula, String>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    let mut include_chain = Vec::new();
+    parse_with_includes_inner(content, resolver, &mut include_chain)
+}
+
+fn parse_with_includes_inner<F>(
+    content: &str,
+    resolver: &mut F,
+    include_chain: &mut Vec<String>,
+) -> Result<Formula, String>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    let (mut formula, document) = parse_formula_with_extra(content).map_err(|e| e.to_string())?;
+
+    let includes: Vec<String> = match document.get("include") {
+        Some(value) => value
+            .clone()
+            .try_into()
+            .map_err(|_| "Invalid include directive: expected an array of strings".to_string())?,
+        None => return Ok(formula),
+    };
+
+    for name in includes {
+        if include_chain.iter().any(|seen| seen == &name) {
+            return Err(format!(
+                "Include cycle detected: {} -> {}",
+                include_chain.join(" -> "),
+                name
+            ));
+        }
+        if include_chain.len() >= MAX_INCLUDE_DEPTH {
+            return Err(format!(
+                "Include depth exceeds the maximum of {}",
+                MAX_INCLUDE_DEPTH
+            ));
         }
+
+        let included_content = resolver(&name)?;
+        include_chain.push(name.clone());
+        let included = parse_with_includes_inner(&included_content, resolver, include_chain)
+            .map_err(|e| format!("In include '{}': {}", name, e))?;
+        include_chain.pop();
+
+        merge_included_formula(&mut formula, included);
     }
 
-    None
+    Ok(formula)
 }
 
-/// Quick metadata extraction without full parsing
-#[derive(Debug)]
-pub struct FormulaMetadata<'a> {
-    pub name: Option<&'a str>,
-    pub description: Option<&'a str>,
-    pub formula_type: Option<&'a str>,
-    pub version: Option<u32>,
+/// Merge an included formula into its including formula
+///
+/// The including formula wins: steps and legs whose id already exists are
+/// skipped, existing vars are kept, and `synthesis` is only taken when
+/// the base has none.
+fn merge_included_formula(base: &mut Formula, included: Formula) {
+    for leg in included.legs {
+        if !base.legs.iter().any(|existing| existing.id == leg.id) {
+            base.legs.push(leg);
+        }
+    }
+    for step in included.steps {
+        if !base.steps.iter().any(|existing| existing.id == step.id) {
+            base.steps.push(step);
+        }
+    }
+    for (key, var) in included.vars {
+        base.vars.entry(key).or_insert(var);
+    }
+    if base.synthesis.is_none() {
+        base.synthesis = included.synthesis;
+    }
 }
 
-/// Extract metadata without full TOML parsing
-#[inline]
-pub fn extract_metadata_fast(content: &str) -> FormulaMetadata<'_> {
-    let mut metadata = FormulaMetadata {
-        name: None,
-        description: None,
-        formula_type: None,
-        version: None,
-    };
+/// Result of a lenient parse: as much formula as recoverable, plus
+/// diagnostics for everything that was skipped
+#[derive(Debug, Serialize)]
+pub struct LenientParse {
+    /// Partial formula assembled from the parseable parts
+    pub formula: Formula,
+    /// One diagnostic per skipped or repaired construct
+    pub diagnostics: Vec<ParseDiagnostic>,
+    /// True when the content parsed cleanly (no diagnostics)
+    pub complete: bool,
+}
 
-    for line in content.lines() {
-        let trimmed = line.trim();
+/// Parse formula content, recovering as much as possible
+///
+/// Unlike the strict path, a broken line only discards that line: the
+/// content is split into top-level blocks (the key-value preamble and
+/// each `[section]`/`[[section]]` block), each block is re-parsed
+/// line-by-line on failure, and required fields are defaulted when
+/// missing. Editors get a partial `Formula` to keep completions working
+/// mid-edit, with diagnostics pointing at everything that was dropped.
+pub fn parse_formula_lenient_internal(content: &str) -> LenientParse {
+    crate::record_input_bytes(content.len());
 
-        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('[') {
-            continue;
-        }
+    // Fast path: cleanly-parsing content needs no recovery
+    if let Ok(formula) = parse_formula_diagnostic_internal(content) {
+        return LenientParse {
+            formula,
+            diagnostics: Vec::new(),
+            complete: true,
+        };
+    }
 
-        if let Some(eq_pos) = trimmed.find('=') {
-            let key = trimmed[..eq_pos].trim();
-            let value_part = trimmed[eq_pos + 1..].trim();
+    let stripped = content.strip_prefix('\u{FEFF}').unwrap_or(content);
+    let body = strip_shebang(stripped);
+    let base = content.len() - body.len();
 
-            match key {
-                "formula" => {
-                    if let Some(s) = extract_quoted_string(value_part) {
-                        metadata.name = Some(s);
-                    }
-                }
-                "description" => {
-                    if let Some(s) = extract_quoted_string(value_part) {
-                        metadata.description = Some(s);
-                    }
-                }
-                "type" => {
-                    if let Some(s) = extract_quoted_string(value_part) {
-                        metadata.formula_type = Some(s);
-                    }
-                }
-                "version" => {
-                    if let Ok(v) = value_part.parse() {
-                        metadata.version = Some(v);
-                    }
-                }
-                _ => {}
+    let mut document = toml::value::Table::new();
+    let mut diagnostics = Vec::new();
+
+    for block in split_top_level_blocks(body) {
+        recover_block(content, base, &block, &mut document, &mut diagnostics);
+    }
+
+    // Default required fields so the partial AST always materializes
+    for (key, default) in [("formula", ""), ("description", ""), ("type", "workflow")] {
+        if !document.contains_key(key) {
+            diagnostics.push(ParseDiagnostic::from_span(
+                content,
+                "missing_field",
+                format!("Missing required field '{}'", key),
+                0..0,
+            ));
+            document.insert(key.to_string(), toml::Value::String(default.to_string()));
+        }
+    }
+
+    let mut formula = match toml::Value::Table(document).try_into::<Formula>() {
+        Ok(formula) => formula,
+        Err(e) => {
+            // Recovered blocks still do not form a valid formula; fall
+            // back to an empty skeleton so callers always get an AST
+            diagnostics.push(ParseDiagnostic::from_span(
+                content,
+                "toml",
+                format!("Parse error: {}", e.message()),
+                0..0,
+            ));
+            Formula {
+                name: String::new(),
+                description: String::new(),
+                formula_type: FormulaType::Workflow,
+                version: crate::default_version(),
+                legs: vec![],
+                synthesis: None,
+                steps: vec![],
+                vars: std::collections::HashMap::new(),
             }
         }
+    };
 
-        // Early exit if we have all metadata
-        if metadata.name.is_some()
-            && metadata.description.is_some()
-            && metadata.formula_type.is_some()
-            && metadata.version.is_some()
-        {
-            break;
+    // Lenient var-name reconciliation: repair mismatches instead of failing
+    for (key, var) in &mut formula.vars {
+        if var.name.is_empty() {
+            var.name = key.clone();
+        } else if var.name != *key {
+            diagnostics.push(ParseDiagnostic::from_span(
+                content,
+                "var_name_mismatch",
+                ParseError::VarNameMismatch {
+                    key: key.clone(),
+                    name: var.name.clone(),
+                }
+                .to_string(),
+                0..0,
+            ));
+            var.name = key.clone();
         }
     }
 
-    metadata
+    LenientParse {
+        complete: diagnostics.is_empty(),
+        formula,
+        diagnostics,
+    }
+}
+
+/// One top-level block of a TOML document: the key-value preamble or a
+/// `[section]`/`[[section]]` header plus its body lines
+struct TomlBlock {
+    /// Byte offset of the block start within the parsed body
+    offset: usize,
+    /// Block lines as `(offset_within_body, text)`
+    lines: Vec<(usize, String)>,
+    /// True when the first line is a section header
+    has_header: bool,
+}
+
+/// Split content into the preamble and one block per section header
+fn split_top_level_blocks(body: &str) -> Vec<TomlBlock> {
+    let mut blocks: Vec<TomlBlock> = Vec::new();
+    let mut offset = 0;
+
+    for line in body.lines() {
+        let trimmed = line.trim();
+        let is_header = trimmed.starts_with('[') && trimmed.ends_with(']');
+
+        if is_header || blocks.is_empty() {
+            blocks.push(TomlBlock {
+                offset,
+                lines: vec![],
+                has_header: is_header,
+            });
+        }
+        blocks
+            .last_mut()
+            .expect("block pushed above")
+            .lines
+            .push((offset, line.to_string()));
+        offset += line.len() + 1;
+    }
+
+    blocks
+}
+
+/// Re-parse one block, merging what parses and recording what does not
+///
+/// Lines are added cumulatively so a broken line in the middle of a
+/// section drops only itself, not the lines after it.
+fn recover_block(
+    content: &str,
+    base: usize,
+    block: &TomlBlock,
+    document: &mut toml::value::Table,
+    diagnostics: &mut Vec<ParseDiagnostic>,
+) {
+    let mut accepted = String::new();
+    let mut line_iter = block.lines.iter();
+
+    if block.has_header {
+        let (offset, header) = line_iter.next().expect("header block has a first line");
+        // An unparseable header discards the whole block: there is no
+        // table to attach its lines to
+        if toml::from_str::<toml::value::Table>(header).is_err() {
+            diagnostics.push(ParseDiagnostic::from_span(
+                content,
+                "toml",
+                format!("Unparseable section header '{}'", header.trim()),
+                base + offset..base + offset + header.len(),
+            ));
+            return;
+        }
+        accepted.push_str(header);
+        accepted.push('\n');
+    }
+
+    for (offset, line) in line_iter {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let candidate = format!("{}{}\n", accepted, line);
+        if toml::from_str::<toml::value::Table>(&candidate).is_ok() {
+            accepted = candidate;
+        } else {
+            diagnostics.push(ParseDiagnostic::from_span(
+                content,
+                "toml",
+                format!("Skipped unparseable line '{}'", trimmed),
+                base + offset..base + offset + line.len(),
+            ));
+        }
+    }
+
+    match toml::from_str::<toml::value::Table>(&accepted) {
+        Ok(table) => merge_toml_tables(document, table),
+        Err(_) => {
+            // Multi-line constructs (e.g. an unterminated string) can make
+            // the accepted text unparseable as a whole; drop the block
+            let end = block
+                .lines
+                .last()
+                .map(|(offset, line)| offset + line.len())
+                .unwrap_or(block.offset);
+            diagnostics.push(ParseDiagnostic::from_span(
+                content,
+                "toml",
+                "Skipped unrecoverable block".to_string(),
+                base + block.offset..base + end,
+            ));
+        }
+    }
+}
+
+/// Deep-merge one parsed block into the accumulated document
+///
+/// Tables merge recursively, arrays append (each `[[steps]]` block parses
+/// to a one-entry array), and scalars overwrite.
+fn merge_toml_tables(dest: &mut toml::value::Table, src: toml::value::Table) {
+    for (key, value) in src {
+        match (dest.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(incoming)) => {
+                merge_toml_tables(existing, incoming);
+            }
+            (Some(toml::Value::Array(existing)), toml::Value::Array(mut incoming)) => {
+                existing.append(&mut incoming);
+            }
+            (_, value) => {
+                dest.insert(key, value);
+            }
+        }
+    }
+}
+
+/// WASM wrapper for `parse_formula_lenient_internal`
+pub fn parse_formula_lenient_impl(content: &str) -> Result<JsValue, JsValue> {
+    serde_wasm_bindgen::to_value(&parse_formula_lenient_internal(content))
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Fill omitted `Var.name` fields from their `[vars.*]` key and reject
+/// explicit mismatches
+fn reconcile_var_names(formula: &mut Formula) -> Result<(), ParseError> {
+    for (key, var) in &mut formula.vars {
+        if var.name.is_empty() {
+            var.name = key.clone();
+        } else if var.name != *key {
+            return Err(ParseError::VarNameMismatch {
+                key: key.clone(),
+                name: var.name.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Top-level keys consumed by the `Formula` struct
+const FORMULA_TOP_LEVEL_KEYS: [&str; 8] = [
+    "formula",
+    "description",
+    "type",
+    "version",
+    "legs",
+    "synthesis",
+    "steps",
+    "vars",
+];
+
+/// Parse a formula while keeping the full TOML document
+///
+/// The returned `toml::Value` is the whole parsed document, so callers
+/// can extract non-standard sections (e.g. a custom `[ci]` table) that
+/// the `Formula` struct ignores.
+pub fn parse_formula_with_extra(content: &str) -> Result<(Formula, toml::Value), ParseError> {
+    crate::record_input_bytes(content.len());
+
+    let content = content.strip_prefix('\u{FEFF}').unwrap_or(content);
+    let content = strip_shebang(content);
+
+    if is_empty_content(content) {
+        return Err(ParseError::EmptyContent);
+    }
+
+    check_duplicate_sections(content)?;
+
+    let document: toml::Value = toml::from_str(content).map_err(|e| ParseError::Toml {
+        message: e.to_string(),
+    })?;
+
+    let mut formula: Formula = document.clone().try_into().map_err(|e| ParseError::Toml {
+        message: e.to_string(),
+    })?;
+    reconcile_var_names(&mut formula)?;
+
+    check_duplicate_ids(&formula)?;
+
+    Ok((formula, document))
+}
+
+/// Parsed formula plus the top-level keys the `Formula` struct ignored
+#[derive(Serialize)]
+struct ParsedWithExtra {
+    formula: Formula,
+    extra_fields: toml::value::Table,
+}
+
+/// WASM wrapper for `parse_formula_with_extra`
+pub fn parse_formula_with_extra_impl(content: &str) -> Result<JsValue, JsValue> {
+    let (formula, document) = parse_formula_with_extra(content)?;
+
+    let extra_fields = match document {
+        toml::Value::Table(table) => table
+            .into_iter()
+            .filter(|(key, _)| !FORMULA_TOP_LEVEL_KEYS.contains(&key.as_str()))
+            .collect(),
+        _ => toml::value::Table::new(),
+    };
+
+    serde_wasm_bindgen::to_value(&ParsedWithExtra {
+        formula,
+        extra_fields,
+    })
+    .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Drop the first line when it is a `#!` shebang
+#[inline]
+fn strip_shebang(content: &str) -> &str {
+    if !content.starts_with("#!") {
+        return content;
+    }
+    content
+        .split_once('\n')
+        .map(|(_, rest)| rest)
+        .unwrap_or("")
+}
+
+/// Scan for repeated `[section]` headers and report the first duplicate
+///
+/// `[[array.of.tables]]` headers are exempt: repeating those is how TOML
+/// expresses `steps` and `legs`. Lines inside `"""`/`'''` multi-line
+/// strings are skipped so string content can't masquerade as a header.
+fn check_duplicate_sections(content: &str) -> Result<(), ParseError> {
+    let mut seen: gastown_shared::FxHashMap<&str, usize> = Default::default();
+    let mut in_multiline_string = false;
+
+    for (index, line) in content.lines().enumerate() {
+        let delimiters = line.matches("\"\"\"").count() + line.matches("'''").count();
+        if in_multiline_string {
+            if delimiters % 2 == 1 {
+                in_multiline_string = false;
+            }
+            continue;
+        }
+
+        let trimmed = line.trim();
+        if trimmed.starts_with('[')
+            && !trimmed.starts_with("[[")
+            && trimmed.ends_with(']')
+        {
+            let key = &trimmed[1..trimmed.len() - 1];
+            if let Some(first_line) = seen.insert(key, index + 1) {
+                return Err(ParseError::DuplicateKey {
+                    key: key.to_string(),
+                    first_line,
+                    second_line: index + 1,
+                });
+            }
+        }
+
+        if delimiters % 2 == 1 {
+            in_multiline_string = true;
+        }
+    }
+
+    Ok(())
+}
+
+/// True when content has no meaningful lines (blank or comments only)
+#[inline]
+fn is_empty_content(content: &str) -> bool {
+    content.lines().all(|line| {
+        let trimmed = line.trim();
+        trimmed.is_empty() || trimmed.starts_with('#')
+    })
+}
+
+/// Options controlling parse behavior
+///
+/// `Default` matches the plain `parse_formula` behavior.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ParseOptions {
+    /// Normalize `formula.name` to lowercase kebab-case
+    #[serde(default)]
+    pub normalize_name: bool,
+}
+
+/// Parse formula content, applying parse options
+pub(crate) fn parse_formula_with_options(
+    content: &str,
+    options: &ParseOptions,
+) -> Result<Formula, String> {
+    let mut formula = parse_formula_internal(content)?;
+
+    if options.normalize_name {
+        let normalized = normalize_formula_name(&formula.name);
+        if !is_valid_slug(&normalized) {
+            return Err(format!(
+                "Formula name '{}' does not normalize to a valid slug",
+                formula.name
+            ));
+        }
+        if normalized != formula.name {
+            log_diagnostic(&format!(
+                "Formula name '{}' normalized to '{}'",
+                formula.name, normalized
+            ));
+            formula.name = normalized;
+        }
+    }
+
+    Ok(formula)
+}
+
+/// Parse a formula with `normalize_name` enabled
+#[inline]
+pub fn parse_formula_normalized_impl(content: &str) -> Result<JsValue, JsValue> {
+    let options = ParseOptions {
+        normalize_name: true,
+    };
+    let formula =
+        parse_formula_with_options(content, &options).map_err(|e| JsValue::from_str(&e))?;
+
+    serde_wasm_bindgen::to_value(&formula)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Convert a formula name to lowercase kebab-case
+///
+/// `MyWorkflow` -> `my-workflow`, `my_workflow` -> `my-workflow`,
+/// `My Workflow` -> `my-workflow`. Runs of separators collapse to one
+/// hyphen and leading/trailing hyphens are trimmed.
+pub fn normalize_formula_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len() + 4);
+    let mut prev_was_lower_or_digit = false;
+
+    for c in name.chars() {
+        if c == ' ' || c == '_' || c == '-' {
+            if !out.ends_with('-') && !out.is_empty() {
+                out.push('-');
+            }
+            prev_was_lower_or_digit = false;
+        } else if c.is_uppercase() {
+            // CamelCase boundary becomes a hyphen
+            if prev_was_lower_or_digit && !out.ends_with('-') {
+                out.push('-');
+            }
+            out.extend(c.to_lowercase());
+            prev_was_lower_or_digit = false;
+        } else {
+            out.push(c);
+            prev_was_lower_or_digit = c.is_lowercase() || c.is_ascii_digit();
+        }
+    }
+
+    out.trim_end_matches('-').to_string()
+}
+
+/// Check a name against the slug shape `[a-z0-9]+(-[a-z0-9]+)*`
+pub fn is_valid_slug(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+        && !name.contains("--")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Emit a parser diagnostic (browser console on WASM, no-op on native)
+fn log_diagnostic(message: &str) {
+    #[cfg(target_arch = "wasm32")]
+    web_sys::console::warn_1(&message.into());
+    #[cfg(not(target_arch = "wasm32"))]
+    let _ = message;
+}
+
+/// Validate formula syntax without full parsing
+///
+/// # Performance
+/// Faster than parse_formula_impl as it skips serialization
+#[inline(always)]
+pub fn validate_formula_impl(content: &str) -> bool {
+    if content.is_empty() {
+        return false;
+    }
+    toml::from_str::<Formula>(content).is_ok()
+}
+
+/// Get the formula type from TOML content
+///
+/// # Performance
+/// Optimized path that only extracts the type field
+#[inline]
+pub fn get_formula_type_impl(content: &str) -> Result<String, JsValue> {
+    // Fast path: look for type field directly
+    if let Some(type_str) = extract_type_fast(content) {
+        return Ok(type_str.to_string());
+    }
+
+    // Fallback to full parse
+    let formula: Formula = toml::from_str(content)
+        .map_err(|e| JsValue::from_str(&format!("Parse error: {}", e)))?;
+
+    let type_str = match formula.formula_type {
+        FormulaType::Convoy => "convoy",
+        FormulaType::Workflow => "workflow",
+        FormulaType::Expansion => "expansion",
+        FormulaType::Aspect => "aspect",
+    };
+
+    Ok(type_str.to_string())
+}
+
+/// Fast extraction of type field without full parsing
+///
+/// Scans for `type = "..."` pattern directly
+#[inline]
+fn extract_type_fast(content: &str) -> Option<&str> {
+    // Look for type = "..." pattern
+    for line in content.lines() {
+        let trimmed = line.trim();
+
+        // Skip comments and empty lines
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        // Check for type field
+        if trimmed.starts_with("type") {
+            // Extract value after '='
+            if let Some(eq_pos) = trimmed.find('=') {
+                let value_part = trimmed[eq_pos + 1..].trim();
+
+                // Remove quotes
+                if value_part.starts_with('"') && value_part.len() > 2 {
+                    let end = value_part[1..].find('"')?;
+                    let type_str = &value_part[1..1 + end];
+
+                    // Validate type
+                    match type_str {
+                        "convoy" | "workflow" | "expansion" | "aspect" => {
+                            return Some(type_str);
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Batch parse multiple formulas
+///
+/// # Performance
+/// More efficient than parsing individually due to memory reuse
+#[inline]
+pub fn parse_batch_impl(contents: &[&str]) -> Result<Vec<Formula>, JsValue> {
+    let mut results = Vec::with_capacity(contents.len());
+
+    for content in contents {
+        let formula: Formula = toml::from_str(content)
+            .map_err(|e| JsValue::from_str(&format!("Parse error: {}", e)))?;
+        results.push(formula);
+    }
+
+    Ok(results)
+}
+
+/// Pre-validate multiple formulas in parallel-friendly manner
+#[inline]
+pub fn validate_batch_impl(contents: &[&str]) -> Vec<bool> {
+    contents.iter().map(|c| validate_formula_impl(c)).collect()
+}
+
+/// Extract formula name without full parsing
+#[inline]
+pub fn extract_name_fast(content: &str) -> Option<&str> {
+    for line in content.lines() {
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        if trimmed.starts_with("formula") {
+            if let Some(eq_pos) = trimmed.find('=') {
+                let value_part = trimmed[eq_pos + 1..].trim();
+
+                if value_part.starts_with('"') && value_part.len() > 2 {
+                    let end = value_part[1..].find('"')?;
+                    return Some(&value_part[1..1 + end]);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Extract formula description without full parsing
+#[inline]
+pub fn extract_description_fast(content: &str) -> Option<&str> {
+    for line in content.lines() {
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        if trimmed.starts_with("description") {
+            if let Some(eq_pos) = trimmed.find('=') {
+                let value_part = trimmed[eq_pos + 1..].trim();
+
+                if value_part.starts_with('"') && value_part.len() > 2 {
+                    let end = value_part[1..].find('"')?;
+                    return Some(&value_part[1..1 + end]);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Top-level formula metadata, parsed without touching steps/legs/vars
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormulaHeader {
+    pub name: String,
+    pub description: String,
+    pub formula_type: FormulaType,
+    pub version: u32,
+    pub tags: Vec<String>,
+}
+
+// Shadow struct matching the raw TOML header field names
+#[derive(Deserialize)]
+struct RawHeader {
+    formula: String,
+    description: String,
+    #[serde(rename = "type")]
+    formula_type: FormulaType,
+    #[serde(default = "crate::default_version")]
+    version: u32,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// Extract just the formula header (top-level scalar fields)
+///
+/// # Performance
+/// Only the content before the first `[...]` section header is parsed,
+/// which is significantly faster than a full parse on large formulas
+pub fn extract_formula_header(content: &str) -> Result<FormulaHeader, ParseError> {
+    let content = content.strip_prefix('\u{FEFF}').unwrap_or(content);
+
+    // Truncate at the first table/array-of-tables header: everything after
+    // it belongs to steps/legs/vars/synthesis
+    let header_end = content
+        .lines()
+        .take_while(|line| !line.trim_start().starts_with('['))
+        .map(|line| line.len() + 1)
+        .sum::<usize>()
+        .min(content.len());
+
+    let raw: RawHeader = toml::from_str(&content[..header_end]).map_err(|e| ParseError::Toml {
+        message: e.to_string(),
+    })?;
+
+    Ok(FormulaHeader {
+        name: raw.formula,
+        description: raw.description,
+        formula_type: raw.formula_type,
+        version: raw.version,
+        tags: raw.tags,
+    })
+}
+
+/// Registry of deprecated field paths mapped to their replacements
+///
+/// When a field is renamed, the old path is added here so formulas using
+/// the old name get a deprecation warning instead of a silent ignore.
+pub fn deprecation_registry() -> &'static [(&'static str, &'static str)] {
+    &[
+        ("steps.depends_on", "steps.needs"),
+        ("legs.sequence", "legs.order"),
+    ]
+}
+
+/// Detect deprecated fields in raw TOML content
+///
+/// Unknown fields are tolerated by the `Formula` deserializer, so this
+/// runs as a separate pass over the parsed TOML document and reports any
+/// field found in [`deprecation_registry`].
+pub fn check_deprecated_fields(content: &str) -> Vec<crate::LintWarning> {
+    let mut warnings = Vec::new();
+
+    let Ok(doc) = content.parse::<toml::Value>() else {
+        return warnings;
+    };
+
+    let mut check_tables = |section: &str, known: &[&str]| {
+        let Some(tables) = doc.get(section).and_then(|v| v.as_array()) else {
+            return;
+        };
+        for table in tables.iter().filter_map(|t| t.as_table()) {
+            for key in table.keys() {
+                if known.contains(&key.as_str()) {
+                    continue;
+                }
+                let path = format!("{}.{}", section, key);
+                if let Some((old, new)) = deprecation_registry()
+                    .iter()
+                    .find(|(old, _)| *old == path)
+                {
+                    warnings.push(crate::LintWarning::new(
+                        "DEPRECATED_FIELD",
+                        format!("Field '{}' is deprecated; use '{}' instead", old, new),
+                        crate::Severity::Warning,
+                    ));
+                }
+            }
+        }
+    };
+
+    check_tables(
+        "steps",
+        &["id", "title", "description", "needs", "duration", "requires"],
+    );
+    check_tables(
+        "legs",
+        &["id", "title", "focus", "description", "agent", "order"],
+    );
+
+    warnings
+}
+
+/// Serialize a formula back to TOML
+///
+/// The output is guaranteed to round-trip: feeding it to `parse_formula`
+/// yields a formula equal to the input. Empty `steps`/`legs`/`vars` are
+/// omitted (TOML forbids plain values after array-of-table headers, and
+/// the parser fills the same defaults on the way back in).
+pub fn formula_to_toml(formula: &Formula) -> Result<String, String> {
+    let mut value =
+        toml::Value::try_from(formula).map_err(|e| format!("Serialize error: {}", e))?;
+
+    if let Some(table) = value.as_table_mut() {
+        table.retain(|_, v| match v {
+            toml::Value::Array(items) => !items.is_empty(),
+            toml::Value::Table(entries) => !entries.is_empty(),
+            _ => true,
+        });
+    }
+
+    toml::to_string(&value).map_err(|e| format!("Serialize error: {}", e))
+}
+
+/// Render a formula's dependency graph as Graphviz DOT
+///
+/// Steps become nodes wired by their `needs` edges; legs become nodes
+/// chained in declaration order (their implicit execution order). Node
+/// styling follows the formula type so mixed dashboards stay readable.
+pub fn formula_to_dot(formula: &Formula) -> String {
+    let (shape, fillcolor) = match formula.formula_type {
+        FormulaType::Workflow => ("box", "#dae8fc"),
+        FormulaType::Convoy => ("ellipse", "#fff2cc"),
+        FormulaType::Expansion => ("component", "#e1d5e7"),
+        FormulaType::Aspect => ("diamond", "#d5e8d4"),
+    };
+
+    let mut out = format!("digraph \"{}\" {{\n", dot_escape(&formula.name));
+    out.push_str("    rankdir=LR;\n");
+    out.push_str(&format!(
+        "    node [shape={}, style=filled, fillcolor=\"{}\"];\n",
+        shape, fillcolor
+    ));
+
+    for step in &formula.steps {
+        out.push_str(&format!(
+            "    \"{}\" [label=\"{}\"];\n",
+            dot_escape(&step.id),
+            dot_escape(&step.title)
+        ));
+    }
+    for step in &formula.steps {
+        for need in &step.needs {
+            out.push_str(&format!(
+                "    \"{}\" -> \"{}\";\n",
+                dot_escape(need),
+                dot_escape(&step.id)
+            ));
+        }
+    }
+
+    for (i, leg) in formula.legs.iter().enumerate() {
+        out.push_str(&format!(
+            "    \"{}\" [label=\"{}\"];\n",
+            dot_escape(&leg.id),
+            dot_escape(&leg.title)
+        ));
+        if i > 0 {
+            out.push_str(&format!(
+                "    \"{}\" -> \"{}\";\n",
+                dot_escape(&formula.legs[i - 1].id),
+                dot_escape(&leg.id)
+            ));
+        }
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+/// Escape a string for use inside a double-quoted DOT identifier
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Errors produced while packing or unpacking formula archives
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum PackError {
+    /// A formula failed TOML (de)serialization
+    Toml { name: String, message: String },
+    /// The ZIP archive is malformed
+    Archive { message: String },
+}
+
+impl std::fmt::Display for PackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackError::Toml { name, message } => {
+                write!(f, "TOML error in formula '{}': {}", name, message)
+            }
+            PackError::Archive { message } => write!(f, "Archive error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for PackError {}
+
+impl From<PackError> for JsValue {
+    fn from(err: PackError) -> Self {
+        JsValue::from_str(&err.to_string())
+    }
+}
+
+/// Pack formulas into a ZIP archive, one TOML file per formula
+///
+/// Each entry is named `{formula.name}.toml`. Entries are stored
+/// uncompressed so packing stays cheap inside WASM; registries that want
+/// compression can compress the whole blob.
+pub fn pack_formula_archive(formulas: &[Formula]) -> Result<Vec<u8>, PackError> {
+    use std::io::Write;
+
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Stored);
+
+    for formula in formulas {
+        let toml_content = formula_to_toml(formula).map_err(|message| PackError::Toml {
+            name: formula.name.clone(),
+            message,
+        })?;
+
+        writer
+            .start_file(format!("{}.toml", formula.name), options)
+            .and_then(|_| {
+                writer
+                    .write_all(toml_content.as_bytes())
+                    .map_err(zip::result::ZipError::Io)
+            })
+            .map_err(|e| PackError::Archive {
+                message: e.to_string(),
+            })?;
+    }
+
+    writer
+        .finish()
+        .map(|cursor| cursor.into_inner())
+        .map_err(|e| PackError::Archive {
+            message: e.to_string(),
+        })
+}
+
+/// Unpack a ZIP archive of TOML formulas
+pub fn unpack_formula_archive(bytes: &[u8]) -> Result<Vec<Formula>, PackError> {
+    use std::io::Read;
+
+    let mut archive =
+        zip::ZipArchive::new(std::io::Cursor::new(bytes)).map_err(|e| PackError::Archive {
+            message: e.to_string(),
+        })?;
+
+    let mut formulas = Vec::with_capacity(archive.len());
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(|e| PackError::Archive {
+            message: e.to_string(),
+        })?;
+
+        let mut content = String::new();
+        file.read_to_string(&mut content)
+            .map_err(|e| PackError::Archive {
+                message: e.to_string(),
+            })?;
+
+        formulas.push(toml::from_str(&content).map_err(|e| PackError::Toml {
+            name: file.name().to_string(),
+            message: e.to_string(),
+        })?);
+    }
+
+    Ok(formulas)
+}
+
+/// Incremental parser for delimiter-separated formula bundles
+///
+/// Bundles concatenate formula documents separated by a `---` line (the
+/// same convention as multi-document YAML streams). Callers push chunks
+/// as they arrive — chunk boundaries can fall anywhere, including inside
+/// a line — and drain completed formulas via `next_formula`. Each
+/// document is parsed with format autodetection, so TOML and YAML
+/// formulas can be mixed in one bundle.
+///
+/// This keeps JS<->WASM overhead to one call per chunk instead of one
+/// call per formula, which matters for bundles of thousands of formulas.
+#[derive(Default)]
+pub struct FormulaBundleStream {
+    /// Unconsumed tail of the bundle (at most one incomplete document)
+    buffer: String,
+    /// Parsed formulas not yet drained by the caller
+    ready: std::collections::VecDeque<Formula>,
+    /// Documents parsed so far, for error positions
+    parsed_count: usize,
+}
+
+impl FormulaBundleStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next chunk of the bundle
+    ///
+    /// Parses every document completed by this chunk; the first parse
+    /// failure is returned with the document's position in the bundle.
+    pub fn push(&mut self, chunk: &str) -> Result<(), String> {
+        self.buffer.push_str(chunk);
+
+        while let Some((start, resume)) = find_document_delimiter(&self.buffer) {
+            let segment: String = self.buffer[..start].to_string();
+            self.buffer.drain(..resume);
+            self.parse_segment(&segment)?;
+        }
+        Ok(())
+    }
+
+    /// Signal end of input, parsing any trailing document
+    pub fn finish(&mut self) -> Result<(), String> {
+        let segment = std::mem::take(&mut self.buffer);
+        self.parse_segment(&segment)
+    }
+
+    /// Drain the next parsed formula, in bundle order
+    pub fn next_formula(&mut self) -> Option<Formula> {
+        self.ready.pop_front()
+    }
+
+    /// Number of parsed formulas waiting to be drained
+    pub fn pending_count(&self) -> usize {
+        self.ready.len()
+    }
+
+    /// Parse one completed document; blank documents (e.g. between two
+    /// consecutive delimiters) are skipped
+    fn parse_segment(&mut self, segment: &str) -> Result<(), String> {
+        if is_empty_content(segment) {
+            return Ok(());
+        }
+
+        let formula = parse_formula_any_internal(segment)
+            .map_err(|e| format!("Bundle document {}: {}", self.parsed_count + 1, e))?;
+        self.parsed_count += 1;
+        self.ready.push_back(formula);
+        Ok(())
+    }
+}
+
+/// Find the next complete `---` delimiter line in the buffer
+///
+/// Returns `(line_start, index_after_newline)`. Only lines already
+/// terminated by `\n` count, so a delimiter split across chunks is left
+/// for the next push.
+fn find_document_delimiter(buffer: &str) -> Option<(usize, usize)> {
+    let mut offset = 0;
+    while let Some(nl) = buffer[offset..].find('\n') {
+        let line_end = offset + nl;
+        if buffer[offset..line_end].trim_end_matches('\r').trim() == "---" {
+            return Some((offset, line_end + 1));
+        }
+        offset = line_end + 1;
+    }
+    None
+}
+
+/// One failed document in a batch parse
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BatchParseError {
+    /// Index of the failing document in the input array
+    pub index: usize,
+    pub message: String,
+}
+
+/// Result of a batch parse, aligned with the input array
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BatchParse {
+    /// One slot per input: the parsed formula, or `None` on failure
+    pub formulas: Vec<Option<Formula>>,
+    /// One entry per failed input, with its index
+    pub errors: Vec<BatchParseError>,
+}
+
+/// Parse many formula documents in one call
+///
+/// Startup loads hundreds of formulas; crossing the JS<->WASM boundary
+/// once per formula dominates the cost. This parses the whole array in
+/// one call and serializes one result, keeping slot `i` aligned with
+/// input `i` so callers can map errors back to their sources.
+pub fn parse_formula_batch_internal(contents: &[String]) -> BatchParse {
+    let mut result = BatchParse {
+        formulas: Vec::with_capacity(contents.len()),
+        errors: Vec::new(),
+    };
+
+    for (index, content) in contents.iter().enumerate() {
+        match parse_formula_internal(content) {
+            Ok(formula) => result.formulas.push(Some(formula)),
+            Err(message) => {
+                result.formulas.push(None);
+                result.errors.push(BatchParseError { index, message });
+            }
+        }
+    }
+
+    result
+}
+
+/// WASM wrapper for `parse_formula_batch_internal`
+#[inline]
+pub fn parse_formula_batch_impl(contents_json: &str) -> Result<JsValue, JsValue> {
+    let contents: Vec<String> = serde_json::from_str(contents_json)
+        .map_err(|e| JsValue::from_str(&format!("Invalid contents array: {}", e)))?;
+    let batch = parse_formula_batch_internal(&contents);
+    serde_wasm_bindgen::to_value(&batch)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Keys accepted at the top level of a formula document
+const FORMULA_KEYS: &[&str] = &[
+    "formula",
+    "description",
+    "type",
+    "version",
+    "vars",
+    "steps",
+    "legs",
+    "synthesis",
+    "include",
+];
+
+/// Keys accepted in a `[[steps]]` table
+const STEP_KEYS: &[&str] = &["id", "title", "description", "needs", "duration", "requires"];
+
+/// Keys accepted in a `[[legs]]` table
+const LEG_KEYS: &[&str] = &["id", "title", "focus", "description", "agent", "order"];
+
+/// Keys accepted in a `[vars.*]` table
+const VAR_KEYS: &[&str] = &[
+    "name",
+    "type",
+    "description",
+    "default",
+    "required",
+    "pattern",
+    "enum",
+    "min",
+    "max",
+    "min_exclusive",
+    "max_exclusive",
+    "min_length",
+    "max_length",
+    "format",
+    "secret",
+];
+
+/// Keys accepted in the `[synthesis]` table
+const SYNTHESIS_KEYS: &[&str] = &["strategy", "format", "description"];
+
+/// Report unknown keys in a formula document, with suggestions
+///
+/// TOML deserialization silently drops keys the schema does not know, so
+/// a typo like `descripton` just loses the field. This pass walks the
+/// raw document and emits a diagnostic for every key outside the schema,
+/// with a Levenshtein-based "did you mean" suggestion when a known key
+/// is close. Returns `Err` only when the document is not valid TOML.
+pub fn check_unknown_keys_internal(content: &str) -> Result<Vec<ParseDiagnostic>, String> {
+    let stripped = content.strip_prefix('\u{FEFF}').unwrap_or(content);
+    let body = strip_shebang(stripped);
+    let base = content.len() - body.len();
+
+    let document: toml::Value = toml::from_str(body).map_err(|e| format!("Parse error: {}", e))?;
+    let table = match document.as_table() {
+        Some(table) => table,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut diagnostics = Vec::new();
+    check_table_keys(content, body, base, table, FORMULA_KEYS, "formula", &mut diagnostics);
+
+    for (section, keys) in [("steps", STEP_KEYS), ("legs", LEG_KEYS)] {
+        if let Some(entries) = table.get(section).and_then(|v| v.as_array()) {
+            for entry in entries.iter().filter_map(|e| e.as_table()) {
+                check_table_keys(content, body, base, entry, keys, section, &mut diagnostics);
+            }
+        }
+    }
+    if let Some(vars) = table.get("vars").and_then(|v| v.as_table()) {
+        for var in vars.values().filter_map(|v| v.as_table()) {
+            check_table_keys(content, body, base, var, VAR_KEYS, "vars", &mut diagnostics);
+        }
+    }
+    if let Some(synthesis) = table.get("synthesis").and_then(|v| v.as_table()) {
+        check_table_keys(
+            content,
+            body,
+            base,
+            synthesis,
+            SYNTHESIS_KEYS,
+            "synthesis",
+            &mut diagnostics,
+        );
+    }
+
+    Ok(diagnostics)
+}
+
+/// WASM wrapper for `check_unknown_keys_internal`
+#[inline]
+pub fn check_unknown_keys_impl(content: &str) -> Result<JsValue, JsValue> {
+    let diagnostics =
+        check_unknown_keys_internal(content).map_err(|e| JsValue::from_str(&e))?;
+    serde_wasm_bindgen::to_value(&diagnostics)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Diagnose every key in `table` that is not in `known`
+fn check_table_keys(
+    content: &str,
+    body: &str,
+    base: usize,
+    table: &toml::map::Map<String, toml::Value>,
+    known: &[&str],
+    context: &str,
+    diagnostics: &mut Vec<ParseDiagnostic>,
+) {
+    for key in table.keys() {
+        if known.contains(&key.as_str()) {
+            continue;
+        }
+        let mut message = format!("Unknown key '{}' in {}", key, context);
+        if let Some(suggestion) = suggest_key(key, known) {
+            message.push_str(&format!(" (did you mean '{}'?)", suggestion));
+        }
+        // Best-effort span: first occurrence of the key in the document
+        let span = match body.find(key.as_str()) {
+            Some(start) => base + start..base + start + key.len(),
+            None => 0..0,
+        };
+        diagnostics.push(ParseDiagnostic::from_span(
+            content,
+            "unknown_key",
+            message,
+            span,
+        ));
+    }
+}
+
+/// Report `Step.needs` entries that name no existing step, with suggestions
+///
+/// Unresolved `needs` references are silently dropped during molecule
+/// generation and only surface when execution deadlocks, so this pass
+/// lets editors and CI catch them up front. Each dangling reference gets
+/// a diagnostic spanning its occurrence in the source, with a
+/// Levenshtein-based "did you mean" suggestion when a declared step id
+/// is close. Returns `Err` only when the content does not parse.
+pub fn check_dangling_needs_internal(content: &str) -> Result<Vec<ParseDiagnostic>, String> {
+    let formula = parse_formula_internal(content)?;
+
+    let stripped = content.strip_prefix('\u{FEFF}').unwrap_or(content);
+    let body = strip_shebang(stripped);
+    let base = content.len() - body.len();
+
+    let known: Vec<&str> = formula.steps.iter().map(|step| step.id.as_str()).collect();
+
+    let mut diagnostics = Vec::new();
+    for step in &formula.steps {
+        for need in &step.needs {
+            if known.contains(&need.as_str()) {
+                continue;
+            }
+            let mut message = format!(
+                "Step '{}' needs '{}', which is not a declared step",
+                step.id, need
+            );
+            if let Some(suggestion) = suggest_key(need, &known) {
+                message.push_str(&format!(" (did you mean '{}'?)", suggestion));
+            }
+            // Best-effort span: first occurrence of the quoted id after
+            // the step declaring it
+            let needle = format!("\"{}\"", need);
+            let from = body.find(&format!("\"{}\"", step.id)).unwrap_or(0);
+            let span = match body[from..].find(&needle) {
+                Some(start) => {
+                    let start = base + from + start;
+                    start..start + needle.len()
+                }
+                None => 0..0,
+            };
+            diagnostics.push(ParseDiagnostic::from_span(
+                content,
+                "dangling_needs",
+                message,
+                span,
+            ));
+        }
+    }
+
+    Ok(diagnostics)
+}
+
+/// WASM wrapper for `check_dangling_needs_internal`
+#[inline]
+pub fn check_dangling_needs_impl(content: &str) -> Result<JsValue, JsValue> {
+    let diagnostics =
+        check_dangling_needs_internal(content).map_err(|e| JsValue::from_str(&e))?;
+    serde_wasm_bindgen::to_value(&diagnostics)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Closest known key within an edit distance of 2, if any
+fn suggest_key<'a>(unknown: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|candidate| (levenshtein(unknown, candidate), *candidate))
+        .filter(|&(distance, _)| distance <= 2 && distance < unknown.len())
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein edit distance (two-row dynamic programming)
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b_chars.len()).collect();
+    let mut current = vec![0; b_chars.len() + 1];
+
+    for (i, a_char) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, &b_char) in b_chars.iter().enumerate() {
+            let substitution = prev[j] + usize::from(a_char != b_char);
+            current[j + 1] = substitution.min(prev[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut current);
+    }
+    prev[b_chars.len()]
+}
+
+/// A fix that `apply_fixes` can perform on a formula document
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct FormulaFix {
+    /// Stable fix id to pass to `apply_fixes` (e.g. `add_version`,
+    /// `sort_legs`, `rename_key:descripton`)
+    pub id: String,
+    /// Human-readable description for the editor's fix list
+    pub description: String,
+}
+
+/// List the fixes available for a formula document
+///
+/// Covers the mechanical corrections: a missing `version` key, legs
+/// whose explicit `order` disagrees with their file order, and unknown
+/// keys with an obvious correction. Returns `Err` only when the content
+/// is not valid TOML.
+pub fn list_fixes_internal(content: &str) -> Result<Vec<FormulaFix>, String> {
+    let stripped = content.strip_prefix('\u{FEFF}').unwrap_or(content);
+    let body = strip_shebang(stripped);
+
+    let document: toml::Value = toml::from_str(body).map_err(|e| format!("Parse error: {}", e))?;
+
+    let mut fixes = Vec::new();
+
+    if document.get("version").is_none() {
+        fixes.push(FormulaFix {
+            id: "add_version".to_string(),
+            description: "Add the missing 'version = 1' key".to_string(),
+        });
+    }
+
+    if legs_misordered(&document) {
+        fixes.push(FormulaFix {
+            id: "sort_legs".to_string(),
+            description: "Reorder [[legs]] blocks by their 'order' field".to_string(),
+        });
+    }
+
+    for (wrong, suggestion) in collect_key_renames(&document) {
+        fixes.push(FormulaFix {
+            id: format!("rename_key:{}", wrong),
+            description: format!("Rename unknown key '{}' to '{}'", wrong, suggestion),
+        });
+    }
+
+    Ok(fixes)
+}
+
+/// True when legs declare `order` values out of file order
+fn legs_misordered(document: &toml::Value) -> bool {
+    let Some(legs) = document.get("legs").and_then(|v| v.as_array()) else {
+        return false;
+    };
+    let orders: Vec<i64> = legs
+        .iter()
+        .filter_map(|leg| leg.get("order").and_then(|o| o.as_integer()))
+        .collect();
+    orders.windows(2).any(|pair| pair[0] > pair[1])
+}
+
+/// Unknown keys across the document that have a close known key
+fn collect_key_renames(document: &toml::Value) -> Vec<(String, String)> {
+    fn collect(
+        table: &toml::map::Map<String, toml::Value>,
+        known: &[&str],
+        renames: &mut Vec<(String, String)>,
+    ) {
+        for key in table.keys() {
+            if known.contains(&key.as_str()) {
+                continue;
+            }
+            if let Some(suggestion) = suggest_key(key, known) {
+                renames.push((key.clone(), suggestion.to_string()));
+            }
+        }
+    }
+
+    let mut renames = Vec::new();
+    let Some(table) = document.as_table() else {
+        return renames;
+    };
+    collect(table, FORMULA_KEYS, &mut renames);
+    for (section, keys) in [("steps", STEP_KEYS), ("legs", LEG_KEYS)] {
+        if let Some(entries) = table.get(section).and_then(|v| v.as_array()) {
+            for entry in entries.iter().filter_map(|e| e.as_table()) {
+                collect(entry, keys, &mut renames);
+            }
+        }
+    }
+    if let Some(vars) = table.get("vars").and_then(|v| v.as_table()) {
+        for var in vars.values().filter_map(|v| v.as_table()) {
+            collect(var, VAR_KEYS, &mut renames);
+        }
+    }
+    if let Some(synthesis) = table.get("synthesis").and_then(|v| v.as_table()) {
+        collect(synthesis, SYNTHESIS_KEYS, &mut renames);
+    }
+    renames
+}
+
+/// Apply the requested fixes to a formula document, returning corrected TOML
+///
+/// `fix_ids` uses the ids from `list_fixes_internal`. Fixes edit the
+/// source textually, so comments and formatting outside the fixed spots
+/// are preserved. Unknown fix ids are an error; requesting a fix the
+/// document does not need is a no-op.
+pub fn apply_fixes_internal(content: &str, fix_ids: &[String]) -> Result<String, String> {
+    let stripped = content.strip_prefix('\u{FEFF}').unwrap_or(content);
+    let body = strip_shebang(stripped);
+    let document: toml::Value =
+        toml::from_str(body).map_err(|e| format!("Parse error: {}", e))?;
+
+    let mut fixed = content.to_string();
+    for fix_id in fix_ids {
+        match fix_id.as_str() {
+            "add_version" => {
+                if document.get("version").is_none() {
+                    fixed = insert_version_key(&fixed);
+                }
+            }
+            "sort_legs" => {
+                fixed = sort_leg_blocks(&fixed);
+            }
+            _ => match fix_id.strip_prefix("rename_key:") {
+                Some(wrong) => {
+                    let Some((_, suggestion)) = collect_key_renames(&document)
+                        .into_iter()
+                        .find(|(key, _)| key == wrong)
+                    else {
+                        continue;
+                    };
+                    fixed = rename_key(&fixed, wrong, &suggestion);
+                }
+                None => return Err(format!("Unknown fix id '{}'", fix_id)),
+            },
+        }
+    }
+
+    Ok(fixed)
+}
+
+/// Insert `version = 1` after the `type = ...` line (or the `formula`
+/// line when there is no type key yet)
+fn insert_version_key(content: &str) -> String {
+    let mut lines: Vec<&str> = content.lines().collect();
+    let at = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with("type ") || line.trim_start().starts_with("type="))
+        .or_else(|| {
+            lines
+                .iter()
+                .position(|line| line.trim_start().starts_with("formula "))
+        })
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    lines.insert(at, "version = 1");
+    let mut result = lines.join("\n");
+    if content.ends_with('\n') {
+        result.push('\n');
+    }
+    result
+}
+
+/// Reorder `[[legs]]` blocks so their `order` fields ascend
+///
+/// Blocks run from a `[[legs]]` header to the next section header; legs
+/// without an `order` keep their relative position (stable sort).
+fn sort_leg_blocks(content: &str) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    // (start, end) line ranges of each [[legs]] block
+    let mut blocks: Vec<(usize, usize)> = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        if trimmed == "[[legs]]" {
+            if let Some(last) = blocks.last_mut() {
+                if last.1 == lines.len() {
+                    last.1 = i;
+                }
+            }
+            blocks.push((i, lines.len()));
+        } else if trimmed.starts_with('[') {
+            if let Some(last) = blocks.last_mut() {
+                if last.1 == lines.len() && i > last.0 {
+                    last.1 = i;
+                }
+            }
+        }
+    }
+    if blocks.len() < 2 {
+        return content.to_string();
+    }
+
+    let order_of = |&(start, end): &(usize, usize)| -> i64 {
+        lines[start..end]
+            .iter()
+            .find_map(|line| {
+                let rest = line.trim().strip_prefix("order")?.trim_start();
+                rest.strip_prefix('=')?.trim().parse::<i64>().ok()
+            })
+            .unwrap_or(i64::MAX)
+    };
+    let mut sorted = blocks.clone();
+    sorted.sort_by_key(order_of);
+    if sorted == blocks {
+        return content.to_string();
+    }
+
+    let mut result_lines: Vec<&str> = lines[..blocks[0].0].to_vec();
+    for &(start, end) in &sorted {
+        result_lines.extend(&lines[start..end]);
+    }
+    result_lines.extend(&lines[blocks.last().unwrap().1..]);
+    let mut result = result_lines.join("\n");
+    if content.ends_with('\n') {
+        result.push('\n');
+    }
+    result
+}
+
+/// Rename a TOML key everywhere it appears as an assignment or header
+fn rename_key(content: &str, wrong: &str, suggestion: &str) -> String {
+    let escaped = regex::escape(wrong);
+    // `wrong = ...` assignments at line starts
+    let assignment = regex::Regex::new(&format!(r"(?m)^(\s*){}(\s*=)", escaped))
+        .expect("escaped key pattern compiles");
+    let result = assignment.replace_all(content, format!("${{1}}{}${{2}}", suggestion));
+    // `[wrong]` / `[[wrong]]` / `[wrong.sub]` headers
+    let header = regex::Regex::new(&format!(r"(?m)^(\s*\[\[?){}(\.|\])", escaped))
+        .expect("escaped key pattern compiles");
+    header
+        .replace_all(&result, format!("${{1}}{}${{2}}", suggestion))
+        .into_owned()
+}
+
+/// WASM wrapper for `list_fixes_internal`
+#[inline]
+pub fn list_fixes_impl(content: &str) -> Result<JsValue, JsValue> {
+    let fixes = list_fixes_internal(content).map_err(|e| JsValue::from_str(&e))?;
+    serde_wasm_bindgen::to_value(&fixes)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// WASM wrapper for `apply_fixes_internal`
+#[inline]
+pub fn apply_fixes_impl(content: &str, fix_ids_json: &str) -> Result<String, JsValue> {
+    let fix_ids: Vec<String> = serde_json::from_str(fix_ids_json)
+        .map_err(|e| JsValue::from_str(&format!("Fix ids parse error: {}", e)))?;
+    apply_fixes_internal(content, &fix_ids).map_err(|e| JsValue::from_str(&e))
+}
+
+/// Result of a one-shot bundle parse: every document that parsed, plus
+/// an error entry for each one that did not
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BundleParse {
+    /// Formulas that parsed, in bundle order
+    pub formulas: Vec<Formula>,
+    /// One message per failed document, e.g. "Document 2: ..."
+    pub errors: Vec<String>,
+}
+
+/// Parse a multi-document formula bundle in one shot
+///
+/// Documents are separated by a `+++` line (or `---`, matching
+/// `FormulaBundleStream`); each is parsed with format autodetection.
+/// Unlike the stream, a failed document does not stop the parse — its
+/// error is recorded with the document's position and the remaining
+/// documents are still parsed.
+pub fn parse_formula_bundle_internal(content: &str) -> BundleParse {
+    let mut result = BundleParse {
+        formulas: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    let mut document_number = 0;
+    let mut rest = content;
+    loop {
+        let (segment, remainder) = match find_bundle_delimiter(rest) {
+            Some((start, resume)) => (&rest[..start], Some(&rest[resume..])),
+            None => (rest, None),
+        };
+
+        if !is_empty_content(segment) {
+            document_number += 1;
+            match parse_formula_any_internal(segment) {
+                Ok(formula) => result.formulas.push(formula),
+                Err(e) => result
+                    .errors
+                    .push(format!("Document {}: {}", document_number, e)),
+            }
+        }
+
+        match remainder {
+            Some(r) => rest = r,
+            None => break,
+        }
+    }
+
+    result
+}
+
+/// WASM wrapper for `parse_formula_bundle_internal`
+#[inline]
+pub fn parse_formula_bundle_impl(content: &str) -> Result<JsValue, JsValue> {
+    let bundle = parse_formula_bundle_internal(content);
+    serde_wasm_bindgen::to_value(&bundle)
+        .map_err(|e| JsValue::from_str(&format!("Serialize error: {}", e)))
+}
+
+/// Find the next `+++` or `---` delimiter line in a complete bundle
+///
+/// Returns `(line_start, index_after_line)`. Unlike
+/// `find_document_delimiter`, the final line does not need a trailing
+/// newline — the input is complete, not a stream chunk.
+fn find_bundle_delimiter(content: &str) -> Option<(usize, usize)> {
+    let mut offset = 0;
+    loop {
+        let (line_end, resume) = match content[offset..].find('\n') {
+            Some(nl) => (offset + nl, offset + nl + 1),
+            None => (content.len(), content.len()),
+        };
+        let line = content[offset..line_end].trim_end_matches('\r').trim();
+        if line == "+++" || line == "---" {
+            return Some((offset, resume));
+        }
+        if resume == content.len() && line_end == content.len() {
+            return None;
+        }
+        offset = resume;
+    }
+}
+
+/// Reformat TOML formula content into the canonical style
+///
+/// Parses strictly and re-emits through `formula_to_toml`, so the output
+/// has schema key ordering, one `[[steps]]`/`[[legs]]` table per entry,
+/// and normalized spacing — suitable for enforcing in pre-commit hooks.
+/// Formatting is idempotent. Comments are dropped (they do not survive
+/// deserialization); a leading shebang line is preserved.
+pub fn format_formula_internal(content: &str) -> Result<String, String> {
+    let formula = parse_formula_internal(content)?;
+    let formatted = formula_to_toml(&formula)?;
+
+    let stripped = content.strip_prefix('\u{FEFF}').unwrap_or(content);
+    if stripped.starts_with("#!") {
+        let shebang = stripped.lines().next().unwrap_or("");
+        return Ok(format!("{}\n{}", shebang, formatted));
+    }
+    Ok(formatted)
+}
+
+/// WASM wrapper for `format_formula_internal`
+#[inline]
+pub fn format_formula_impl(content: &str) -> Result<String, JsValue> {
+    format_formula_internal(content).map_err(|e| JsValue::from_str(&e))
+}
+
+/// Kind of a line-level TOML token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenKind {
+    /// `[section]`
+    TableHeader,
+    /// `key = value`
+    KeyValue,
+    /// `[[section]]`
+    ArrayOfTablesHeader,
+    /// `# comment`
+    Comment,
+}
+
+/// A single token produced by [`FormulaTokenizer`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FormulaToken {
+    pub kind: TokenKind,
+    /// Semantic content: table name for headers, `key = value` text for
+    /// key/value lines, comment text for comments
+    pub value: String,
+    /// 1-based line number
+    pub line: u32,
+    /// 1-based column of the first non-whitespace character
+    pub col: u32,
+}
+
+/// Streaming line-level tokenizer over TOML formula content
+///
+/// Reads from any `std::io::Read` without buffering the whole document,
+/// so multi-MB config bundles can be scanned with constant memory. This
+/// is a low-level API for tools that need structure without full
+/// deserialization (outline views, grep-like scans, include resolvers).
+///
+/// Blank lines are skipped; IO errors end the iteration.
+pub struct FormulaTokenizer<R: std::io::Read> {
+    reader: std::io::BufReader<R>,
+    line: u32,
+    buf: String,
+}
+
+impl<R: std::io::Read> FormulaTokenizer<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: std::io::BufReader::new(reader),
+            line: 0,
+            buf: String::new(),
+        }
+    }
+}
+
+impl<R: std::io::Read> Iterator for FormulaTokenizer<R> {
+    type Item = FormulaToken;
+
+    fn next(&mut self) -> Option<FormulaToken> {
+        use std::io::BufRead;
+
+        loop {
+            self.buf.clear();
+            match self.reader.read_line(&mut self.buf) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => {}
+            }
+            self.line += 1;
+
+            let trimmed = self.buf.trim_end();
+            let content = trimmed.trim_start();
+            if content.is_empty() {
+                continue;
+            }
+
+            let col = (trimmed.len() - content.len() + 1) as u32;
+            let (kind, value) = classify_line(content);
+            return Some(FormulaToken {
+                kind,
+                value,
+                line: self.line,
+                col,
+            });
+        }
+    }
+}
+
+/// Classify one non-blank TOML line into a token kind plus its value
+fn classify_line(content: &str) -> (TokenKind, String) {
+    if let Some(comment) = content.strip_prefix('#') {
+        return (TokenKind::Comment, comment.trim().to_string());
+    }
+    if let Some(inner) = content
+        .strip_prefix("[[")
+        .and_then(|s| s.strip_suffix("]]"))
+    {
+        return (TokenKind::ArrayOfTablesHeader, inner.trim().to_string());
+    }
+    if let Some(inner) = content.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        return (TokenKind::TableHeader, inner.trim().to_string());
+    }
+    (TokenKind::KeyValue, content.to_string())
+}
+
+/// Structured human-readable explanation of a formula
+///
+/// Used by pipeline UIs to show a quick "formula info card" without
+/// walking the full formula JSON in JavaScript
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FormulaExplanation {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub formula_type: String,
+    pub description: String,
+    pub var_count: usize,
+    pub step_count: usize,
+    pub leg_count: usize,
+    pub has_synthesis: bool,
+    pub complexity_score: usize,
+}
+
+/// Build a structured explanation for a formula
+pub fn explain_formula_internal(formula: &Formula) -> FormulaExplanation {
+    let var_refs = count_var_references(formula);
+    let depth = dependency_depth(formula);
+
+    FormulaExplanation {
+        name: formula.name.clone(),
+        formula_type: match formula.formula_type {
+            FormulaType::Convoy => "convoy",
+            FormulaType::Workflow => "workflow",
+            FormulaType::Expansion => "expansion",
+            FormulaType::Aspect => "aspect",
+        }
+        .to_string(),
+        description: formula.description.clone(),
+        var_count: formula.vars.len(),
+        step_count: formula.steps.len(),
+        leg_count: formula.legs.len(),
+        has_synthesis: formula.synthesis.is_some(),
+        complexity_score: formula.steps.len() + formula.legs.len() + var_refs + depth,
+    }
+}
+
+/// Count `{{...}}` template references across all templated fields
+fn count_var_references(formula: &Formula) -> usize {
+    let count = |text: &str| text.matches("{{").count();
+
+    let mut total = count(&formula.name) + count(&formula.description);
+    for step in &formula.steps {
+        total += count(&step.title) + count(&step.description);
+    }
+    for leg in &formula.legs {
+        total += count(&leg.title) + count(&leg.focus) + count(&leg.description);
+    }
+    total
+}
+
+/// Longest dependency chain over the step graph (0 for no steps)
+fn dependency_depth(formula: &Formula) -> usize {
+    if formula.steps.is_empty() {
+        return 0;
+    }
+
+    let id_to_index: gastown_shared::FxHashMap<&str, usize> = formula
+        .steps
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.id.as_str(), i))
+        .collect();
+
+    // Memoized depth per step; cycles bottom out at the visit guard
+    fn depth_of(
+        i: usize,
+        formula: &Formula,
+        id_to_index: &gastown_shared::FxHashMap<&str, usize>,
+        memo: &mut [Option<usize>],
+        visiting: &mut [bool],
+    ) -> usize {
+        if let Some(d) = memo[i] {
+            return d;
+        }
+        if visiting[i] {
+            return 0;
+        }
+        visiting[i] = true;
+        let d = 1 + formula.steps[i]
+            .needs
+            .iter()
+            .filter_map(|need| id_to_index.get(need.as_str()).copied())
+            .map(|j| depth_of(j, formula, id_to_index, memo, visiting))
+            .max()
+            .unwrap_or(0);
+        visiting[i] = false;
+        memo[i] = Some(d);
+        d
+    }
+
+    let n = formula.steps.len();
+    let mut memo = vec![None; n];
+    let mut visiting = vec![false; n];
+    (0..n)
+        .map(|i| depth_of(i, formula, &id_to_index, &mut memo, &mut visiting))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Aggregate statistics over a formula collection
+///
+/// Produced for registry dashboards by [`summarize_formula_collection_internal`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionSummary {
+    pub total: usize,
+    pub by_type: std::collections::HashMap<String, usize>,
+    pub by_version: std::collections::HashMap<String, usize>,
+    pub avg_step_count: f64,
+    pub avg_leg_count: f64,
+    pub formulas_with_vars: usize,
+    pub formulas_with_synthesis: usize,
+}
+
+/// Summarize a JSON array of formulas in a single pass
+///
+/// Elements are deserialized one at a time via `RawValue`, so only one
+/// formula is materialized at any point — large registry dumps stay cheap.
+pub fn summarize_formula_collection_internal(
+    formulas_json: &str,
+) -> Result<CollectionSummary, String> {
+    let entries: Vec<&serde_json::value::RawValue> =
+        serde_json::from_str(formulas_json).map_err(|e| format!("Collection parse error: {}", e))?;
+
+    let mut summary = CollectionSummary {
+        total: entries.len(),
+        by_type: std::collections::HashMap::new(),
+        by_version: std::collections::HashMap::new(),
+        avg_step_count: 0.0,
+        avg_leg_count: 0.0,
+        formulas_with_vars: 0,
+        formulas_with_synthesis: 0,
+    };
+
+    let mut total_steps = 0usize;
+    let mut total_legs = 0usize;
+
+    for entry in &entries {
+        let formula: Formula = serde_json::from_str(entry.get())
+            .map_err(|e| format!("Formula parse error: {}", e))?;
+
+        let type_key = match formula.formula_type {
+            FormulaType::Convoy => "convoy",
+            FormulaType::Workflow => "workflow",
+            FormulaType::Expansion => "expansion",
+            FormulaType::Aspect => "aspect",
+        };
+        *summary.by_type.entry(type_key.to_string()).or_insert(0) += 1;
+        *summary
+            .by_version
+            .entry(formula.version.to_string())
+            .or_insert(0) += 1;
+
+        total_steps += formula.steps.len();
+        total_legs += formula.legs.len();
+        if !formula.vars.is_empty() {
+            summary.formulas_with_vars += 1;
+        }
+        if formula.synthesis.is_some() {
+            summary.formulas_with_synthesis += 1;
+        }
+    }
+
+    if summary.total > 0 {
+        summary.avg_step_count = total_steps as f64 / summary.total as f64;
+        summary.avg_leg_count = total_legs as f64 / summary.total as f64;
+    }
+
+    Ok(summary)
+}
+
+/// Quick metadata extraction without full parsing
+#[derive(Debug)]
+pub struct FormulaMetadata<'a> {
+    pub name: Option<&'a str>,
+    pub description: Option<&'a str>,
+    pub formula_type: Option<&'a str>,
+    pub version: Option<u32>,
+}
+
+/// Extract metadata without full TOML parsing
+#[inline]
+pub fn extract_metadata_fast(content: &str) -> FormulaMetadata<'_> {
+    let mut metadata = FormulaMetadata {
+        name: None,
+        description: None,
+        formula_type: None,
+        version: None,
+    };
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('[') {
+            continue;
+        }
+
+        if let Some(eq_pos) = trimmed.find('=') {
+            let key = trimmed[..eq_pos].trim();
+            let value_part = trimmed[eq_pos + 1..].trim();
+
+            match key {
+                "formula" => {
+                    if let Some(s) = extract_quoted_string(value_part) {
+                        metadata.name = Some(s);
+                    }
+                }
+                "description" => {
+                    if let Some(s) = extract_quoted_string(value_part) {
+                        metadata.description = Some(s);
+                    }
+                }
+                "type" => {
+                    if let Some(s) = extract_quoted_string(value_part) {
+                        metadata.formula_type = Some(s);
+                    }
+                }
+                "version" => {
+                    if let Ok(v) = value_part.parse() {
+                        metadata.version = Some(v);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Early exit if we have all metadata
+        if metadata.name.is_some()
+            && metadata.description.is_some()
+            && metadata.formula_type.is_some()
+            && metadata.version.is_some()
+        {
+            break;
+        }
+    }
+
+    metadata
 }
 
 /// Extract quoted string value
@@ -265,68 +2507,665 @@ fn extract_quoted_string(value: &str) -> Option<&str> {
     } else {
         None
     }
-}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Leg, Step, Synthesis, Var};
+
+    const TEST_WORKFLOW: &str = r#"
+formula = "code-review"
+description = "Code review workflow"
+type = "workflow"
+version = 1
+
+[[steps]]
+id = "analyze"
+title = "Analyze Code"
+description = "Analyze the code for issues"
+
+[[steps]]
+id = "review"
+title = "Review Changes"
+description = "Review the changes"
+needs = ["analyze"]
+
+[[steps]]
+id = "approve"
+title = "Approve Changes"
+description = "Approve or reject"
+needs = ["review"]
+"#;
+
+    const TEST_CONVOY: &str = r#"
+formula = "feature-convoy"
+description = "Feature development convoy"
+type = "convoy"
+version = 1
+
+[[legs]]
+id = "research"
+title = "Research Phase"
+focus = "requirements"
+description = "Gather requirements"
+
+[[legs]]
+id = "implement"
+title = "Implementation"
+focus = "coding"
+description = "Implement the feature"
+
+[synthesis]
+strategy = "merge"
+"#;
+
+    #[test]
+    fn test_formula_to_dot() {
+        let workflow = parse_formula_internal(TEST_WORKFLOW).unwrap();
+        let dot = formula_to_dot(&workflow);
+
+        assert!(dot.starts_with("digraph \"code-review\" {"));
+        assert!(dot.contains("node [shape=box"));
+        assert!(dot.contains("\"analyze\" [label=\"Analyze Code\"];"));
+        assert!(dot.contains("\"analyze\" -> \"review\";"));
+        assert!(dot.contains("\"review\" -> \"approve\";"));
+        assert!(dot.ends_with("}\n"));
+
+        // Convoy legs chain in declaration order with convoy styling
+        let convoy = parse_formula_internal(TEST_CONVOY).unwrap();
+        let dot = formula_to_dot(&convoy);
+        assert!(dot.contains("node [shape=ellipse"));
+        assert!(dot.contains("\"research\" -> \"implement\";"));
+    }
+
+    #[test]
+    fn test_parse_workflow() {
+        let result = parse_formula_internal(TEST_WORKFLOW);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_parse_convoy() {
+        let result = parse_formula_internal(TEST_CONVOY);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_parse_strips_bom() {
+        let content = "\u{FEFF}formula = \"test\"\ndescription = \"BOM test\"\ntype = \"workflow\"\n";
+        let formula = parse_formula_internal(content).unwrap();
+        assert_eq!(formula.name, "test");
+    }
+
+    #[test]
+    fn test_parse_strips_shebang() {
+        let content = "#!/usr/bin/env gastown-run\nformula = \"executable\"\ndescription = \"d\"\ntype = \"workflow\"\n";
+        let formula = parse_formula_internal(content).unwrap();
+        assert_eq!(formula.name, "executable");
+
+        // Shebang-only content is still empty
+        assert!(parse_formula_internal("#!/usr/bin/env gastown-run\n").is_err());
+    }
+
+    #[test]
+    fn test_var_name_mismatch() {
+        let content = r#"
+formula = "mismatch"
+description = "d"
+type = "workflow"
+
+[vars.myvar]
+name = "other"
+"#;
+        let err = parse_formula_internal(content).unwrap_err();
+        assert!(err.contains("Var key 'myvar' does not match its name field 'other'"));
+
+        // Omitted name is filled from the key
+        let content = r#"
+formula = "filled"
+description = "d"
+type = "workflow"
+
+[vars.myvar]
+description = "No explicit name"
+"#;
+        let formula = parse_formula_internal(content).unwrap();
+        assert_eq!(formula.vars["myvar"].name, "myvar");
+
+        // Matching names parse cleanly
+        let content = r#"
+formula = "matching"
+description = "d"
+type = "workflow"
+
+[vars.myvar]
+name = "myvar"
+"#;
+        assert!(parse_formula_internal(content).is_ok());
+    }
+
+    #[test]
+    fn test_parse_formula_yaml() {
+        let content = r#"
+formula: yaml-workflow
+description: Parsed from YAML
+type: workflow
+version: 2
+steps:
+  - id: analyze
+    title: Analyze
+    description: Analyze the code
+  - id: review
+    title: Review
+    description: Review changes
+    needs: [analyze]
+vars:
+  env:
+    description: Target environment
+"#;
+        let formula = parse_formula_yaml_internal(content).unwrap();
+        assert_eq!(formula.name, "yaml-workflow");
+        assert_eq!(formula.formula_type, FormulaType::Workflow);
+        assert_eq!(formula.version, 2);
+        assert_eq!(formula.steps.len(), 2);
+        assert_eq!(formula.steps[1].needs, vec!["analyze"]);
+        // Var names reconcile from keys, same as TOML
+        assert_eq!(formula.vars["env"].name, "env");
+
+        // Same validation paths as TOML
+        let expected = "Formula content is empty. Did you forget to load the file?";
+        assert_eq!(parse_formula_yaml_internal("  \n").unwrap_err(), expected);
+        assert!(parse_formula_yaml_internal("formula: [broken").is_err());
+    }
+
+    #[test]
+    fn test_parse_formula_json() {
+        let content = r#"{
+            "formula": "json-workflow",
+            "description": "Generated programmatically",
+            "type": "workflow",
+            "vars": {"env": {"description": "Target environment"}}
+        }"#;
+        let formula = parse_formula_json_internal(content).unwrap();
+        assert_eq!(formula.name, "json-workflow");
+        assert_eq!(formula.version, 1);
+        assert_eq!(formula.vars["env"].name, "env");
+
+        // Same semantic validation as the TOML path
+        let expected = "Formula content is empty. Did you forget to load the file?";
+        assert_eq!(parse_formula_json_internal("").unwrap_err(), expected);
+        let mismatched = r#"{
+            "formula": "bad",
+            "description": "d",
+            "type": "workflow",
+            "vars": {"env": {"name": "other"}}
+        }"#;
+        let err = parse_formula_json_internal(mismatched).unwrap_err();
+        assert!(err.contains("Var key 'env' does not match its name field 'other'"));
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_formula_bundle_stream() {
+        let bundle = concat!(
+            "formula = \"first\"\ndescription = \"d\"\ntype = \"workflow\"\n",
+            "---\n",
+            "formula: second\ndescription: d\ntype: convoy\n",
+            "---\n",
+            "# blank document between delimiters\n",
+            "---\n",
+            "formula = \"third\"\ndescription = \"d\"\ntype = \"workflow\"\n",
+        );
 
-    const TEST_WORKFLOW: &str = r#"
-formula = "code-review"
-description = "Code review workflow"
-type = "workflow"
-version = 1
+        // Chunk boundaries anywhere, including mid-line and mid-delimiter
+        let mut stream = FormulaBundleStream::new();
+        for chunk in bundle.as_bytes().chunks(7) {
+            stream.push(std::str::from_utf8(chunk).unwrap()).unwrap();
+        }
+        stream.finish().unwrap();
 
-[[steps]]
-id = "analyze"
-title = "Analyze Code"
-description = "Analyze the code for issues"
+        assert_eq!(stream.pending_count(), 3);
+        let names: Vec<String> = std::iter::from_fn(|| stream.next_formula())
+            .map(|f| f.name)
+            .collect();
+        assert_eq!(names, vec!["first", "second", "third"]);
+        assert!(stream.next_formula().is_none());
+    }
 
-[[steps]]
-id = "review"
-title = "Review Changes"
-description = "Review the changes"
-needs = ["analyze"]
+    #[test]
+    fn test_formula_bundle_stream_reports_bad_document() {
+        let mut stream = FormulaBundleStream::new();
+        stream
+            .push("formula = \"ok\"\ndescription = \"d\"\ntype = \"workflow\"\n---\n")
+            .unwrap();
+        let err = stream.push("formula = [broken\n---\n").unwrap_err();
+        assert!(err.starts_with("Bundle document 2:"), "{}", err);
+    }
 
-[[steps]]
-id = "approve"
-title = "Approve Changes"
-description = "Approve or reject"
-needs = ["review"]
-"#;
+    #[test]
+    fn test_parse_formula_any_autodetects() {
+        let toml = "formula = \"from-toml\"\ndescription = \"d\"\ntype = \"workflow\"\n";
+        assert_eq!(parse_formula_any_internal(toml).unwrap().name, "from-toml");
 
-    const TEST_CONVOY: &str = r#"
-formula = "feature-convoy"
-description = "Feature development convoy"
-type = "convoy"
-version = 1
+        let yaml = "formula: from-yaml\ndescription: d\ntype: workflow\n";
+        assert_eq!(parse_formula_any_internal(yaml).unwrap().name, "from-yaml");
 
-[[legs]]
-id = "research"
-title = "Research Phase"
-focus = "requirements"
-description = "Gather requirements"
+        // Leading document marker and comments still detect as YAML
+        let yaml = "# comment\n---\nformula: marked\ndescription: d\ntype: workflow\n";
+        assert_eq!(parse_formula_any_internal(yaml).unwrap().name, "marked");
+    }
 
-[[legs]]
-id = "implement"
-title = "Implementation"
-focus = "coding"
-description = "Implement the feature"
+    #[test]
+    fn test_parse_formula_diagnostics() {
+        // Bad value on line 3: span should point at it
+        let content = "formula = \"diag\"\ndescription = \"d\"\ntype = 42\n";
+        let diagnostic = parse_formula_diagnostic_internal(content).unwrap_err();
+        assert_eq!(diagnostic.code, "toml");
+        assert_eq!(diagnostic.line, 3);
+        assert!(diagnostic.byte_offset > 0);
+        assert_eq!(&content[diagnostic.byte_offset..][..diagnostic.byte_len], "42");
 
-[synthesis]
-strategy = "merge"
-"#;
+        // Empty content anchors at the start
+        let diagnostic = parse_formula_diagnostic_internal("  \n").unwrap_err();
+        assert_eq!(diagnostic.code, "empty_content");
+        assert_eq!((diagnostic.line, diagnostic.col), (1, 1));
+
+        // Duplicate section spans the second header line
+        let content = "formula = \"dup\"\ndescription = \"d\"\ntype = \"workflow\"\n[synthesis]\nstrategy = \"a\"\n[synthesis]\n";
+        let diagnostic = parse_formula_diagnostic_internal(content).unwrap_err();
+        assert_eq!(diagnostic.code, "duplicate_key");
+        assert_eq!(diagnostic.line, 6);
+        assert_eq!(
+            &content[diagnostic.byte_offset..][..diagnostic.byte_len],
+            "[synthesis]"
+        );
+
+        // Var name mismatch spans the offending vars header
+        let content = "formula = \"m\"\ndescription = \"d\"\ntype = \"workflow\"\n\n[vars.env]\nname = \"other\"\n";
+        let diagnostic = parse_formula_diagnostic_internal(content).unwrap_err();
+        assert_eq!(diagnostic.code, "var_name_mismatch");
+        assert_eq!(diagnostic.line, 5);
+        assert_eq!(
+            &content[diagnostic.byte_offset..][..diagnostic.byte_len],
+            "[vars.env]"
+        );
+
+        // A shebang shifts spans back to original-content coordinates
+        let content = "#!/usr/bin/env gastown-run\nformula = \"diag\"\ndescription = \"d\"\ntype = 42\n";
+        let diagnostic = parse_formula_diagnostic_internal(content).unwrap_err();
+        assert_eq!(diagnostic.line, 4);
+        assert_eq!(&content[diagnostic.byte_offset..][..diagnostic.byte_len], "42");
+    }
 
     #[test]
-    fn test_parse_workflow() {
-        let result = parse_formula_impl(TEST_WORKFLOW);
-        assert!(result.is_ok());
+    fn test_parse_formula_batch() {
+        let contents = vec![
+            TEST_WORKFLOW.to_string(),
+            "not a formula".to_string(),
+            TEST_CONVOY.to_string(),
+        ];
+
+        let batch = parse_formula_batch_internal(&contents);
+        assert_eq!(batch.formulas.len(), 3);
+        assert_eq!(batch.formulas[0].as_ref().unwrap().name, "code-review");
+        assert!(batch.formulas[1].is_none());
+        assert_eq!(batch.formulas[2].as_ref().unwrap().name, "feature-convoy");
+        assert_eq!(batch.errors.len(), 1);
+        assert_eq!(batch.errors[0].index, 1);
     }
 
     #[test]
-    fn test_parse_convoy() {
-        let result = parse_formula_impl(TEST_CONVOY);
-        assert!(result.is_ok());
+    fn test_list_and_apply_fixes() {
+        let content = concat!(
+            "# release convoy\n",
+            "formula = \"fixable\"\n",
+            "descripton = \"Typoed\"\n",
+            "type = \"convoy\"\n",
+            "\n",
+            "[[legs]]\n",
+            "id = \"second\"\n",
+            "title = \"Second\"\n",
+            "focus = \"b\"\n",
+            "description = \"Runs second\"\n",
+            "order = 2\n",
+            "\n",
+            "[[legs]]\n",
+            "id = \"first\"\n",
+            "title = \"First\"\n",
+            "focus = \"a\"\n",
+            "description = \"Runs first\"\n",
+            "order = 1\n",
+        );
+
+        let fixes = list_fixes_internal(content).unwrap();
+        let ids: Vec<&str> = fixes.iter().map(|f| f.id.as_str()).collect();
+        assert!(ids.contains(&"add_version"));
+        assert!(ids.contains(&"sort_legs"));
+        assert!(ids.contains(&"rename_key:descripton"));
+
+        let fix_ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        let fixed = apply_fixes_internal(content, &fix_ids).unwrap();
+
+        // The corrected document parses and needs no further fixes
+        let formula = parse_formula_internal(&fixed).unwrap();
+        assert_eq!(formula.version, 1);
+        assert_eq!(formula.description, "Typoed");
+        assert_eq!(formula.legs[0].id, "first");
+        assert_eq!(formula.legs[1].id, "second");
+        assert!(list_fixes_internal(&fixed).unwrap().is_empty());
+
+        // Comments survive the textual edits
+        assert!(fixed.contains("# release convoy"));
+
+        assert!(apply_fixes_internal(content, &["explode".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_check_dangling_needs() {
+        let content = concat!(
+            "formula = \"dangling\"\n",
+            "description = \"Needs a step that does not exist\"\n",
+            "type = \"workflow\"\n",
+            "\n",
+            "[[steps]]\n",
+            "id = \"analyze\"\n",
+            "title = \"Analyze\"\n",
+            "description = \"Analyze\"\n",
+            "\n",
+            "[[steps]]\n",
+            "id = \"review\"\n",
+            "title = \"Review\"\n",
+            "description = \"Review\"\n",
+            "needs = [\"analize\", \"deploy\"]\n",
+        );
+
+        let diagnostics = check_dangling_needs_internal(content).unwrap();
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics.iter().all(|d| d.code == "dangling_needs"));
+
+        // A close miss gets a suggestion; an unrelated id does not
+        let typo = &diagnostics[0];
+        assert!(typo.message.contains("'analize'") && typo.message.contains("did you mean 'analyze'"));
+        assert_eq!(typo.line, 14);
+        assert!(!diagnostics[1].message.contains("did you mean"));
+
+        // Resolved references are clean
+        let clean = check_dangling_needs_internal(TEST_WORKFLOW).unwrap();
+        assert!(clean.is_empty());
+    }
+
+    #[test]
+    fn test_check_unknown_keys_suggestions() {
+        let content = concat!(
+            "formula = \"test\"\n",
+            "descripton = \"Typoed\"\n",
+            "description = \"Real\"\n",
+            "type = \"workflow\"\n",
+            "\n",
+            "[[stpes]]\n",
+            "id = \"s1\"\n",
+            "\n",
+            "[[steps]]\n",
+            "id = \"s1\"\n",
+            "title = \"Step\"\n",
+            "description = \"Step\"\n",
+            "durration = 5\n",
+        );
+
+        let diagnostics = check_unknown_keys_internal(content).unwrap();
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert!(messages
+            .iter()
+            .any(|m| m.contains("'descripton'") && m.contains("did you mean 'description'")));
+        assert!(messages
+            .iter()
+            .any(|m| m.contains("'stpes'") && m.contains("did you mean 'steps'")));
+        assert!(messages
+            .iter()
+            .any(|m| m.contains("'durration'") && m.contains("did you mean 'duration'")));
+        assert!(diagnostics.iter().all(|d| d.code == "unknown_key"));
+
+        // The typoed top-level key is located in the document
+        let typo = diagnostics
+            .iter()
+            .find(|d| d.message.contains("'descripton'"))
+            .unwrap();
+        assert_eq!(typo.line, 2);
+        assert_eq!(typo.col, 1);
+    }
+
+    #[test]
+    fn test_check_unknown_keys_clean_formula() {
+        assert!(check_unknown_keys_internal(TEST_WORKFLOW).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_formula_bundle() {
+        let bundle = concat!(
+            "formula = \"first\"\n",
+            "description = \"First\"\n",
+            "type = \"workflow\"\n",
+            "+++\n",
+            "this is not a formula\n",
+            "+++\n",
+            "formula = \"third\"\n",
+            "description = \"Third\"\n",
+            "type = \"convoy\"\n",
+        );
+
+        let result = parse_formula_bundle_internal(bundle);
+        assert_eq!(result.formulas.len(), 2);
+        assert_eq!(result.formulas[0].name, "first");
+        assert_eq!(result.formulas[1].name, "third");
+        assert_eq!(result.errors.len(), 1);
+        assert!(result.errors[0].starts_with("Document 2:"), "{}", result.errors[0]);
+    }
+
+    #[test]
+    fn test_parse_formula_bundle_mixed_delimiters_and_blanks() {
+        let bundle = concat!(
+            "formula = \"a\"\n",
+            "description = \"A\"\n",
+            "type = \"workflow\"\n",
+            "---\n",
+            "+++\n",
+            "formula = \"b\"\n",
+            "description = \"B\"\n",
+            "type = \"workflow\""
+        );
+
+        let result = parse_formula_bundle_internal(bundle);
+        assert!(result.errors.is_empty(), "{:?}", result.errors);
+        let names: Vec<&str> = result.formulas.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_parse_formula_with_includes() {
+        let shared_legs = concat!(
+            "formula = \"shared-legs\"\n",
+            "description = \"Shared convoy legs\"\n",
+            "type = \"convoy\"\n",
+            "\n",
+            "[vars.env]\n",
+            "default = \"staging\"\n",
+            "\n",
+            "[[legs]]\n",
+            "id = \"security\"\n",
+            "title = \"Security review\"\n",
+            "focus = \"security\"\n",
+            "description = \"Shared security leg\"\n",
+        );
+        let content = concat!(
+            "formula = \"release\"\n",
+            "description = \"Release convoy\"\n",
+            "type = \"convoy\"\n",
+            "include = [\"shared-legs\"]\n",
+            "\n",
+            "[vars.env]\n",
+            "default = \"prod\"\n",
+            "\n",
+            "[[legs]]\n",
+            "id = \"build\"\n",
+            "title = \"Build\"\n",
+            "focus = \"build\"\n",
+            "description = \"Build artifacts\"\n",
+        );
+
+        let mut resolver = |name: &str| -> Result<String, String> {
+            match name {
+                "shared-legs" => Ok(shared_legs.to_string()),
+                other => Err(format!("Unknown include '{}'", other)),
+            }
+        };
+        let formula = parse_formula_with_includes(content, &mut resolver).unwrap();
+
+        // Included legs append after the including formula's own
+        let ids: Vec<&str> = formula.legs.iter().map(|leg| leg.id.as_str()).collect();
+        assert_eq!(ids, vec!["build", "security"]);
+
+        // The including formula wins var conflicts
+        assert_eq!(formula.vars["env"].default.as_deref(), Some("prod"));
+
+        // Unknown includes surface the resolver error
+        let content = "formula = \"f\"\ndescription = \"d\"\ntype = \"convoy\"\ninclude = [\"missing\"]\n";
+        let err = parse_formula_with_includes(content, &mut resolver).unwrap_err();
+        assert!(err.contains("Unknown include 'missing'"));
+    }
+
+    #[test]
+    fn test_parse_formula_with_includes_detects_cycles() {
+        let a = "formula = \"a\"\ndescription = \"d\"\ntype = \"convoy\"\ninclude = [\"b\"]\n";
+        let b = "formula = \"b\"\ndescription = \"d\"\ntype = \"convoy\"\ninclude = [\"a\"]\n";
+
+        let mut resolver = |name: &str| -> Result<String, String> {
+            match name {
+                "a" => Ok(a.to_string()),
+                "b" => Ok(b.to_string()),
+                other => Err(format!("Unknown include '{}'", other)),
+            }
+        };
+        let err = parse_formula_with_includes(a, &mut resolver).unwrap_err();
+        assert!(err.contains("Include cycle detected"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_formula_lenient_clean_content() {
+        let content = "formula = \"clean\"\ndescription = \"d\"\ntype = \"workflow\"\n";
+        let result = parse_formula_lenient_internal(content);
+        assert!(result.complete);
+        assert!(result.diagnostics.is_empty());
+        assert_eq!(result.formula.name, "clean");
+    }
+
+    #[test]
+    fn test_parse_formula_lenient_recovers_around_broken_line() {
+        // The broken duration line must not take down the rest of the step
+        let content = concat!(
+            "formula = \"partial\"\n",
+            "description = \"d\"\n",
+            "type = \"workflow\"\n",
+            "\n",
+            "[[steps]]\n",
+            "id = \"analyze\"\n",
+            "title = \"Analyze\"\n",
+            "duration = \n",
+            "description = \"Analyze the code\"\n",
+        );
+        let result = parse_formula_lenient_internal(content);
+
+        assert!(!result.complete);
+        assert_eq!(result.formula.name, "partial");
+        assert_eq!(result.formula.steps.len(), 1);
+        assert_eq!(result.formula.steps[0].id, "analyze");
+        assert_eq!(result.formula.steps[0].description, "Analyze the code");
+        assert_eq!(result.formula.steps[0].duration, None);
+
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].code, "toml");
+        assert_eq!(result.diagnostics[0].line, 8);
+        assert!(result.diagnostics[0].message.contains("duration ="));
+    }
+
+    #[test]
+    fn test_parse_formula_lenient_defaults_missing_fields() {
+        // Mid-edit content with no type yet: defaulted, with a diagnostic
+        let content = "formula = \"draft\"\ndescription = \"d\"\n";
+        let result = parse_formula_lenient_internal(content);
+
+        assert!(!result.complete);
+        assert_eq!(result.formula.name, "draft");
+        assert_eq!(result.formula.formula_type, FormulaType::Workflow);
+        assert!(result
+            .diagnostics
+            .iter()
+            .any(|d| d.code == "missing_field" && d.message.contains("'type'")));
+    }
+
+    #[test]
+    fn test_parse_formula_lenient_repairs_var_name_mismatch() {
+        let content = concat!(
+            "formula = \"vars\"\n",
+            "description = \"d\"\n",
+            "type = \"workflow\"\n",
+            "\n",
+            "[vars.env]\n",
+            "name = \"other\"\n",
+        );
+        let result = parse_formula_lenient_internal(content);
+
+        assert!(!result.complete);
+        assert_eq!(result.formula.vars["env"].name, "env");
+        assert!(result
+            .diagnostics
+            .iter()
+            .any(|d| d.code == "var_name_mismatch"));
+    }
+
+    #[test]
+    fn test_parse_empty_content() {
+        let expected = "Formula content is empty. Did you forget to load the file?";
+        assert_eq!(parse_formula_internal("").unwrap_err(), expected);
+        assert_eq!(parse_formula_internal("   \n\t\n").unwrap_err(), expected);
+        assert_eq!(
+            parse_formula_internal("# just a comment\n# another one\n").unwrap_err(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_normalize_formula_name() {
+        assert_eq!(normalize_formula_name("MyWorkflow"), "my-workflow");
+        assert_eq!(normalize_formula_name("my_workflow"), "my-workflow");
+        assert_eq!(normalize_formula_name("My  Big Workflow"), "my-big-workflow");
+        assert_eq!(normalize_formula_name("already-kebab"), "already-kebab");
+        assert_eq!(normalize_formula_name("HTTPServer"), "httpserver");
+        assert_eq!(normalize_formula_name("v2Deploy"), "v2-deploy");
+    }
+
+    #[test]
+    fn test_is_valid_slug() {
+        assert!(is_valid_slug("my-workflow"));
+        assert!(is_valid_slug("v2"));
+        assert!(!is_valid_slug(""));
+        assert!(!is_valid_slug("-leading"));
+        assert!(!is_valid_slug("double--hyphen"));
+        assert!(!is_valid_slug("Upper"));
+    }
+
+    #[test]
+    fn test_parse_formula_with_normalize_option() {
+        let content = "formula = \"My Workflow\"\ndescription = \"d\"\ntype = \"workflow\"\n";
+
+        // Default options leave the name untouched
+        let plain = parse_formula_with_options(content, &ParseOptions::default()).unwrap();
+        assert_eq!(plain.name, "My Workflow");
+
+        let options = ParseOptions {
+            normalize_name: true,
+        };
+        let normalized = parse_formula_with_options(content, &options).unwrap();
+        assert_eq!(normalized.name, "my-workflow");
     }
 
     #[test]
@@ -358,9 +3197,459 @@ strategy = "merge"
         assert_eq!(meta.version, Some(1));
     }
 
+    #[test]
+    fn test_extract_formula_header() {
+        let header = extract_formula_header(TEST_WORKFLOW).unwrap();
+        assert_eq!(header.name, "code-review");
+        assert_eq!(header.description, "Code review workflow");
+        assert_eq!(header.formula_type, FormulaType::Workflow);
+        assert_eq!(header.version, 1);
+        assert!(header.tags.is_empty());
+    }
+
+    #[test]
+    fn test_extract_formula_header_with_tags() {
+        let content = r#"
+formula = "tagged"
+description = "Has tags"
+type = "convoy"
+tags = ["ci", "release"]
+
+[[legs]]
+id = "leg1"
+title = "Leg 1"
+focus = "f"
+description = "d"
+"#;
+        let header = extract_formula_header(content).unwrap();
+        assert_eq!(header.tags, vec!["ci".to_string(), "release".to_string()]);
+    }
+
+    #[test]
+    fn test_pack_unpack_round_trip() {
+        let workflow = parse_formula_internal(TEST_WORKFLOW).unwrap();
+        let convoy = parse_formula_internal(TEST_CONVOY).unwrap();
+
+        let bytes = pack_formula_archive(&[workflow.clone(), convoy.clone()]).unwrap();
+        let unpacked = unpack_formula_archive(&bytes).unwrap();
+
+        assert_eq!(unpacked.len(), 2);
+        assert_eq!(unpacked[0].name, workflow.name);
+        assert_eq!(unpacked[0].steps.len(), workflow.steps.len());
+        assert_eq!(unpacked[1].name, convoy.name);
+        assert_eq!(unpacked[1].legs.len(), convoy.legs.len());
+    }
+
+    #[test]
+    fn test_unpack_rejects_garbage() {
+        let err = unpack_formula_archive(b"not a zip archive").unwrap_err();
+        assert!(matches!(err, PackError::Archive { .. }));
+    }
+
+    #[test]
+    fn test_tokenizer_kinds_and_positions() {
+        let content = "formula = \"tok\"\n\n# a comment\n[[steps]]\nid = \"s1\"\n[synthesis]\nstrategy = \"merge\"\n";
+        let tokens: Vec<FormulaToken> =
+            FormulaTokenizer::new(std::io::Cursor::new(content)).collect();
+
+        assert_eq!(tokens.len(), 6);
+        assert_eq!(tokens[0].kind, TokenKind::KeyValue);
+        assert_eq!(tokens[0].value, "formula = \"tok\"");
+        assert_eq!((tokens[0].line, tokens[0].col), (1, 1));
+        assert_eq!(tokens[1].kind, TokenKind::Comment);
+        assert_eq!(tokens[1].value, "a comment");
+        assert_eq!(tokens[1].line, 3);
+        assert_eq!(tokens[2].kind, TokenKind::ArrayOfTablesHeader);
+        assert_eq!(tokens[2].value, "steps");
+        assert_eq!(tokens[4].kind, TokenKind::TableHeader);
+        assert_eq!(tokens[4].value, "synthesis");
+        assert_eq!(tokens[4].line, 6);
+    }
+
+    #[test]
+    fn test_tokenizer_indented_line_col() {
+        let content = "  key = 1\n";
+        let tokens: Vec<FormulaToken> =
+            FormulaTokenizer::new(std::io::Cursor::new(content)).collect();
+        assert_eq!(tokens[0].col, 3);
+    }
+
+    #[test]
+    fn test_summarize_formula_collection() {
+        let workflow = parse_formula_internal(TEST_WORKFLOW).unwrap();
+        let convoy = parse_formula_internal(TEST_CONVOY).unwrap();
+        let formulas_json =
+            serde_json::to_string(&vec![workflow.clone(), workflow, convoy]).unwrap();
+
+        let summary = summarize_formula_collection_internal(&formulas_json).unwrap();
+
+        assert_eq!(summary.total, 3);
+        assert_eq!(summary.by_type.get("workflow"), Some(&2));
+        assert_eq!(summary.by_type.get("convoy"), Some(&1));
+        assert_eq!(summary.by_version.get("1"), Some(&3));
+        // 3+3+0 steps and 0+0+2 legs over 3 formulas
+        assert!((summary.avg_step_count - 2.0).abs() < f64::EPSILON);
+        assert!((summary.avg_leg_count - 2.0 / 3.0).abs() < f64::EPSILON);
+        assert_eq!(summary.formulas_with_vars, 0);
+        assert_eq!(summary.formulas_with_synthesis, 1);
+    }
+
+    #[test]
+    fn test_summarize_empty_collection() {
+        let summary = summarize_formula_collection_internal("[]").unwrap();
+        assert_eq!(summary.total, 0);
+        assert_eq!(summary.avg_step_count, 0.0);
+    }
+
+    #[test]
+    fn test_check_deprecated_fields() {
+        let content = r#"
+formula = "legacy"
+description = "Uses a deprecated field"
+type = "workflow"
+
+[[steps]]
+id = "step1"
+title = "Step 1"
+description = "First step"
+depends_on = ["step0"]
+"#;
+        let warnings = check_deprecated_fields(content);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, "DEPRECATED_FIELD");
+        assert!(warnings[0].message.contains("steps.needs"));
+
+        // Current field names produce no warnings
+        assert!(check_deprecated_fields(TEST_WORKFLOW).is_empty());
+    }
+
+    #[test]
+    fn test_explain_formula() {
+        let formula = parse_formula_internal(TEST_WORKFLOW).unwrap();
+        let explanation = explain_formula_internal(&formula);
+
+        assert_eq!(explanation.name, "code-review");
+        assert_eq!(explanation.formula_type, "workflow");
+        assert_eq!(explanation.step_count, 3);
+        assert_eq!(explanation.leg_count, 0);
+        assert_eq!(explanation.var_count, 0);
+        assert!(!explanation.has_synthesis);
+        // 3 steps + 0 legs + 0 var refs + depth 3 (analyze -> review -> approve)
+        assert_eq!(explanation.complexity_score, 6);
+    }
+
     #[test]
     fn test_get_formula_type() {
         assert_eq!(get_formula_type_impl(TEST_WORKFLOW).unwrap(), "workflow");
         assert_eq!(get_formula_type_impl(TEST_CONVOY).unwrap(), "convoy");
     }
+
+    #[test]
+    fn test_duplicate_section_rejected() {
+        let content = r#"
+formula = "dup"
+description = "Duplicate vars section"
+type = "workflow"
+
+[vars.env]
+name = "env"
+
+[vars.env]
+name = "env"
+default = "prod"
+"#;
+        let err = parse_formula_internal(content).unwrap_err();
+        assert!(err.contains("Duplicate section '[vars.env]'"));
+        assert!(err.contains("line 6"));
+        assert!(err.contains("line 9"));
+
+        // Repeated [[steps]]/[[legs]] headers are legal array-of-tables
+        // syntax and must not trip the duplicate check
+        assert!(parse_formula_internal(TEST_WORKFLOW).is_ok());
+        assert!(parse_formula_internal(TEST_CONVOY).is_ok());
+    }
+
+    #[test]
+    fn test_duplicate_step_id_rejected() {
+        let content = r#"
+formula = "dup-id"
+description = "Two steps share an id"
+type = "workflow"
+
+[[steps]]
+id = "build"
+title = "Build"
+description = "Build it"
+
+[[steps]]
+id = "build"
+title = "Build again"
+description = "Shadows the first"
+"#;
+        let err = parse_formula_internal(content).unwrap_err();
+        assert!(err.contains("Duplicate step id 'build'"), "{}", err);
+
+        // The diagnostic path underlines the second occurrence
+        let diagnostic = parse_formula_diagnostic_internal(content).unwrap_err();
+        assert_eq!(diagnostic.code, "duplicate_id");
+        assert_eq!(diagnostic.line, 12);
+
+        // A leg reusing a step id is also rejected
+        let content = r#"
+formula = "dup-cross"
+description = "Leg reuses a step id"
+type = "workflow"
+
+[[steps]]
+id = "review"
+title = "Review"
+description = "Review it"
+
+[[legs]]
+id = "review"
+title = "Review leg"
+focus = "review"
+description = "Same id"
+"#;
+        let err = parse_formula_internal(content).unwrap_err();
+        assert!(err.contains("Duplicate leg id 'review'"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_formula_with_extra() {
+        let content = r#"
+formula = "with-extra"
+description = "Has a custom CI section"
+type = "workflow"
+
+[[steps]]
+id = "build"
+title = "Build"
+description = "Build it"
+
+[ci]
+provider = "github"
+timeout_minutes = 30
+"#;
+        let (formula, document) = parse_formula_with_extra(content).unwrap();
+        assert_eq!(formula.name, "with-extra");
+        assert_eq!(formula.steps.len(), 1);
+
+        // The full document retains sections the Formula struct ignores
+        let ci = document.get("ci").unwrap();
+        assert_eq!(ci.get("provider").unwrap().as_str(), Some("github"));
+        assert_eq!(ci.get("timeout_minutes").unwrap().as_integer(), Some(30));
+
+        // Standard pipeline checks still apply
+        assert_eq!(
+            parse_formula_with_extra("").unwrap_err(),
+            ParseError::EmptyContent
+        );
+    }
+
+    #[test]
+    fn test_formula_to_toml_round_trip_fixtures() {
+        for content in [TEST_WORKFLOW, TEST_CONVOY] {
+            let formula = parse_formula_internal(content).unwrap();
+            let toml_content = formula_to_toml(&formula).unwrap();
+            let reparsed = parse_formula_internal(&toml_content).unwrap();
+            assert_eq!(reparsed, formula);
+        }
+    }
+
+    #[test]
+    fn test_format_formula() {
+        // Keys out of schema order, noisy spacing, and a comment
+        let content = concat!(
+            "# legacy header comment\n",
+            "type = \"workflow\"\n",
+            "version   =    2\n",
+            "formula = \"messy\"\n",
+            "description = \"d\"\n",
+            "\n",
+            "[[steps]]\n",
+            "description = \"First step\"\n",
+            "id = \"one\"\n",
+            "title = \"One\"\n",
+        );
+        let formatted = format_formula_internal(content).unwrap();
+
+        // Canonical key order and spacing
+        assert!(formatted.starts_with("formula = \"messy\"\ndescription = \"d\"\ntype = \"workflow\"\nversion = 2\n"));
+
+        // Formatting is idempotent and lossless
+        assert_eq!(format_formula_internal(&formatted).unwrap(), formatted);
+        assert_eq!(
+            parse_formula_internal(&formatted).unwrap(),
+            parse_formula_internal(content).unwrap()
+        );
+
+        // Shebang lines survive formatting
+        let executable = format!("#!/usr/bin/env gastown-run\n{}", content);
+        let formatted = format_formula_internal(&executable).unwrap();
+        assert!(formatted.starts_with("#!/usr/bin/env gastown-run\nformula = "));
+    }
+
+    #[test]
+    fn test_formula_to_toml_preserves_schema_field_order() {
+        // Emitted top-level keys must follow the schema's field order so
+        // serialized formulas are canonical and diff cleanly
+        let formula = parse_formula_internal(TEST_WORKFLOW).unwrap();
+        let toml_content = formula_to_toml(&formula).unwrap();
+
+        let mut last_position = 0;
+        for key in ["formula", "description", "type", "version"] {
+            let position = toml_content
+                .find(&format!("{} = ", key))
+                .unwrap_or_else(|| panic!("missing top-level key '{}'", key));
+            assert!(position >= last_position, "'{}' out of schema order", key);
+            last_position = position;
+        }
+    }
+
+    // ------------------------------------------------------------------
+    // Property tests: formula_to_toml must round-trip losslessly through
+    // parse_formula for any formula, including special TOML characters,
+    // multi-line descriptions, Unicode var names, and empty steps/legs
+    // ------------------------------------------------------------------
+
+    use proptest::prelude::*;
+
+    /// Arbitrary text including newlines, quotes, and Unicode
+    fn arb_text() -> impl Strategy<Value = String> {
+        prop::collection::vec(any::<char>(), 0..24).prop_map(String::from_iter)
+    }
+
+    fn arb_formula_type() -> impl Strategy<Value = FormulaType> {
+        prop_oneof![
+            Just(FormulaType::Convoy),
+            Just(FormulaType::Workflow),
+            Just(FormulaType::Expansion),
+            Just(FormulaType::Aspect),
+        ]
+    }
+
+    /// Numeric bounds restricted to exactly-representable values so the
+    /// float survives TOML formatting bit-for-bit
+    fn arb_bound() -> impl Strategy<Value = Option<f64>> {
+        prop::option::of((-1000i32..1000).prop_map(f64::from))
+    }
+
+    prop_compose! {
+        fn arb_step()(
+            id in arb_text(),
+            title in arb_text(),
+            description in arb_text(),
+            needs in prop::collection::vec(arb_text(), 0..3),
+            duration in prop::option::of(any::<u32>()),
+            requires in prop::collection::vec(arb_text(), 0..3),
+        ) -> Step {
+            Step { id, title, description, needs, duration, requires, when: None, foreach: None }
+        }
+    }
+
+    prop_compose! {
+        fn arb_leg()(
+            id in arb_text(),
+            title in arb_text(),
+            focus in arb_text(),
+            description in arb_text(),
+            agent in prop::option::of(arb_text()),
+            order in prop::option::of(any::<u32>()),
+        ) -> Leg {
+            Leg { id, title, focus, description, agent, order, when: None, vars: Default::default() }
+        }
+    }
+
+    prop_compose! {
+        fn arb_var()(
+            description in prop::option::of(arb_text()),
+            default in prop::option::of(arb_text()),
+            required in any::<bool>(),
+            pattern in prop::option::of(arb_text()),
+            enum_values in prop::option::of(prop::collection::vec(arb_text(), 0..3)),
+            min in arb_bound(),
+            max in arb_bound(),
+        ) -> Var {
+            Var {
+                name: String::new(),
+                description,
+                default,
+                required,
+                pattern,
+                enum_values,
+                min,
+                max,
+                ..Default::default()
+            }
+        }
+    }
+
+    prop_compose! {
+        fn arb_synthesis()(
+            strategy in arb_text(),
+            format in prop::option::of(arb_text()),
+            description in prop::option::of(arb_text()),
+        ) -> Synthesis {
+            Synthesis { strategy, format, description }
+        }
+    }
+
+    prop_compose! {
+        fn arb_formula()(
+            name in arb_text(),
+            description in arb_text(),
+            formula_type in arb_formula_type(),
+            version in any::<u32>(),
+            legs in prop::collection::vec(arb_leg(), 0..3),
+            synthesis in prop::option::of(arb_synthesis()),
+            steps in prop::collection::vec(arb_step(), 0..3),
+            vars in prop::collection::hash_map(arb_text(), arb_var(), 0..3),
+        ) -> Formula {
+            // Step and leg ids must be unique or parsing rejects them
+            let legs: Vec<Leg> = legs
+                .into_iter()
+                .enumerate()
+                .map(|(i, mut leg)| {
+                    leg.id = format!("leg-{}-{}", i, leg.id);
+                    leg
+                })
+                .collect();
+            let steps: Vec<Step> = steps
+                .into_iter()
+                .enumerate()
+                .map(|(i, mut step)| {
+                    step.id = format!("step-{}-{}", i, step.id);
+                    step
+                })
+                .collect();
+            // Var names must agree with their keys or parsing rejects them
+            let vars = vars
+                .into_iter()
+                .map(|(key, mut var)| {
+                    var.name = key.clone();
+                    (key, var)
+                })
+                .collect();
+            Formula {
+                name,
+                description,
+                formula_type,
+                version,
+                legs,
+                synthesis,
+                steps,
+                vars,
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn toml_round_trip_property_test(formula in arb_formula()) {
+            let toml_content = formula_to_toml(&formula).unwrap();
+            let parsed = parse_formula_internal(&toml_content).unwrap();
+            prop_assert_eq!(parsed, formula);
+        }
+    }
 }
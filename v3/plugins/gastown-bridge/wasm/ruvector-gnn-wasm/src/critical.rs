@@ -154,8 +154,8 @@ fn topo_sort_kahn_indices(beads: &[BeadNode], id_to_index: &FxHashMap<&str, usiz
     // Initialize queue with nodes that have no dependencies
     let mut queue: std::collections::VecDeque<usize> = std::collections::VecDeque::with_capacity(n);
 
-    for i in 0..n {
-        if in_degree[i] == 0 {
+    for (i, &deg) in in_degree.iter().enumerate() {
+        if deg == 0 {
             queue.push_back(i);
         }
     }
@@ -282,7 +282,7 @@ mod tests {
         assert_eq!(result.path[2], "c");
 
         // All nodes are critical (no slack)
-        for (_, s) in &result.slack {
+        for s in result.slack.values() {
             assert_eq!(*s, 0);
         }
     }
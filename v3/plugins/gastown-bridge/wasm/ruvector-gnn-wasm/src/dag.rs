@@ -12,7 +12,7 @@
 use wasm_bindgen::prelude::*;
 use petgraph::graph::{DiGraph, NodeIndex};
 use petgraph::algo::is_cyclic_directed;
-use gastown_shared::{FxHashMap, FxHashSet, pool::SmallBuffer, capacity};
+use gastown_shared::{FxHashMap, FxHashSet, pool::SmallBuffer};
 use crate::BeadNode;
 
 /// Check if the dependency graph has cycles
@@ -54,10 +54,10 @@ pub fn build_adjacency_impl(beads_json: &str) -> Result<String, JsValue> {
     adjacency.reserve(beads.len());
 
     for bead in &beads {
-        adjacency.entry(bead.id.clone()).or_insert_with(Vec::new);
+        adjacency.entry(bead.id.clone()).or_default();
         for blocked in &bead.blocks {
             adjacency.entry(bead.id.clone())
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(blocked.clone());
         }
     }
@@ -192,6 +192,7 @@ fn find_cycle_nodes_internal(beads: &[BeadNode]) -> Vec<String> {
     let mut sccs: Vec<Vec<usize>> = Vec::new();
 
     // Recursive helper (inlined for performance)
+    #[allow(clippy::too_many_arguments)]
     fn strongconnect(
         v: usize,
         adj: &[SmallBuffer<usize, 8>],
@@ -273,7 +274,7 @@ fn compute_levels_internal(beads: &[BeadNode]) -> FxHashMap<usize, Vec<String>>
         if bead.blocked_by.is_empty() {
             queue.push_back(bead.id.clone());
             level_map.insert(bead.id.clone(), 0);
-            levels.entry(0).or_insert_with(Vec::new).push(bead.id.clone());
+            levels.entry(0).or_default().push(bead.id.clone());
         }
     }
 
@@ -284,13 +285,13 @@ fn compute_levels_internal(beads: &[BeadNode]) -> FxHashMap<usize, Vec<String>>
     for bead in beads {
         for blocker in &bead.blocked_by {
             successors.entry(blocker.as_str())
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(&bead.id);
         }
     }
 
     while let Some(id) = queue.pop_front() {
-        let current_level = *level_map.get(&id).unwrap_or(&0);
+        let _current_level = *level_map.get(&id).unwrap_or(&0);
 
         // Find beads that this one blocks
         if let Some(blocked_beads) = successors.get(id.as_str()) {
@@ -314,7 +315,7 @@ fn compute_levels_internal(beads: &[BeadNode]) -> FxHashMap<usize, Vec<String>>
 
                         let new_level = max_dep_level + 1;
                         level_map.insert(blocked_id.to_string(), new_level);
-                        levels.entry(new_level).or_insert_with(Vec::new).push(blocked_id.to_string());
+                        levels.entry(new_level).or_default().push(blocked_id.to_string());
                         queue.push_back(blocked_id.to_string());
                     }
                 }
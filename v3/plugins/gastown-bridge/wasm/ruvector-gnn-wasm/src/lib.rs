@@ -28,8 +28,6 @@
 
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
-use petgraph::graph::{DiGraph, NodeIndex};
-use petgraph::algo::{toposort, is_cyclic_directed};
 use gastown_shared::FxHashMap;
 
 mod dag;
@@ -113,8 +113,8 @@ fn topo_sort_kahn(beads: &[BeadNode]) -> TopoSortResult {
     // Initialize queue with nodes that have no dependencies
     let mut queue: std::collections::VecDeque<usize> = std::collections::VecDeque::with_capacity(n);
 
-    for i in 0..n {
-        if in_degree[i] == 0 {
+    for (i, &deg) in in_degree.iter().enumerate() {
+        if deg == 0 {
             queue.push_back(i);
         }
     }